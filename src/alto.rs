@@ -0,0 +1,110 @@
+//! Writing ALTO, the XML format libraries use for OCR results, with the
+//! position of every line and word.
+//!
+//! Tesseract produces a page for each line image. The blocks on those are
+//! moved to where the line was on the screen, and every subtitle becomes a
+//! page of its own. ALTO has nowhere for times, so the subtitle's times are
+//! given as the page's printed number, like `00:01:02.500-00:01:04.000`.
+
+use std::fmt::Write;
+
+use crate::{
+    format::format_time,
+    ocr::Subtitle,
+    writer::{Result, SubtitleWriter},
+};
+
+const HEADER: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<alto xmlns="http://www.loc.gov/standards/alto/ns-v3#" xmlns:xlink="http://www.w3.org/1999/xlink" xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance" xsi:schemaLocation="http://www.loc.gov/standards/alto/ns-v3# http://www.loc.gov/alto/v3/alto-3-0.xsd">
+	<Description>
+		<MeasurementUnit>pixel</MeasurementUnit>
+		<sourceImageInformation>
+			<fileName></fileName>
+		</sourceImageInformation>
+		<OCRProcessing ID="OCR_0">
+			<ocrProcessingStep>
+				<processingSoftware>
+					<softwareName>vobsubocr</softwareName>
+				</processingSoftware>
+			</ocrProcessingStep>
+		</OCRProcessing>
+	</Description>
+	<Layout>
+"#;
+
+const FOOTER: &str = "\t</Layout>\n</alto>\n";
+
+/// Take the blocks on a page of Tesseract's ALTO for a line image whose top
+/// left corner was at `origin` on the screen, moving them there.
+pub fn place_line(page: &str, origin: (i32, i32)) -> String {
+    let start = page
+        .find("<PrintSpace")
+        .and_then(|i| page[i..].find('>').map(|j| i + j + 1))
+        .unwrap_or(0);
+    let end = page.rfind("</PrintSpace>").filter(|&end| end >= start);
+    let blocks = page[start..end.unwrap_or(page.len())]
+        .trim_start_matches('\n')
+        .trim_end_matches('\t');
+    shift_positions(blocks, origin)
+}
+
+/// Writes subtitles as an ALTO document with a page per subtitle.
+pub struct AltoWriter;
+
+impl SubtitleWriter for AltoWriter {
+    fn write(&self, subtitles: &[Subtitle]) -> Result<Vec<u8>> {
+        let mut xml = String::from(HEADER);
+        for (i, subtitle) in subtitles.iter().enumerate() {
+            let placement = &subtitle.placement;
+            writeln!(
+                xml,
+                "\t\t<Page WIDTH=\"{0}\" HEIGHT=\"{1}\" PHYSICAL_IMG_NR=\"{2}\" \
+                 PRINTED_IMG_NR=\"{3}-{4}\" ID=\"page_{5}\">\n\
+                 \t\t\t<PrintSpace HPOS=\"0\" VPOS=\"0\" WIDTH=\"{0}\" HEIGHT=\"{1}\">",
+                placement.screen_width,
+                placement.screen_height,
+                i,
+                format_time(subtitle.time_span.start),
+                format_time(subtitle.time_span.end),
+                i + 1,
+            )
+            .unwrap();
+            for (j, line) in subtitle
+                .lines
+                .iter()
+                .filter_map(|l| l.markup.as_ref())
+                .enumerate()
+            {
+                // Tesseract numbers its elements per page, so make them unique.
+                let prefix = format!("ID=\"s{}_l{}_", i + 1, j + 1);
+                xml.push_str(&line.replace("ID=\"", &prefix));
+            }
+            xml.push_str("\t\t\t</PrintSpace>\n\t\t</Page>\n");
+        }
+        xml.push_str(FOOTER);
+        Ok(xml.into_bytes())
+    }
+}
+
+/// Add an offset to every `HPOS` and `VPOS` attribute.
+fn shift_positions(alto: &str, (dx, dy): (i32, i32)) -> String {
+    let mut output = String::with_capacity(alto.len());
+    let mut rest = alto;
+    while let Some(i) = rest.find("POS=\"") {
+        let offset = match &rest[..i] {
+            before if before.ends_with('H') => dx,
+            before if before.ends_with('V') => dy,
+            _ => 0,
+        };
+        output.push_str(&rest[..i + 5]);
+        rest = &rest[i + 5..];
+        let len = rest.find('"').unwrap_or(rest.len());
+        match rest[..len].parse::<i32>() {
+            Ok(position) => write!(output, "{}", position + offset).unwrap(),
+            Err(_) => output.push_str(&rest[..len]),
+        }
+        rest = &rest[len..];
+    }
+    output.push_str(rest);
+    output
+}
@@ -0,0 +1,86 @@
+//! Describing the subtitles of each input without running OCR, for
+//! `--analyze`.
+
+use std::{collections::HashMap, fmt::Write as _, path::Path};
+
+use crate::{
+    format::{format_time, hex_color},
+    opt::OcrOpt,
+    preprocessor::{self, SubtitleTrack},
+};
+
+/// Roughly how long Tesseract takes to read a line of text on one thread, in
+/// seconds.
+const SECONDS_PER_LINE: f64 = 0.06;
+
+/// Describe an input's subtitles: their language, number, times, screen size
+/// and palette, and roughly how long OCR would take, having found their lines
+/// of text.
+pub fn describe(input: &Path, track: &SubtitleTrack, opt: &OcrOpt) -> String {
+    let bitmaps = &track.bitmaps;
+    let mut report = format!("{}\n", input.display());
+    let language = track.language.as_deref().unwrap_or("unknown");
+    writeln!(report, "  Language: {}", language).unwrap();
+    writeln!(report, "  OCR language: {}", opt.tesseract_language()).unwrap();
+    let forced = bitmaps.iter().filter(|bitmap| bitmap.force).count();
+    writeln!(report, "  Subtitles: {} ({} forced)", bitmaps.len(), forced).unwrap();
+
+    let start = bitmaps.iter().map(|bitmap| bitmap.time_span.start).min();
+    let end = bitmaps.iter().map(|bitmap| bitmap.time_span.end).max();
+    if let (Some(start), Some(end)) = (start, end) {
+        writeln!(
+            report,
+            "  Time range: {} to {}",
+            format_time(start),
+            format_time(end)
+        )
+        .unwrap();
+    }
+
+    let mut sizes: HashMap<(u32, u32), usize> = HashMap::new();
+    for bitmap in bitmaps {
+        *sizes.entry(bitmap.screen_size).or_default() += 1;
+    }
+    let mut sizes: Vec<_> = sizes.into_iter().collect();
+    sizes.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    let sizes: Vec<String> = sizes
+        .iter()
+        .map(|((width, height), _)| format!("{}x{}", width, height))
+        .collect();
+    if !sizes.is_empty() {
+        writeln!(report, "  Resolution: {}", sizes.join(", ")).unwrap();
+    }
+
+    if let Some(palette) = &track.palette {
+        let colors: Vec<String> = palette.iter().map(|&color| hex_color(color)).collect();
+        writeln!(report, "  Palette: {}", colors.join(", ")).unwrap();
+    }
+
+    let lines: usize = preprocessor::preprocess_subtitles(bitmaps, opt)
+        .iter()
+        .map(|(_, vobsub)| vobsub.lines.len())
+        .sum();
+    let threads = opt
+        .jobs
+        .map_or_else(rayon::current_num_threads, |jobs| jobs as usize);
+    let seconds = lines as f64 * SECONDS_PER_LINE / threads.max(1) as f64;
+    writeln!(
+        report,
+        "  Lines of text: {}, taking roughly {} on {} thread{}",
+        lines,
+        format_duration(seconds),
+        threads,
+        if threads == 1 { "" } else { "s" }
+    )
+    .unwrap();
+    report
+}
+
+/// Format a number of seconds like `1m 05s`.
+fn format_duration(seconds: f64) -> String {
+    let seconds = seconds.ceil() as u64;
+    match seconds {
+        0..=59 => format!("{}s", seconds),
+        _ => format!("{}m {:02}s", seconds / 60, seconds % 60),
+    }
+}
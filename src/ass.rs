@@ -0,0 +1,186 @@
+//! Writing Advanced SubStation Alpha subtitles, which, unlike SRT, can keep
+//! track of where each subtitle was placed on the screen and what color it was.
+
+use std::fmt::Write;
+
+use crate::{
+    ocr::Subtitle,
+    preprocessor::{Column, Placement},
+    writer::{Result, SubtitleWriter},
+};
+use subparse::timetypes::TimePoint;
+
+/// Screen size to use when there are no subtitles to take it from.
+const DEFAULT_SCREEN_SIZE: (u32, u32) = (720, 480);
+
+/// Text colors with all channels at least this bright are left to the style.
+const WHITE_THRESHOLD: u8 = 0xc0;
+
+/// Writes subtitles as an ASS script.
+pub struct AssWriter;
+
+impl SubtitleWriter for AssWriter {
+    fn write(&self, subtitles: &[Subtitle]) -> Result<Vec<u8>> {
+        let (width, height) = subtitles
+            .first()
+            .map(|s| (s.placement.screen_width, s.placement.screen_height))
+            .unwrap_or(DEFAULT_SCREEN_SIZE);
+        // Scale the font and margin from what looks right on a 480-line DVD.
+        let font_size = (height * 26 + 240) / 480;
+        let margin = (height * 20 + 240) / 480;
+
+        let mut script = String::new();
+        script.push_str("[Script Info]\n");
+        script.push_str("ScriptType: v4.00+\n");
+        writeln!(script, "PlayResX: {}", width).unwrap();
+        writeln!(script, "PlayResY: {}", height).unwrap();
+        script.push_str("WrapStyle: 0\n");
+        script.push_str("ScaledBorderAndShadow: yes\n\n");
+
+        script.push_str("[V4+ Styles]\n");
+        script.push_str(
+            "Format: Name, Fontname, Fontsize, PrimaryColour, SecondaryColour, OutlineColour, \
+             BackColour, Bold, Italic, Underline, StrikeOut, ScaleX, ScaleY, Spacing, Angle, \
+             BorderStyle, Outline, Shadow, Alignment, MarginL, MarginR, MarginV, Encoding\n",
+        );
+        writeln!(
+            script,
+            "Style: Default,Arial,{},&H00FFFFFF,&H000000FF,&H00000000,&H80000000,\
+             0,0,0,0,100,100,0,0,1,2,1,2,{},{},{},1",
+            font_size, margin, margin, margin
+        )
+        .unwrap();
+        script.push('\n');
+
+        script.push_str("[Events]\n");
+        script.push_str(
+            "Format: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text\n",
+        );
+        for subtitle in subtitles {
+            let placement = &subtitle.placement;
+            let mut tags = String::new();
+            let margin_v = match alignment(placement) {
+                // Leave ordinary subtitles to the style, at the same distance
+                // from the bottom as they were.
+                2 => placement
+                    .screen_height
+                    .saturating_sub(placement.y + placement.height),
+                alignment => {
+                    let (x, y) = anchor(placement, alignment);
+                    write!(tags, "\\an{}\\pos({},{})", alignment, x, y).unwrap();
+                    0
+                }
+            };
+            // Speakers in their own colors get them line by line instead.
+            let speaker_colors = subtitle.has_speaker_colors();
+            if !speaker_colors {
+                if let Some(color) = color_override(subtitle.text_color) {
+                    tags.push_str(&color);
+                }
+            }
+            if !tags.is_empty() {
+                tags = format!("{{{}}}", tags);
+            }
+            writeln!(
+                script,
+                "Dialogue: 0,{},{},Default,,0,0,{},,{}{}",
+                format_time(subtitle.time_span.start),
+                format_time(subtitle.time_span.end),
+                margin_v,
+                tags,
+                format_text(subtitle, speaker_colors),
+            )
+            .unwrap();
+        }
+        Ok(script.into_bytes())
+    }
+}
+
+/// Find the numpad-style `\an` alignment matching where the text was: at the
+/// top or bottom, and on the left, in the middle or on the right.
+fn alignment(placement: &Placement) -> u32 {
+    let column = match placement.column() {
+        Column::Left => 1,
+        Column::Center => 2,
+        Column::Right => 3,
+    };
+    if placement.is_top() {
+        column + 6
+    } else {
+        column
+    }
+}
+
+/// Find the point of the text's rectangle that `\pos` places for an alignment.
+fn anchor(placement: &Placement, alignment: u32) -> (u32, u32) {
+    let x = match alignment % 3 {
+        1 => placement.x,
+        2 => placement.x + placement.width / 2,
+        _ => placement.x + placement.width,
+    };
+    let y = if alignment > 6 {
+        placement.y
+    } else {
+        placement.y + placement.height
+    };
+    (x, y)
+}
+
+/// Format a time as `H:MM:SS.cc`.
+fn format_time(time: TimePoint) -> String {
+    let centis = (time.msecs().max(0) + 5) / 10;
+    format!(
+        "{}:{:02}:{:02}.{:02}",
+        centis / 360_000,
+        centis / 6000 % 60,
+        centis / 100 % 60,
+        centis % 100
+    )
+}
+
+/// Find the override setting the color of text, unless it's white and left to
+/// the style.
+fn color_override(color: [u8; 3]) -> Option<String> {
+    let [r, g, b] = color;
+    if r.min(g).min(b) < WHITE_THRESHOLD {
+        Some(format!("\\c&H{:02X}{:02X}{:02X}&", b, g, r))
+    } else {
+        None
+    }
+}
+
+/// Escape override blocks, italicize italic lines, color each line if asked
+/// to, and join the lines of the text with hard line breaks, leaving out blank
+/// lines at either end.
+fn format_text(subtitle: &Subtitle, line_colors: bool) -> String {
+    let lines: Vec<_> = subtitle
+        .lines
+        .iter()
+        .flat_map(|line| line.text.lines().map(move |text| (text.trim(), line)))
+        .collect();
+    let start = lines.iter().position(|(text, _)| !text.is_empty());
+    let end = lines.iter().rposition(|(text, _)| !text.is_empty());
+    let lines = match (start, end) {
+        (Some(start), Some(end)) => &lines[start..=end],
+        _ => &[],
+    };
+    lines
+        .iter()
+        .map(|&(text, line)| {
+            let mut text = text.replace('{', "\\{").replace('}', "\\}");
+            if text.is_empty() {
+                return text;
+            }
+            if line.italic {
+                text = format!("{{\\i1}}{}{{\\i0}}", text);
+            }
+            if line_colors {
+                // A bare `\c` goes back to the style's color.
+                let color = color_override(line.color).unwrap_or_else(|| "\\c".to_owned());
+                text = format!("{{{}}}{}", color, text);
+            }
+            text
+        })
+        .collect::<Vec<_>>()
+        .join("\\N")
+}
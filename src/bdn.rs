@@ -0,0 +1,321 @@
+//! Reading Scenarist/BDN XML subtitles, where each event's image is a separate
+//! PNG file next to the XML.
+
+use std::{
+    fmt::Write as _,
+    fs,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use crate::{compression, preprocessor::SubtitleBitmap};
+use rayon::prelude::*;
+use snafu::{OptionExt, ResultExt, Snafu};
+use subparse::timetypes::{TimePoint, TimeSpan};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Could not read {}: {}", filename.display(), source))]
+    Io {
+        filename: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("Could not parse {}: {}", filename.display(), source))]
+    Xml {
+        filename: PathBuf,
+        source: roxmltree::Error,
+    },
+
+    #[snafu(display("Not a BDN XML file"))]
+    NotBdn,
+
+    #[snafu(display("Event is missing the {} attribute", name))]
+    MissingAttribute { name: &'static str },
+
+    #[snafu(display("Invalid frame rate: {}", value))]
+    InvalidFrameRate { value: String },
+
+    #[snafu(display("Invalid timecode: {}", value))]
+    InvalidTimecode { value: String },
+
+    #[snafu(display("Could not load image {}: {}", filename.display(), source))]
+    Image {
+        filename: PathBuf,
+        source: image::ImageError,
+    },
+
+    #[snafu(display("Could not write {}: {}", filename.display(), source))]
+    Write {
+        filename: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("Could not save image {}: {}", filename.display(), source))]
+    SaveImage {
+        filename: PathBuf,
+        source: image::ImageError,
+    },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// An event from the XML, before its image is loaded.
+struct Event {
+    time_span: TimeSpan,
+    screen_size: (u32, u32),
+    force: bool,
+    graphics: Vec<Graphic>,
+}
+
+/// One of an event's images, positioned on the screen.
+struct Graphic {
+    filename: PathBuf,
+    x: u32,
+    y: u32,
+}
+
+/// Read a BDN XML file and the PNG images it references.
+pub fn read_bdn(path: impl AsRef<Path>) -> Result<Vec<SubtitleBitmap>> {
+    let path = path.as_ref();
+    let mut xml = String::new();
+    compression::open(path)
+        .and_then(|mut file| file.read_to_string(&mut xml))
+        .context(IoSnafu { filename: path })?;
+    let document = roxmltree::Document::parse(&xml).context(XmlSnafu { filename: path })?;
+    let root = document.root_element();
+    if !root.has_tag_name("BDN") {
+        return NotBdnSnafu {}.fail();
+    }
+
+    let format = root.descendants().find(|n| n.has_tag_name("Format"));
+    let screen_size = match format.and_then(|n| n.attribute("VideoFormat")) {
+        Some("480i") | Some("480p") => (720, 480),
+        Some("576i") | Some("576p") => (720, 576),
+        Some("720p") => (1280, 720),
+        _ => (1920, 1080),
+    };
+    let frame_rate = match format.and_then(|n| n.attribute("FrameRate")) {
+        Some(value) => value
+            .parse::<f64>()
+            .ok()
+            .filter(|&fps| fps > 0.0)
+            .context(InvalidFrameRateSnafu { value })?,
+        None => 23.976,
+    };
+
+    let directory = path.parent().unwrap_or_else(|| Path::new(""));
+    let events = root
+        .descendants()
+        .filter(|n| n.has_tag_name("Event"))
+        .map(|event| {
+            let attribute = |name| {
+                event
+                    .attribute(name)
+                    .context(MissingAttributeSnafu { name })
+            };
+            let start = parse_timecode(attribute("InTC")?, frame_rate)?;
+            let end = parse_timecode(attribute("OutTC")?, frame_rate)?;
+            let graphics = event
+                .children()
+                .filter(|n| n.has_tag_name("Graphic"))
+                .map(|graphic| {
+                    let position = |name| {
+                        graphic
+                            .attribute(name)
+                            .and_then(|value| value.parse().ok())
+                            .unwrap_or(0)
+                    };
+                    Graphic {
+                        filename: directory.join(graphic.text().unwrap_or("").trim()),
+                        x: position("X"),
+                        y: position("Y"),
+                    }
+                })
+                .collect();
+            Ok(Event {
+                time_span: TimeSpan::new(start, end),
+                screen_size,
+                force: matches!(event.attribute("Forced"), Some(f) if f.eq_ignore_ascii_case("true")),
+                graphics,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    events.par_iter().map(load_event).collect()
+}
+
+/// Load an event's images, combining them into one if there are several.
+fn load_event(event: &Event) -> Result<SubtitleBitmap> {
+    let images = event
+        .graphics
+        .iter()
+        .map(|graphic| {
+            image::open(&graphic.filename)
+                .map(|image| (graphic, image.into_rgba8()))
+                .context(ImageSnafu {
+                    filename: &graphic.filename,
+                })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    // Graphics are positioned relative to the screen; crop to their union.
+    let left = images.iter().map(|(g, _)| g.x).min().unwrap_or(0);
+    let top = images.iter().map(|(g, _)| g.y).min().unwrap_or(0);
+    let right = images
+        .iter()
+        .map(|(g, i)| g.x + i.width())
+        .max()
+        .unwrap_or(0);
+    let bottom = images
+        .iter()
+        .map(|(g, i)| g.y + i.height())
+        .max()
+        .unwrap_or(0);
+    let mut canvas = image::RgbaImage::new(right - left, bottom - top);
+    for (graphic, image) in &images {
+        image::imageops::overlay(
+            &mut canvas,
+            image,
+            (graphic.x - left) as i64,
+            (graphic.y - top) as i64,
+        );
+    }
+
+    Ok(SubtitleBitmap::from_rgba(
+        event.time_span,
+        event.force,
+        (left, top),
+        event.screen_size,
+        &canvas,
+    ))
+}
+
+/// Parse an `HH:MM:SS:FF` timecode, where the last field counts frames.
+fn parse_timecode(value: &str, frame_rate: f64) -> Result<TimePoint> {
+    let fields = value
+        .split(':')
+        .map(|field| field.parse::<u32>().ok())
+        .collect::<Option<Vec<_>>>()
+        .filter(|fields| fields.len() == 4)
+        .context(InvalidTimecodeSnafu { value })?;
+    let seconds = (fields[0] * 3600 + fields[1] * 60 + fields[2]) as f64;
+    let msecs = seconds * 1000.0 + fields[3] as f64 * 1000.0 / frame_rate;
+    Ok(TimePoint::from_msecs(msecs.round() as i64))
+}
+
+/// Write subtitle bitmaps as a BDN XML file, with each image saved as a PNG
+/// next to it.
+pub fn write_bdn(
+    path: impl AsRef<Path>,
+    bitmaps: &[SubtitleBitmap],
+    frame_rate: f64,
+) -> Result<()> {
+    let path = path.as_ref();
+    let directory = path.parent().unwrap_or_else(|| Path::new(""));
+    let name = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    let bitmaps = bitmaps
+        .iter()
+        .filter(|bitmap| bitmap.width > 0 && bitmap.height > 0)
+        .collect::<Vec<_>>();
+    let video_format = match bitmaps.first().map(|bitmap| bitmap.screen_size.1) {
+        Some(480) => "480i",
+        Some(576) => "576i",
+        Some(720) => "720p",
+        _ => "1080p",
+    };
+    let timecode = |time| format_timecode(time, frame_rate);
+
+    let mut xml = String::new();
+    xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str("<BDN Version=\"0.93\" xmlns:xsi=\"http://www.w3.org/2001/XMLSchema-instance\" ");
+    xml.push_str("xsi:noNamespaceSchemaLocation=\"BD-03-006-0093b BDN File Format.xsd\">\n");
+    xml.push_str("  <Description>\n");
+    writeln!(
+        xml,
+        "    <Name Title=\"{}\" Content=\"\"/>",
+        escape_attribute(&name)
+    )
+    .unwrap();
+    writeln!(
+        xml,
+        "    <Format VideoFormat=\"{}\" FrameRate=\"{}\" DropFrame=\"False\"/>",
+        video_format, frame_rate
+    )
+    .unwrap();
+    writeln!(
+        xml,
+        "    <Events Type=\"Graphic\" FirstEventInTC=\"{}\" LastEventOutTC=\"{}\" NumberofEvents=\"{}\"/>",
+        bitmaps
+            .first()
+            .map_or_else(|| timecode(TimePoint::from_msecs(0)), |b| timecode(b.time_span.start)),
+        bitmaps
+            .last()
+            .map_or_else(|| timecode(TimePoint::from_msecs(0)), |b| timecode(b.time_span.end)),
+        bitmaps.len()
+    )
+    .unwrap();
+    xml.push_str("  </Description>\n");
+    xml.push_str("  <Events>\n");
+    for (i, bitmap) in bitmaps.iter().enumerate() {
+        let image_name = format!("{}_{:04}.png", name, i + 1);
+        let image_path = directory.join(&image_name);
+        bitmap.to_rgba().save(&image_path).context(SaveImageSnafu {
+            filename: image_path,
+        })?;
+        writeln!(
+            xml,
+            "    <Event InTC=\"{}\" OutTC=\"{}\" Forced=\"{}\">",
+            timecode(bitmap.time_span.start),
+            timecode(bitmap.time_span.end),
+            if bitmap.force { "True" } else { "False" }
+        )
+        .unwrap();
+        writeln!(
+            xml,
+            "      <Graphic Width=\"{}\" Height=\"{}\" X=\"{}\" Y=\"{}\">{}</Graphic>",
+            bitmap.width,
+            bitmap.height,
+            bitmap.x,
+            bitmap.y,
+            escape_attribute(&image_name)
+        )
+        .unwrap();
+        xml.push_str("    </Event>\n");
+    }
+    xml.push_str("  </Events>\n");
+    xml.push_str("</BDN>\n");
+    fs::write(path, xml).context(WriteSnafu { filename: path })
+}
+
+/// Format a time as an `HH:MM:SS:FF` timecode, the inverse of
+/// [`parse_timecode`].
+pub fn format_timecode(time: TimePoint, frame_rate: f64) -> String {
+    let msecs = time.msecs().max(0);
+    let mut seconds = msecs / 1000;
+    let mut frames = ((msecs % 1000) as f64 * frame_rate / 1000.0).round() as i64;
+    if frames as f64 >= frame_rate.ceil() {
+        seconds += 1;
+        frames = 0;
+    }
+    format!(
+        "{:02}:{:02}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60,
+        frames
+    )
+}
+
+/// Escape a string for use in an XML attribute or text.
+fn escape_attribute(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
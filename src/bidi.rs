@@ -0,0 +1,45 @@
+//! Fixing up lines of right-to-left text, like Hebrew and Arabic.
+//!
+//! Tesseract reads the punctuation around right-to-left text in visual order,
+//! so the punctuation ending a sentence, which is displayed on the left, comes
+//! out at the start of the line, and a dialogue dash, displayed on the right,
+//! comes out at the end. Players also lay a line out in the direction of its
+//! first letter, which a right-to-left mark at the start of the line sets
+//! even when it begins with punctuation or a Latin word.
+
+use crate::punctuation::DASHES;
+
+/// The right-to-left mark.
+const RLM: char = '\u{200f}';
+
+/// Punctuation which ends a sentence, including the Arabic comma, semicolon
+/// and question mark.
+const ENDING: &[char] = &['.', ',', '!', '?', ':', ';', '…', '،', '؛', '؟'];
+
+/// Put the punctuation of a right-to-left line back where it belongs and mark
+/// the line as right to left, leaving left-to-right lines alone.
+pub fn fix_line(line: &str) -> String {
+    if !matches!(line.chars().find(|c| c.is_alphabetic()), Some(c) if is_rtl(c)) {
+        return line.to_owned();
+    }
+    let line = line.trim();
+    let rest = line.trim_start_matches(ENDING);
+    let ending = &line[..line.len() - rest.len()];
+    let rest = rest.trim();
+    let text = rest.trim_end_matches(DASHES);
+    let dash = &rest[text.len()..];
+
+    let mut fixed = String::from(RLM);
+    if !dash.is_empty() {
+        fixed.push_str(dash);
+        fixed.push(' ');
+    }
+    fixed.push_str(text.trim_end());
+    fixed.push_str(ending);
+    fixed
+}
+
+/// Whether a character belongs to a right-to-left script.
+fn is_rtl(c: char) -> bool {
+    matches!(c, '\u{0590}'..='\u{08ff}' | '\u{fb1d}'..='\u{fdff}' | '\u{fe70}'..='\u{feff}')
+}
@@ -0,0 +1,146 @@
+//! A cache of OCR results on disk, so that running again on the same subtitles
+//! only recognizes the lines which changed.
+//!
+//! Each line's result is kept in a JSON file named after a hash of the line
+//! image and of every setting which affects OCR. Results are stored as soon as
+//! each line is recognized, so a run which is interrupted picks up where it
+//! left off when it is started again.
+
+use std::{
+    env, fs, io,
+    path::PathBuf,
+    process,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Once,
+    },
+};
+
+use crate::{ocr::Markup, opt::OcrOpt};
+use image::GrayImage;
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// Failing to write to the cache usually fails for every line, so only the
+/// first failure is reported.
+static WARN_WRITE: Once = Once::new();
+
+/// What OCR made of a line image.
+#[derive(Serialize, Deserialize)]
+pub struct Recognized {
+    pub text: String,
+    pub confidence: i32,
+    /// hOCR or ALTO for the line image, before it is moved to where the line
+    /// was on the screen.
+    pub markup: Option<String>,
+}
+
+pub struct Cache {
+    dir: PathBuf,
+    /// Hash of the settings, which every key starts from.
+    settings: u64,
+    /// How many lines were found in the cache.
+    hits: AtomicUsize,
+}
+
+impl Cache {
+    /// Use the cache directory, unless caching is turned off or there is no
+    /// place for it.
+    pub fn new(opt: &OcrOpt, markup: Option<Markup>) -> Option<Self> {
+        if opt.no_cache {
+            return None;
+        }
+        let dir = opt.cache_dir.clone().or_else(default_dir)?;
+        let settings = format!(
+            "{} {:?} {:?} {:?} {:?} {} {:?} {:?} {:?} {:?} {} {:?}",
+            env!("CARGO_PKG_VERSION"),
+            opt.engine,
+            opt.tessdata_dir,
+            opt.tesseract_language(),
+            opt.oem,
+            opt.psm,
+            opt.config_file_variables(),
+            opt.tesseract_config(),
+            opt.mark_words_below,
+            opt.word_marker,
+            opt.dpi,
+            markup,
+        );
+        Some(Self {
+            dir,
+            settings: fnv1a(FNV_OFFSET_BASIS, settings.as_bytes()),
+            hits: AtomicUsize::new(0),
+        })
+    }
+
+    /// Find the key of a line image.
+    pub fn key(&self, image: &GrayImage) -> u64 {
+        let hash = fnv1a(self.settings, &image.width().to_le_bytes());
+        let hash = fnv1a(hash, &image.height().to_le_bytes());
+        fnv1a(hash, image.as_raw())
+    }
+
+    /// Look up a line's result.
+    pub fn get(&self, key: u64) -> Option<Recognized> {
+        let json = fs::read(self.path(key)).ok()?;
+        let recognized = serde_json::from_slice(&json).ok()?;
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        Some(recognized)
+    }
+
+    /// How many lines have been found in the cache.
+    pub fn hits(&self) -> usize {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// Store a line's result, warning if it can't be.
+    pub fn put(&self, key: u64, recognized: &Recognized) {
+        if let Err(e) = self.write(key, recognized) {
+            WARN_WRITE.call_once(|| {
+                warn!(
+                    "Could not write to the OCR cache in {}: {}",
+                    self.dir.display(),
+                    e
+                )
+            });
+        }
+    }
+
+    fn write(&self, key: u64, recognized: &Recognized) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        // Write to a file of our own and move it into place, since other
+        // threads or processes may be storing the same line.
+        let temporary = self.dir.join(format!(
+            "{:016x}.{}-{}.tmp",
+            key,
+            process::id(),
+            rayon::current_thread_index().unwrap_or(0)
+        ));
+        fs::write(&temporary, serde_json::to_vec(recognized)?)?;
+        fs::rename(&temporary, self.path(key))
+    }
+
+    fn path(&self, key: u64) -> PathBuf {
+        self.dir.join(format!("{:016x}.json", key))
+    }
+}
+
+/// The platform's usual place for caches.
+fn default_dir() -> Option<PathBuf> {
+    let base = env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+        .or_else(|| env::var_os("LOCALAPPDATA").map(PathBuf::from))?;
+    Some(base.join("vobsubocr"))
+}
+
+/// Hash bytes with FNV-1a, which, unlike the standard library's hasher, is
+/// the same in every build.
+fn fnv1a(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(FNV_PRIME)
+    })
+}
@@ -0,0 +1,1085 @@
+//! Running the subcommands, for the binary, which only parses the command line
+//! and sets up logging.
+
+use crate::{
+    analyze, bdn, compression,
+    fixes::FixList,
+    hearing_impaired, info, interactive, language, ocr,
+    opt::{
+        BitmapFormat, Command, CompletionsOpt, ConvertOpt, DumpStage, InputOpt, OcrOpt, Opt,
+        Spellcheck, StreamSelector, Threshold,
+    },
+    preprocessor::{self, SubtitleBitmap, SubtitleTrack},
+    punctuation, recase, review, spelling,
+    summary::{self, Stage, Summary},
+    sup, timing, writer,
+};
+use clap::{builder::PossibleValuesParser, CommandFactory};
+use log::{error, info, warn};
+use rayon::prelude::*;
+use snafu::{ensure, OptionExt, ResultExt, Snafu};
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+use subparse::timetypes::TimePoint;
+
+/// How often `--watch` looks for new or changed files.
+const WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Exit code for errors without a code of their own.
+const EXIT_FAILURE: i32 = 1;
+
+/// Exit code when an input can't be read or parsed.
+const EXIT_READ: i32 = 3;
+
+/// Exit code when OCR fails, on everything or on some subtitle images.
+const EXIT_OCR: i32 = 4;
+
+/// Exit code when an output can't be written.
+const EXIT_WRITE: i32 = 5;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Could not parse VOB subtitles from {}: {}", filename.display(), source))]
+    ReadSubtitles {
+        filename: PathBuf,
+        source: preprocessor::Error,
+    },
+
+    #[snafu(display("More --offsets given than inputs"))]
+    TooManyOffsets,
+
+    #[snafu(display("--end {} isn't after --start {}", end, start))]
+    EmptyTimeRange { start: TimePoint, end: TimePoint },
+
+    #[snafu(display("Could not start the thread pool: {}", source))]
+    ThreadPool { source: rayon::ThreadPoolBuildError },
+
+    #[snafu(display("No OCR language given, and the input doesn't declare one; use -l"))]
+    MissingLanguage,
+
+    #[snafu(display("No Tesseract language known for track language `{}`; use -l", code))]
+    UnknownLanguage { code: String },
+
+    #[snafu(display("{}", source))]
+    Spelling { source: spelling::Error },
+
+    #[snafu(display("{}", source))]
+    Info { source: info::Error },
+
+    #[snafu(display("{}", source))]
+    Summary { source: summary::Error },
+
+    #[snafu(display("{}", source))]
+    Interactive { source: interactive::Error },
+
+    #[snafu(display("Could not perform OCR on subtitles: {}", source))]
+    Ocr { source: ocr::Error },
+
+    #[snafu(display("OCR failed on subtitle {} at {}: {}", index, time, source))]
+    OcrSubtitle {
+        index: usize,
+        time: TimePoint,
+        source: ocr::Error,
+    },
+
+    #[snafu(display("{}", source))]
+    GenerateSubtitles { source: writer::Error },
+
+    #[snafu(display("Could not write subtitle file {}: {}", filename.display(), source))]
+    WriteSubtitles {
+        filename: PathBuf,
+        source: io::Error,
+    },
+
+    #[snafu(display("Could not write archive {}: {}", filename.display(), source))]
+    WriteArchive {
+        filename: PathBuf,
+        source: zip::result::ZipError,
+    },
+
+    #[snafu(display("Can't tell the format to write {} in; use --format", filename.display()))]
+    UnknownBitmapFormat { filename: PathBuf },
+
+    #[snafu(display("Could not write SUP file {}: {}", filename.display(), source))]
+    WriteSup {
+        filename: PathBuf,
+        source: io::Error,
+    },
+
+    #[snafu(display("Could not write BDN XML: {}", source))]
+    WriteBdn { source: bdn::Error },
+
+    #[snafu(display("Could not write review page {}: {}", filename.display(), source))]
+    WriteReview {
+        filename: PathBuf,
+        source: review::Error,
+    },
+
+    #[snafu(display("Could not read directory {}: {}", dirname.display(), source))]
+    ReadDir { dirname: PathBuf, source: io::Error },
+
+    #[snafu(display("No idx files found in the input directories"))]
+    NoInputs,
+
+    #[snafu(display("Can only watch directories: {}", path.display()))]
+    WatchFile { path: PathBuf },
+
+    #[snafu(display("Could not create dump directory {}: {}", dirname.display(), source))]
+    CreateDumpDir { dirname: PathBuf, source: io::Error },
+
+    #[snafu(display("Could not write image dump file {}: {}", filename.display(), source))]
+    DumpImage {
+        filename: PathBuf,
+        source: image::ImageError,
+    },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl Error {
+    /// The exit code telling scripts what kind of error this is.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::ReadSubtitles { .. }
+            | Error::Info { .. }
+            | Error::ReadDir { .. }
+            | Error::NoInputs => EXIT_READ,
+            Error::Ocr { .. } | Error::OcrSubtitle { .. } => EXIT_OCR,
+            Error::GenerateSubtitles { .. }
+            | Error::WriteSubtitles { .. }
+            | Error::WriteArchive { .. }
+            | Error::WriteSup { .. }
+            | Error::WriteBdn { .. }
+            | Error::WriteReview { .. }
+            | Error::CreateDumpDir { .. }
+            | Error::DumpImage { .. }
+            | Error::Summary { .. } => EXIT_WRITE,
+            _ => EXIT_FAILURE,
+        }
+    }
+}
+
+/// Read every input, shifting each by its offset and keeping the subtitles
+/// chosen by `--forced-only` or `--no-forced` and starting between `--start`
+/// and `--end`.
+fn read_inputs(opt: &InputOpt) -> Result<Vec<SubtitleTrack>> {
+    ensure!(
+        opt.offsets.len() <= opt.inputs.len(),
+        TooManyOffsetsSnafu {}
+    );
+    if let (Some(start), Some(end)) = (opt.start, opt.end) {
+        ensure!(start < end, EmptyTimeRangeSnafu { start, end });
+    }
+
+    let mut inputs = Vec::new();
+    for (i, input) in opt.inputs.iter().enumerate() {
+        let mut track = preprocessor::read_bitmaps(opt, input)
+            .context(ReadSubtitlesSnafu { filename: input })?;
+        if opt.forced_only || opt.no_forced {
+            track
+                .bitmaps
+                .retain(|bitmap| bitmap.force == opt.forced_only);
+            if track.bitmaps.is_empty() && opt.forced_only {
+                warn!("No forced subtitles in {}", input.display());
+            }
+        }
+        if let Some(&offset) = opt.offsets.get(i) {
+            for bitmap in &mut track.bitmaps {
+                bitmap.time_span += offset;
+            }
+        }
+        if opt.start.is_some() || opt.end.is_some() {
+            track.bitmaps.retain(|bitmap| {
+                let start = bitmap.time_span.start;
+                !matches!(opt.start, Some(from) if start < from)
+                    && !matches!(opt.end, Some(to) if start >= to)
+            });
+        }
+        inputs.push(track);
+    }
+    if opt.start.is_some() || opt.end.is_some() {
+        let count: usize = inputs.iter().map(|input| input.bitmaps.len()).sum();
+        if count == 0 {
+            warn!("No subtitles between --start and --end");
+        }
+    }
+    Ok(inputs)
+}
+
+/// Run a subcommand, returning the exit code.
+pub fn run(opt: Opt) -> Result<i32> {
+    match opt.command {
+        Command::Ocr(ocr_opt) => recognize(*ocr_opt),
+        Command::Convert(convert_opt) => {
+            convert(&convert_opt)?;
+            Ok(0)
+        }
+        Command::Info(info_opt) => {
+            print!("{}", info::describe(&info_opt).context(InfoSnafu {})?);
+            Ok(0)
+        }
+        Command::Completions(completions_opt) => {
+            print_completions(&completions_opt);
+            Ok(0)
+        }
+    }
+}
+
+/// Print the completion script for a shell, which completes `-l` with the
+/// languages installed when it is generated.
+fn print_completions(opt: &CompletionsOpt) {
+    let languages = language::installed(opt.tessdata_dir.as_deref());
+    let mut command = Opt::command();
+    if !languages.is_empty() {
+        command = command.mut_subcommand("ocr", |ocr| {
+            ocr.mut_arg("lang", |lang| {
+                lang.value_parser(PossibleValuesParser::new(languages))
+            })
+        });
+    }
+    let name = command.get_name().to_owned();
+    clap_complete::generate(opt.shell, &mut command, name, &mut io::stdout());
+}
+
+/// Recognize the text of the inputs' subtitles and write it, for the `ocr`
+/// subcommand.
+fn recognize(mut opt: OcrOpt) -> Result<i32> {
+    opt.warn_unknown_variables();
+    if opt.interactive {
+        interactive::check_terminal().context(InteractiveSnafu {})?;
+    }
+
+    if let Some(jobs) = opt.jobs {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(jobs as usize)
+            .build_global()
+            .context(ThreadPoolSnafu {})?;
+    }
+
+    if opt.watch {
+        return watch(&mut opt);
+    }
+    recognize_inputs(&mut opt)
+}
+
+/// Convert the idx files appearing in the watched directories, each once it
+/// and its sub file have stopped changing since the last look, and again
+/// whenever they change after that.
+fn watch(opt: &mut OcrOpt) -> Result<i32> {
+    let dirnames = opt.input.inputs.clone();
+    for dirname in &dirnames {
+        ensure!(dirname.is_dir(), WatchFileSnafu { path: dirname });
+    }
+    // The language chosen for one track shouldn't carry over to the next.
+    let lang = opt.lang.clone();
+    let mut seen = HashMap::new();
+    let mut converted = HashMap::new();
+    loop {
+        let mut found = Vec::new();
+        for dirname in &dirnames {
+            find_idx_files(dirname, opt.recursive, &mut found)?;
+        }
+        for idx in found {
+            let sub = match compression::find(&idx.with_extension("sub")) {
+                Some(sub) => sub,
+                None => continue,
+            };
+            let state = match (fs::metadata(&idx), fs::metadata(&sub)) {
+                (Ok(idx), Ok(sub)) => (idx.len(), sub.len(), sub.modified().ok()),
+                _ => continue,
+            };
+            let unchanged = seen.insert(idx.clone(), state) == Some(state);
+            if !unchanged || converted.get(&idx) == Some(&state) {
+                continue;
+            }
+            converted.insert(idx.clone(), state);
+            opt.input.inputs = vec![idx.clone()];
+            opt.lang = lang.clone();
+            opt.separate_outputs = true;
+            match recognize_inputs(opt) {
+                Ok(0) => info!("Converted {}", idx.display()),
+                Ok(_) => warn!(
+                    "Converted {}, but OCR failed on some subtitles",
+                    idx.display()
+                ),
+                Err(e) => error!("Could not convert {}: {}", idx.display(), e),
+            }
+        }
+        thread::sleep(WATCH_INTERVAL);
+    }
+}
+
+/// Recognize the text of the subtitles of `opt`'s inputs and write it.
+fn recognize_inputs(opt: &mut OcrOpt) -> Result<i32> {
+    if opt.recursive {
+        opt.input.inputs = find_inputs(&opt.input.inputs)?;
+        opt.separate_outputs = opt.output_archive.is_none();
+    }
+    if opt.auto_output {
+        opt.separate_outputs = true;
+    }
+
+    let mut summary = Summary::new(opt.min_confidence);
+
+    // Inputs are joined into one output unless each gets its own file in an
+    // archive.
+    let inputs = read_inputs(&opt.input)?;
+    let track_language = inputs.iter().find_map(|input| input.language.as_deref());
+    choose_language(opt, track_language)?;
+    summary.languages = opt.lang.clone();
+    summary.end_stage(Stage::Read);
+    if opt.analyze {
+        for (input, track) in opt.input.inputs.iter().zip(&inputs) {
+            print!("{}", analyze::describe(input, track, opt));
+        }
+        return Ok(0);
+    }
+    let inputs = inputs.into_iter().map(|input| input.bitmaps);
+    let groups: Vec<Vec<_>> = if opt.output_archive.is_some() || opt.separate_outputs {
+        inputs.collect()
+    } else {
+        vec![inputs.flatten().collect()]
+    };
+    let mut vobsubs = Vec::new();
+    let mut sources = Vec::new();
+    let mut group_sizes = Vec::new();
+    // The index each subtitle has among all of them, which `--only` picks
+    // from and dumped images are named with.
+    let mut indices = Vec::new();
+    let mut index = 0;
+    for bitmaps in &groups {
+        let group = preprocessor::preprocess_subtitles(bitmaps, opt);
+        let mut size = 0;
+        for (bitmap, vobsub) in group {
+            if !matches!(&opt.only, Some(only) if !only.contains(&index)) {
+                sources.push(bitmap);
+                vobsubs.push(vobsub);
+                indices.push(index);
+                size += 1;
+            }
+            index += 1;
+        }
+        group_sizes.push(size);
+    }
+    if sources.is_empty() && opt.only.is_some() {
+        warn!("No subtitles at the indices given with --only");
+    }
+    if let Some(count) = opt.preview {
+        return preview(vobsubs, &indices, count as usize, opt);
+    }
+
+    // Dump images if requested.
+    if opt.dump {
+        dump_images(&sources, &vobsubs, &indices, opt)?;
+    }
+
+    // Render the review page's images before OCR takes the subtitles.
+    let thumbnails = match &opt.review {
+        Some(path) => vobsubs
+            .par_iter()
+            .map(review::thumbnail)
+            .collect::<review::Result<Vec<_>>>()
+            .context(WriteReviewSnafu { filename: path })?,
+        None => Vec::new(),
+    };
+
+    summary.end_stage(Stage::Preprocess);
+
+    let mut subtitles = ocr::process(vobsubs, opt).context(OcrSnafu {})?;
+    if opt.strict {
+        // The others failing were only skipped because of it.
+        let failure = subtitles
+            .iter()
+            .position(|subtitle| matches!(subtitle, Err(e) if !matches!(e, ocr::Error::Skipped)));
+        if let Some(i) = failure {
+            if let Err(source) = subtitles.swap_remove(i) {
+                let time = sources[i].time_span.start;
+                return Err(source).context(OcrSubtitleSnafu {
+                    index: indices[i],
+                    time,
+                });
+            }
+        }
+    }
+    if opt.grayscale_pass {
+        let indices: Vec<_> = (0..subtitles.len()).collect();
+        reprocess(
+            &sources,
+            &mut subtitles,
+            &indices,
+            opt.threshold,
+            !opt.grayscale,
+            opt,
+        )?;
+    }
+    retry_low_confidence(&sources, &mut subtitles, opt)?;
+    summary.end_stage(Stage::Ocr);
+
+    if let Some(path) = &opt.review {
+        review::write_review(path, &subtitles, &thumbnails)
+            .context(WriteReviewSnafu { filename: path })?;
+    }
+
+    if let Some(threshold) = opt.min_confidence.filter(|_| opt.interactive) {
+        let dropped = interactive::review(&mut subtitles, &sources, threshold)
+            .context(InteractiveSnafu {})?;
+        drop_subtitles(&mut subtitles, &mut group_sizes, &dropped);
+    }
+    // Time spent on the review page and waiting for answers isn't part of
+    // any stage.
+    summary.skip_time();
+
+    // Log errors and remove bad results, then create a subtitle file for each
+    // group.
+    let mut return_code = 0;
+    let mut fix_lists = Vec::new();
+    if opt.fix_ocr_errors {
+        fix_lists.push(FixList::builtin(&opt.lang));
+    }
+    if !opt.fix_confusions.is_empty() {
+        fix_lists.push(FixList::confusions(&opt.fix_confusions));
+    }
+    fix_lists.extend(opt.fix_list.iter().cloned());
+    let dictionary = match opt.spellcheck {
+        Some(_) => {
+            Some(spelling::Dictionary::open(&opt.dictionary, &opt.lang).context(SpellingSnafu {})?)
+        }
+        None => None,
+    };
+    let mut unknown_words = Vec::new();
+    let format = writer::output_format(opt);
+    let writer = writer::new(format, opt);
+    let mut subtitles = subtitles.into_iter();
+    let mut files = Vec::new();
+    let mut counts = Vec::new();
+    let mut forced_files = Vec::new();
+    let mut failures = 0;
+    let forced_writer = writer::new(writer::forced_output_format(opt), opt);
+    let mut forced_file = None;
+    for size in group_sizes {
+        let group: Vec<ocr::Subtitle> = subtitles
+            .by_ref()
+            .take(size)
+            .filter_map(|maybe_subtitle| match maybe_subtitle {
+                Ok(subtitle) => Some(subtitle),
+                Err(e) => {
+                    warn!("Error while running OCR on subtitle image: {}", e);
+                    if !opt.lax {
+                        return_code = EXIT_OCR;
+                    }
+                    failures += 1;
+                    None
+                }
+            })
+            .collect();
+        let mut group = check_confidence(group, opt);
+        if !fix_lists.is_empty() || !opt.replace.is_empty() {
+            group = fix_text(group, &fix_lists, opt);
+        }
+        if opt.remove_hi {
+            group = hearing_impaired::remove(group);
+        }
+        if opt.recase {
+            recase::recase(
+                &mut group,
+                &opt.proper_nouns,
+                dictionary.as_ref(),
+                &opt.lang,
+            );
+        }
+        if let Some(dictionary) = &dictionary {
+            let correct = opt.spellcheck == Some(Spellcheck::Correct);
+            unknown_words.extend(spelling::check(&mut group, dictionary, correct));
+        }
+        if opt.normalize_punctuation {
+            normalize_punctuation(&mut group, opt);
+        }
+        if let Some((scale, offset)) = opt.retiming() {
+            timing::retime(&mut group, scale, offset);
+        }
+        if let Some(strategy) = opt.fix_overlaps {
+            timing::fix_overlaps(&mut group, strategy);
+        }
+        timing::enforce_durations(&mut group, opt.min_duration, opt.max_duration, opt.min_gap);
+        if opt.speaker_dashes {
+            for subtitle in &mut group {
+                subtitle.add_speaker_dashes();
+            }
+        }
+        if let Some(max_line_length) = opt.max_line_length {
+            for subtitle in &mut group {
+                subtitle.reflow(max_line_length as usize);
+            }
+        }
+        summary.add_subtitles(&group);
+        counts.push(group.len());
+        files.push(writer.write(&group).context(GenerateSubtitlesSnafu {})?);
+        if let Some(output) = &opt.forced_output {
+            let forced: Vec<_> = group.into_iter().filter(|s| s.force).collect();
+            if forced.is_empty() {
+                warn!("No forced subtitles to write to {}", output.display());
+            }
+            forced_file = Some(
+                forced_writer
+                    .write(&forced)
+                    .context(GenerateSubtitlesSnafu {})?,
+            );
+        } else if opt.auto_output && !opt.input.forced_only {
+            // Players pick up forced subtitles from a file of their own.
+            let forced: Vec<_> = group.into_iter().filter(|s| s.force).collect();
+            if forced.is_empty() {
+                forced_files.push(None);
+            } else {
+                let file = writer.write(&forced).context(GenerateSubtitlesSnafu {})?;
+                forced_files.push(Some((file, forced.len())));
+            }
+        }
+    }
+
+    summary.failed = failures;
+    summary.unknown_words = unknown_words.len();
+    summary.end_stage(Stage::Postprocess);
+
+    match &opt.spelling_report {
+        Some(path) => spelling::write_report(path, &unknown_words).context(SpellingSnafu {})?,
+        None if !unknown_words.is_empty() => {
+            for word in &unknown_words {
+                info!(
+                    "Unknown word `{}` in subtitle {} at {}, confidence {}: {:?}",
+                    word.word, word.number, word.time, word.confidence, word.line
+                );
+            }
+            warn!(
+                "{} words weren't in the dictionary; list them with --spelling-report",
+                unknown_words.len()
+            );
+        }
+        None => {}
+    }
+
+    match (&opt.output_archive, &opt.output) {
+        (None, None) if opt.separate_outputs => {
+            let paths = output_paths(opt, writer::extension(format), false);
+            let forced_paths = output_paths(opt, writer::extension(format), true);
+            let forced_files =
+                forced_paths
+                    .iter()
+                    .zip(&forced_files)
+                    .filter_map(|(path, forced)| {
+                        let (file, count) = forced.as_ref()?;
+                        Some((path, (file, count)))
+                    });
+            let outputs = paths.iter().zip(files.iter().zip(&counts));
+            for (output, (file, count)) in outputs.chain(forced_files) {
+                if let Some(dirname) = output.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+                    fs::create_dir_all(dirname)
+                        .context(WriteSubtitlesSnafu { filename: output })?;
+                }
+                fs::write(output, file).context(WriteSubtitlesSnafu { filename: output })?;
+                info!("Wrote {} subtitles to {}", count, output.display());
+            }
+            info!(
+                "Wrote {} subtitles to {} files, with {} subtitle images failing OCR",
+                counts.iter().sum::<usize>(),
+                files.len(),
+                failures
+            );
+        }
+        (Some(archive), _) => {
+            let paths = output_paths(opt, writer::extension(format), false);
+            write_archive(archive, paths.iter().zip(&files))
+                .context(WriteArchiveSnafu { filename: archive })?;
+        }
+        (None, Some(output)) => {
+            // Write to file.
+            let mut subtitle_file =
+                File::create(output).context(WriteSubtitlesSnafu { filename: output })?;
+            subtitle_file
+                .write_all(&files[0])
+                .context(WriteSubtitlesSnafu { filename: output })?;
+        }
+        (None, None) => {
+            // Write to stdout.
+            io::stdout()
+                .write_all(&files[0])
+                .context(WriteSubtitlesSnafu {
+                    filename: "<stdout>",
+                })?;
+        }
+    }
+
+    if let (Some(output), Some(file)) = (&opt.forced_output, forced_file) {
+        fs::write(output, file).context(WriteSubtitlesSnafu { filename: output })?;
+    }
+
+    summary.end_stage(Stage::Write);
+    if let Some(path) = &opt.summary_json {
+        summary.write(path).context(SummarySnafu {})?;
+    }
+
+    Ok(return_code)
+}
+
+/// Run OCR on `count` subtitles spread evenly among `vobsubs` and print them
+/// with their indices, times and confidence.
+fn preview(
+    vobsubs: Vec<preprocessor::PreprocessedVobSubtitle>,
+    indices: &[usize],
+    count: usize,
+    opt: &OcrOpt,
+) -> Result<i32> {
+    let total = vobsubs.len();
+    let count = count.min(total);
+    // The middle subtitle of each of `count` equal parts.
+    let picked: Vec<usize> = (0..count)
+        .map(|k| (2 * k + 1) * total / (2 * count))
+        .collect();
+    let sample: Vec<_> = vobsubs
+        .into_iter()
+        .enumerate()
+        .filter(|(i, _)| picked.binary_search(i).is_ok())
+        .map(|(_, vobsub)| vobsub)
+        .collect();
+    let mut return_code = 0;
+    for (&i, subtitle) in picked
+        .iter()
+        .zip(ocr::process(sample, opt).context(OcrSnafu {})?)
+    {
+        match subtitle {
+            Ok(subtitle) => {
+                println!(
+                    "{:06} {} confidence {}",
+                    indices[i],
+                    subtitle.time_span.start,
+                    subtitle.confidence()
+                );
+                for line in &subtitle.lines {
+                    println!("  {}", line.text);
+                }
+            }
+            Err(e) => {
+                println!("{:06} OCR failed: {}", indices[i], e);
+                if !opt.lax {
+                    return_code = EXIT_OCR;
+                }
+            }
+        }
+    }
+    Ok(return_code)
+}
+
+/// Dump the images of each requested stage of preprocessing.
+fn dump_images(
+    sources: &[&SubtitleBitmap],
+    vobsubs: &[preprocessor::PreprocessedVobSubtitle],
+    indices: &[usize],
+    opt: &OcrOpt,
+) -> Result<()> {
+    if let Some(dirname) = &opt.dump_dir {
+        fs::create_dir_all(dirname).context(CreateDumpDirSnafu { dirname })?;
+    }
+    for ((source, sub), &i) in sources.iter().zip(vobsubs).zip(indices) {
+        let filename = |part: &str| dump_filename(opt, i, part, sub.time_span.start);
+        if opt.dumps(DumpStage::Raw) {
+            let filename = filename("raw");
+            source
+                .to_indexed()
+                .save(&filename)
+                .context(DumpImageSnafu { filename })?;
+        }
+        if opt.dumps(DumpStage::Palette) {
+            let filename = filename("palette");
+            source
+                .to_rgba()
+                .save(&filename)
+                .context(DumpImageSnafu { filename })?;
+        }
+        if let Some(binarized) = &sub.binarized {
+            let filename = filename("binarized");
+            binarized
+                .save(&filename)
+                .context(DumpImageSnafu { filename })?;
+        }
+        if opt.dumps(DumpStage::Lines) {
+            for (j, line) in sub.lines.iter().enumerate() {
+                let filename = filename(&format!("{:02}", j));
+                line.image
+                    .save(&filename)
+                    .context(DumpImageSnafu { filename })?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Fill in `--dump-name` for one image.
+fn dump_filename(opt: &OcrOpt, index: usize, part: &str, start: TimePoint) -> PathBuf {
+    let msecs = start.msecs().max(0);
+    let start = format!(
+        "{:02}-{:02}-{:02}.{:03}",
+        msecs / 3_600_000,
+        msecs / 60_000 % 60,
+        msecs / 1000 % 60,
+        msecs % 1000
+    );
+    let name = opt
+        .dump_name
+        .replace("{index}", &format!("{:06}", index))
+        .replace("{part}", part)
+        .replace("{start}", &start)
+        .replace("{lang}", &opt.tesseract_language());
+    let filename = format!("{}.png", name);
+    match &opt.dump_dir {
+        Some(dir) => dir.join(filename),
+        None => PathBuf::from(filename),
+    }
+}
+
+/// Use the Tesseract language matching the track's language if none was given
+/// with `-l`, or warn if the given one doesn't match it.
+fn choose_language(opt: &mut OcrOpt, track_language: Option<&str>) -> Result<()> {
+    let detected =
+        track_language.and_then(|code| language::tesseract_language(code).map(|lang| (code, lang)));
+    if opt.lang.is_empty() {
+        let (code, lang) = match (detected, track_language) {
+            (Some(detected), _) => detected,
+            (None, Some(code)) => return UnknownLanguageSnafu { code }.fail(),
+            (None, None) => return MissingLanguageSnafu {}.fail(),
+        };
+        info!(
+            "Using Tesseract language {} for track language {}",
+            lang, code
+        );
+        opt.lang.push(lang.to_owned());
+    } else if let Some((code, lang)) = detected {
+        let given = opt.tesseract_language();
+        if !given.split('+').any(|l| language::same_language(l, lang)) {
+            warn!(
+                "The subtitle track's language is `{}`, but OCR will use `{}`",
+                code, given
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Run OCR again on the subtitles below `--retry-below`, binarized at each of
+/// `--retry-thresholds`, and keep whichever result is most confident.
+fn retry_low_confidence(
+    bitmaps: &[&SubtitleBitmap],
+    subtitles: &mut [ocr::Result<ocr::Subtitle>],
+    opt: &OcrOpt,
+) -> Result<()> {
+    let floor = match opt.retry_below {
+        Some(floor) => floor,
+        None => return Ok(()),
+    };
+    for &threshold in &opt.retry_thresholds {
+        let indices: Vec<_> = subtitles
+            .iter()
+            .enumerate()
+            .filter(|(_, subtitle)| matches!(subtitle, Ok(s) if s.confidence() < floor))
+            .map(|(i, _)| i)
+            .collect();
+        if indices.is_empty() {
+            break;
+        }
+        info!(
+            "Retrying {} subtitles with threshold {}",
+            indices.len(),
+            threshold
+        );
+        reprocess(
+            bitmaps,
+            subtitles,
+            &indices,
+            Threshold::Fixed(threshold),
+            opt.grayscale,
+            opt,
+        )?;
+    }
+    Ok(())
+}
+
+/// Run OCR again on some of the subtitles, preprocessed with another
+/// threshold or rendering, replacing the results that become more
+/// confident.
+fn reprocess(
+    bitmaps: &[&SubtitleBitmap],
+    subtitles: &mut [ocr::Result<ocr::Subtitle>],
+    indices: &[usize],
+    threshold: Threshold,
+    grayscale: bool,
+    opt: &OcrOpt,
+) -> Result<()> {
+    let (indices, vobsubs): (Vec<_>, Vec<_>) = indices
+        .par_iter()
+        .filter_map(|&i| {
+            preprocessor::preprocess_subtitle(bitmaps[i], threshold, grayscale, opt)
+                .map(|vobsub| (i, vobsub))
+        })
+        .unzip();
+    let retries = ocr::process(vobsubs, opt).context(OcrSnafu {})?;
+    for (i, retry) in indices.into_iter().zip(retries) {
+        if let (Ok(retry), Ok(subtitle)) = (retry, &subtitles[i]) {
+            if retry.confidence() > subtitle.confidence() {
+                info!(
+                    "Subtitle at {} improved from confidence {} to {} {} with threshold {}",
+                    subtitle.time_span.start,
+                    subtitle.confidence(),
+                    retry.confidence(),
+                    if grayscale {
+                        "in grayscale"
+                    } else {
+                        "binarized"
+                    },
+                    threshold
+                );
+                subtitles[i] = Ok(retry);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Log the OCR confidence of each subtitle, warning about those below
+/// `--min-confidence` and dropping them if asked to.
+fn check_confidence(subtitles: Vec<ocr::Subtitle>, opt: &OcrOpt) -> Vec<ocr::Subtitle> {
+    subtitles
+        .into_iter()
+        .filter(|subtitle| {
+            let confidence = subtitle.confidence();
+            let start = subtitle.time_span.start;
+            info!("Subtitle at {} has confidence {}", start, confidence);
+            match opt.min_confidence {
+                Some(min_confidence) if confidence < min_confidence => {
+                    warn!(
+                        "Subtitle at {} has low confidence {}{}: {:?}",
+                        start,
+                        confidence,
+                        if opt.drop_low_confidence {
+                            "; dropping it"
+                        } else {
+                            ""
+                        },
+                        subtitle.text()
+                    );
+                    !opt.drop_low_confidence
+                }
+                _ => true,
+            }
+        })
+        .collect()
+}
+
+/// Remove the subtitles at some indices, shrinking the groups they were in.
+fn drop_subtitles<T>(subtitles: &mut Vec<T>, group_sizes: &mut [usize], dropped: &[usize]) {
+    let mut start = 0;
+    for size in group_sizes.iter_mut() {
+        let end = start + *size;
+        *size -= dropped.iter().filter(|&i| (start..end).contains(i)).count();
+        start = end;
+    }
+    let mut i = 0;
+    subtitles.retain(|_| {
+        i += 1;
+        !dropped.contains(&(i - 1))
+    });
+}
+
+/// Apply the OCR fixes and then the `--replace` rules to each line, dropping
+/// lines they empty and subtitles left without lines.
+fn fix_text(
+    subtitles: Vec<ocr::Subtitle>,
+    fix_lists: &[FixList],
+    opt: &OcrOpt,
+) -> Vec<ocr::Subtitle> {
+    subtitles
+        .into_iter()
+        .filter_map(|mut subtitle| {
+            subtitle.lines.retain_mut(|line| {
+                let text = fix_lists
+                    .iter()
+                    .fold(line.text.clone(), |text, fixes| fixes.apply(&text));
+                let text = opt
+                    .replace
+                    .iter()
+                    .fold(text, |text, rules| rules.apply(&text));
+                if text.trim().is_empty() && !line.text.trim().is_empty() {
+                    return false;
+                }
+                line.text = text;
+                true
+            });
+            if subtitle.lines.is_empty() {
+                info!(
+                    "Dropping subtitle at {} emptied by fixes or replacement rules",
+                    subtitle.time_span.start
+                );
+                return None;
+            }
+            Some(subtitle)
+        })
+        .collect()
+}
+
+/// Normalize the punctuation of each subtitle for the first OCR language.
+fn normalize_punctuation(subtitles: &mut [ocr::Subtitle], opt: &OcrOpt) {
+    let language = opt.lang.first().map_or("", String::as_str);
+    for subtitle in subtitles {
+        let text = punctuation::normalize(&subtitle.text(), language);
+        // Line breaks are kept, and a line may have several of its own.
+        let mut rows = text.split('\n');
+        for line in &mut subtitle.lines {
+            let count = line.text.matches('\n').count() + 1;
+            line.text = rows.by_ref().take(count).collect::<Vec<_>>().join("\n");
+        }
+    }
+}
+
+/// Replace each directory among the inputs with the idx files in it and the
+/// directories inside it, in order of their paths.
+fn find_inputs(inputs: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    let mut found = Vec::new();
+    for input in inputs {
+        if input.is_dir() {
+            let before = found.len();
+            find_idx_files(input, true, &mut found)?;
+            if found.len() == before {
+                warn!("No idx files in {}", input.display());
+            }
+        } else {
+            found.push(input.clone());
+        }
+    }
+    ensure!(!found.is_empty(), NoInputsSnafu {});
+    Ok(found)
+}
+
+/// Add the idx files in a directory, and in the directories inside it if
+/// `recursive`, in order of their paths.
+fn find_idx_files(dirname: &Path, recursive: bool, found: &mut Vec<PathBuf>) -> Result<()> {
+    let mut paths = fs::read_dir(dirname)
+        .and_then(|entries| {
+            entries
+                .map(|entry| Ok(entry?.path()))
+                .collect::<io::Result<Vec<_>>>()
+        })
+        .context(ReadDirSnafu { dirname })?;
+    paths.sort();
+    for path in paths {
+        let is_idx = matches!(
+            path.extension().and_then(|ext| ext.to_str()),
+            Some(ext) if ext.eq_ignore_ascii_case("idx")
+        );
+        if path.is_dir() {
+            if recursive {
+                find_idx_files(&path, recursive, found)?;
+            }
+        } else if is_idx {
+            found.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Name the file for each input after the input, and the selected track or
+/// else the OCR language, following `--output-name`, or for `--auto-output`
+/// after the language's two-letter code, marking files of forced subtitles.
+/// Files are placed next to their inputs for `--separate-outputs`, or else in
+/// an output archive.
+fn output_paths(opt: &OcrOpt, extension: &str, forced: bool) -> Vec<PathBuf> {
+    let label = match &opt.input.track {
+        _ if opt.auto_output => opt
+            .lang
+            .first()
+            .and_then(|lang| language::iso_639_1(lang))
+            .map_or_else(|| opt.tesseract_language(), str::to_owned),
+        Some(StreamSelector::Language(lang)) => lang.clone(),
+        Some(StreamSelector::Index(i)) => format!("track{}", i),
+        None => opt.tesseract_language(),
+    };
+    let forced = forced || (opt.auto_output && opt.input.forced_only);
+    let mut paths: Vec<PathBuf> = Vec::new();
+    for input in &opt.input.inputs {
+        let stem = compression::strip_extension(input)
+            .file_stem()
+            .map_or_else(|| "stdin".to_owned(), |s| s.to_string_lossy().into_owned());
+        let mut base = opt
+            .output_name
+            .replace("{stem}", &stem)
+            .replace("{lang}", &label.replace('+', "-"));
+        if forced {
+            base.push_str(".forced");
+        }
+        let place = |name: String| {
+            if opt.separate_outputs {
+                input.with_file_name(name)
+            } else {
+                PathBuf::from(name)
+            }
+        };
+        let mut path = place(format!("{}.{}", base, extension));
+        // Inputs from different directories may share a name.
+        let mut n = 1;
+        while paths.contains(&path) {
+            n += 1;
+            path = place(format!("{}-{}.{}", base, n, extension));
+        }
+        paths.push(path);
+    }
+    paths
+}
+
+/// Write named files into a zip archive.
+fn write_archive<'a>(
+    path: &Path,
+    files: impl IntoIterator<Item = (&'a PathBuf, &'a Vec<u8>)>,
+) -> zip::result::ZipResult<()> {
+    let mut zip = zip::ZipWriter::new(File::create(path)?);
+    for (name, data) in files {
+        zip.start_file(name.to_string_lossy(), zip::write::FileOptions::default())?;
+        zip.write_all(data)?;
+    }
+    zip.finish()?;
+    Ok(())
+}
+
+/// Write the subtitle images to another image-based format.
+fn convert(opt: &ConvertOpt) -> Result<()> {
+    let format = opt.format.or_else(|| {
+        match opt
+            .output
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_ascii_lowercase())
+            .as_deref()
+        {
+            Some("sup") => Some(BitmapFormat::Sup),
+            Some("xml") => Some(BitmapFormat::Bdn),
+            _ => None,
+        }
+    });
+    let format = format.context(UnknownBitmapFormatSnafu {
+        filename: &opt.output,
+    })?;
+
+    let bitmaps: Vec<_> = read_inputs(&opt.input)?
+        .into_iter()
+        .flat_map(|input| input.bitmaps)
+        .collect();
+    match format {
+        BitmapFormat::Sup => sup::write_sup(&opt.output, &bitmaps).context(WriteSupSnafu {
+            filename: &opt.output,
+        }),
+        BitmapFormat::Bdn => {
+            bdn::write_bdn(&opt.output, &bitmaps, opt.fps).context(WriteBdnSnafu {})
+        }
+    }
+}
@@ -0,0 +1,149 @@
+//! Google Cloud Vision as an OCR engine, for fonts Tesseract struggles with.
+//!
+//! Each line image is sent as a PNG to the `images:annotate` endpoint.
+//! Requests from all threads are spaced out to stay within
+//! `--requests-per-second`.
+
+use std::{
+    env,
+    io::Cursor,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    ocr::{BoxError, EngineSnafu, MissingApiKeySnafu, OcrEngine, Result, WriteImageSnafu},
+    opt::OcrOpt,
+};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use image::{DynamicImage, GrayImage, ImageOutputFormat};
+use serde_json::{json, Value};
+use snafu::{OptionExt, ResultExt};
+
+const ENDPOINT: &str = "https://vision.googleapis.com/v1/images:annotate";
+
+/// When the next request may be sent.
+static NEXT_REQUEST: Mutex<Option<Instant>> = Mutex::new(None);
+
+/// Recognizes text with Google Cloud Vision.
+pub struct GoogleVision {
+    api_key: String,
+    interval: Duration,
+    image: Vec<u8>,
+    /// Each word of the last recognized image, with its confidence.
+    words: Option<Vec<(String, f32)>>,
+}
+
+impl GoogleVision {
+    /// Set up the engine with the API key and rate limit from the options.
+    pub fn new(opt: &OcrOpt) -> Result<Self> {
+        let api_key = opt
+            .api_key
+            .clone()
+            .or_else(|| env::var("GOOGLE_API_KEY").ok())
+            .context(MissingApiKeySnafu {})?;
+        Ok(Self {
+            api_key,
+            interval: Duration::from_secs_f64(1.0 / opt.requests_per_second),
+            image: Vec::new(),
+            words: None,
+        })
+    }
+
+    /// Send the image to be recognized, unless it already has been.
+    fn recognize(&mut self) -> Result<&[(String, f32)]> {
+        if self.words.is_none() {
+            wait_for_turn(self.interval);
+            let request = json!({
+                "requests": [{
+                    "image": { "content": STANDARD.encode(&self.image) },
+                    "features": [{ "type": "DOCUMENT_TEXT_DETECTION" }],
+                }]
+            });
+            let response: Value = ureq::post(ENDPOINT)
+                .query("key", &self.api_key)
+                .send_json(request)
+                .map_err(BoxError::from)
+                .and_then(|response| response.into_json().map_err(BoxError::from))
+                .context(EngineSnafu {})?;
+            if let Some(message) = response.pointer("/responses/0/error/message") {
+                let message = message.as_str().unwrap_or_default();
+                return Err(BoxError::from(message)).context(EngineSnafu {});
+            }
+            self.words = Some(words(&response));
+        }
+        Ok(self.words.as_deref().unwrap_or_default())
+    }
+}
+
+impl OcrEngine for GoogleVision {
+    fn set_image(&mut self, image: GrayImage, _dpi: i32) -> Result<()> {
+        let mut png = Cursor::new(Vec::new());
+        DynamicImage::ImageLuma8(image)
+            .write_to(&mut png, ImageOutputFormat::Png)
+            .context(WriteImageSnafu {})?;
+        self.image = png.into_inner();
+        self.words = None;
+        Ok(())
+    }
+
+    fn get_text(&mut self) -> Result<String> {
+        let words = self.recognize()?;
+        Ok(words
+            .iter()
+            .map(|(word, _)| word.as_str())
+            .collect::<Vec<_>>()
+            .join(" "))
+    }
+
+    fn confidence(&self) -> i32 {
+        match self.words.as_deref() {
+            Some(words) if !words.is_empty() => {
+                let total: f32 = words.iter().map(|&(_, confidence)| confidence).sum();
+                (total / words.len() as f32).round() as i32
+            }
+            _ => 0,
+        }
+    }
+
+    fn words(&mut self) -> Result<Vec<(String, f32)>> {
+        self.recognize().map(<[_]>::to_vec)
+    }
+}
+
+/// Wait until a request may be sent, and reserve the next slot.
+fn wait_for_turn(interval: Duration) {
+    let wait = {
+        let mut next_request = NEXT_REQUEST.lock().unwrap();
+        let now = Instant::now();
+        let start = next_request.map_or(now, |next| next.max(now));
+        *next_request = Some(start + interval);
+        start - now
+    };
+    std::thread::sleep(wait);
+}
+
+/// Read the words out of an annotation response, with their confidence scaled
+/// from 0 to 100. Vision gives the text of each symbol in a word separately.
+fn words(response: &Value) -> Vec<(String, f32)> {
+    let pages = response
+        .pointer("/responses/0/fullTextAnnotation/pages")
+        .and_then(Value::as_array);
+    let mut words = Vec::new();
+    for page in pages.into_iter().flatten() {
+        let blocks = page["blocks"].as_array().into_iter().flatten();
+        let paragraphs =
+            blocks.flat_map(|block| block["paragraphs"].as_array().into_iter().flatten());
+        for word in paragraphs.flat_map(|p| p["words"].as_array().into_iter().flatten()) {
+            let text: String = word["symbols"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|symbol| symbol["text"].as_str())
+                .collect();
+            let confidence = word["confidence"].as_f64().unwrap_or(0.0) as f32 * 100.0;
+            words.push((text, confidence));
+        }
+    }
+    words
+}
@@ -0,0 +1,62 @@
+//! Transparent decompression of gzip, zstd and xz compressed inputs.
+//!
+//! The compression format is detected from the file's magic number rather
+//! than its extension, but the extension is still used to find out what kind
+//! of file is inside, e.g. `movie.sub.zst` is read as a `.sub` file.
+
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader},
+    path::{Path, PathBuf},
+};
+
+/// Extensions of compressed files.
+const EXTENSIONS: &[&str] = &["gz", "zst", "xz"];
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+const XZ_MAGIC: &[u8] = &[0xfd, 0x37, 0x7a, 0x58, 0x5a, 0x00];
+
+/// Open a file for reading, decompressing it if necessary.
+pub fn open(path: impl AsRef<Path>) -> io::Result<Box<dyn BufRead + Send>> {
+    decompress(BufReader::new(File::open(path)?))
+}
+
+/// Wrap a reader in a decoder if its contents are compressed.
+pub fn decompress(
+    mut reader: impl BufRead + Send + 'static,
+) -> io::Result<Box<dyn BufRead + Send>> {
+    let magic = reader.fill_buf()?;
+    Ok(if magic.starts_with(GZIP_MAGIC) {
+        Box::new(BufReader::new(flate2::bufread::MultiGzDecoder::new(reader)))
+    } else if magic.starts_with(ZSTD_MAGIC) {
+        Box::new(BufReader::new(zstd::Decoder::with_buffer(reader)?))
+    } else if magic.starts_with(XZ_MAGIC) {
+        Box::new(BufReader::new(xz2::bufread::XzDecoder::new(reader)))
+    } else {
+        Box::new(reader)
+    })
+}
+
+/// Remove a compression extension from the path, if it has one.
+pub fn strip_extension(path: &Path) -> PathBuf {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some(ext) if EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()) => {
+            path.with_extension("")
+        }
+        _ => path.to_owned(),
+    }
+}
+
+/// Find a file, or a compressed version of it.
+pub fn find(path: &Path) -> Option<PathBuf> {
+    if path.is_file() {
+        return Some(path.to_owned());
+    }
+    EXTENSIONS.iter().find_map(|ext| {
+        let mut compressed = path.as_os_str().to_owned();
+        compressed.push(".");
+        compressed.push(ext);
+        Some(PathBuf::from(compressed)).filter(|p| p.is_file())
+    })
+}
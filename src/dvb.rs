@@ -0,0 +1,908 @@
+//! Decoding of DVB bitmap subtitles (ETSI EN 300 743) carried in MPEG
+//! transport streams, such as broadcast recordings.
+//!
+//! Each displayed page is composed from its regions into a single paletted
+//! bitmap, so it can go through the same binarization path as VobSub
+//! subtitles.
+
+use std::{collections::HashMap, io, path::Path};
+
+use crate::{compression, ifo::ycrcb_to_rgb, opt::StreamSelector, preprocessor::SubtitleBitmap};
+use image::Rgba;
+use log::warn;
+use snafu::{OptionExt, ResultExt, Snafu};
+use subparse::timetypes::{TimePoint, TimeSpan};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Could not read transport stream: {}", source))]
+    Io { source: io::Error },
+
+    #[snafu(display("Not an MPEG transport stream"))]
+    NotTransportStream,
+
+    #[snafu(display("Transport stream has no DVB subtitle stream matching {}", stream))]
+    StreamNotFound { stream: StreamSelector },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+const TS_PACKET_SIZE: usize = 188;
+
+/// PTS and PCR values are 33-bit counters of a 90 kHz clock.
+const CLOCK_MASK: u64 = (1 << 33) - 1;
+
+/// A DVB subtitle service announced in a program map table.
+#[derive(Debug, Clone)]
+struct SubtitleService {
+    pid: u16,
+    pcr_pid: u16,
+    /// ISO 639-2 language code.
+    language: String,
+    composition_page: u16,
+    ancillary_page: u16,
+}
+
+/// Decode the selected DVB subtitle stream of a transport stream. If no
+/// stream is selected, the first one is used.
+pub fn read_ts(
+    path: impl AsRef<Path>,
+    stream: Option<&StreamSelector>,
+) -> Result<Vec<SubtitleBitmap>> {
+    let mut reader = compression::open(path).context(IoSnafu {})?;
+
+    // M2TS files prefix every packet with a 4-byte timecode.
+    let mut probe = [0u8; 5];
+    reader.read_exact(&mut probe).context(IoSnafu {})?;
+    let prefix_len = match probe {
+        [0x47, ..] => 0,
+        [_, _, _, _, 0x47] => 4,
+        _ => return NotTransportStreamSnafu {}.fail(),
+    };
+    let mut packet = vec![0u8; prefix_len + TS_PACKET_SIZE];
+    packet[..5].copy_from_slice(&probe);
+    let mut filled = 5;
+
+    let mut pmt_pids: Vec<u16> = Vec::new();
+    let mut parsed_pmt_pids: Vec<u16> = Vec::new();
+    let mut services: Vec<SubtitleService> = Vec::new();
+    let mut selected: Option<SubtitleService> = None;
+    let mut base_time: Option<u64> = None;
+    let mut pes: Vec<u8> = Vec::new();
+    let mut decoder = Decoder::default();
+
+    loop {
+        match reader.read_exact(&mut packet[filled..]) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e).context(IoSnafu {}),
+        }
+        filled = 0;
+        let ts = match parse_ts_packet(&packet[prefix_len..]) {
+            Some(ts) => ts,
+            None => continue,
+        };
+
+        match &selected {
+            None => {
+                // Still discovering the program structure.
+                if ts.pid == 0 && ts.payload_unit_start {
+                    if let Some(pids) = parse_pat(ts.payload) {
+                        pmt_pids = pids;
+                    }
+                } else if pmt_pids.contains(&ts.pid)
+                    && !parsed_pmt_pids.contains(&ts.pid)
+                    && ts.payload_unit_start
+                {
+                    if let Some(found) = parse_pmt(ts.payload) {
+                        parsed_pmt_pids.push(ts.pid);
+                        services.extend(found);
+                    }
+                }
+                if !pmt_pids.is_empty() && parsed_pmt_pids.len() == pmt_pids.len() {
+                    selected = Some(select_service(&services, stream)?.clone());
+                    let service = selected.as_ref().unwrap();
+                    decoder.composition_page = service.composition_page;
+                    decoder.ancillary_page = service.ancillary_page;
+                }
+            }
+            Some(service) => {
+                if base_time.is_none() && ts.pid == service.pcr_pid {
+                    base_time = ts.adaptation.and_then(parse_pcr);
+                }
+                if ts.pid == service.pid {
+                    if ts.payload_unit_start {
+                        if !pes.is_empty() {
+                            decoder.decode_pes(&pes, base_time);
+                        }
+                        pes.clear();
+                        pes.extend_from_slice(ts.payload);
+                    } else if !pes.is_empty() {
+                        pes.extend_from_slice(ts.payload);
+                    }
+                }
+            }
+        }
+    }
+
+    if selected.is_none() {
+        return StreamNotFoundSnafu {
+            stream: stream.cloned().unwrap_or(StreamSelector::Index(0)),
+        }
+        .fail();
+    }
+    if !pes.is_empty() {
+        decoder.decode_pes(&pes, base_time);
+    }
+    decoder.finish();
+    Ok(decoder.bitmaps)
+}
+
+fn select_service<'a>(
+    services: &'a [SubtitleService],
+    stream: Option<&StreamSelector>,
+) -> Result<&'a SubtitleService> {
+    let service = match stream {
+        None => services.first(),
+        Some(StreamSelector::Index(i)) => services.get(*i),
+        Some(StreamSelector::Language(lang)) => services
+            .iter()
+            .find(|s| s.language.eq_ignore_ascii_case(lang)),
+    };
+    service.context(StreamNotFoundSnafu {
+        stream: stream.cloned().unwrap_or(StreamSelector::Index(0)),
+    })
+}
+
+struct TsPacket<'a> {
+    pid: u16,
+    payload_unit_start: bool,
+    adaptation: Option<&'a [u8]>,
+    payload: &'a [u8],
+}
+
+fn parse_ts_packet(packet: &[u8]) -> Option<TsPacket<'_>> {
+    if packet[0] != 0x47 {
+        return None;
+    }
+    let payload_unit_start = packet[1] & 0x40 != 0;
+    let pid = u16::from(packet[1] & 0x1f) << 8 | u16::from(packet[2]);
+    let adaptation_field_control = (packet[3] >> 4) & 0x03;
+    let mut offset = 4;
+    let mut adaptation = None;
+    if adaptation_field_control & 0x02 != 0 {
+        let length = *packet.get(4)? as usize;
+        adaptation = packet.get(5..5 + length);
+        offset += 1 + length;
+    }
+    let payload = if adaptation_field_control & 0x01 != 0 {
+        packet.get(offset..)?
+    } else {
+        &[]
+    };
+    Some(TsPacket {
+        pid,
+        payload_unit_start,
+        adaptation,
+        payload,
+    })
+}
+
+/// Extract the program clock reference base from an adaptation field.
+fn parse_pcr(adaptation: &[u8]) -> Option<u64> {
+    if adaptation.len() < 7 || adaptation[0] & 0x10 == 0 {
+        return None;
+    }
+    Some(
+        u64::from(adaptation[1]) << 25
+            | u64::from(adaptation[2]) << 17
+            | u64::from(adaptation[3]) << 9
+            | u64::from(adaptation[4]) << 1
+            | u64::from(adaptation[5]) >> 7,
+    )
+}
+
+/// Return the body of a PSI section starting in this payload, excluding the
+/// CRC. Sections spanning several packets are not supported, but PATs and PMTs
+/// practically always fit in one.
+fn psi_section(payload: &[u8], table_id: u8) -> Option<&[u8]> {
+    let pointer = *payload.first()? as usize;
+    let section = payload.get(1 + pointer..)?;
+    if section.len() < 3 || section[0] != table_id {
+        return None;
+    }
+    let length = (usize::from(section[1] & 0x0f) << 8 | usize::from(section[2])).checked_sub(4)?;
+    section.get(..3 + length)
+}
+
+/// Return the PIDs of all program map tables listed in a program association
+/// table.
+fn parse_pat(payload: &[u8]) -> Option<Vec<u16>> {
+    let section = psi_section(payload, 0x00)?;
+    Some(
+        section
+            .get(8..)?
+            .chunks_exact(4)
+            .filter(|entry| entry[0] != 0 || entry[1] != 0) // Skip the NIT.
+            .map(|entry| u16::from(entry[2] & 0x1f) << 8 | u16::from(entry[3]))
+            .collect(),
+    )
+}
+
+/// Return all DVB subtitle services listed in a program map table.
+fn parse_pmt(payload: &[u8]) -> Option<Vec<SubtitleService>> {
+    let section = psi_section(payload, 0x02)?;
+    let pcr_pid = u16::from(*section.get(8)? & 0x1f) << 8 | u16::from(*section.get(9)?);
+    let program_info_length =
+        usize::from(*section.get(10)? & 0x0f) << 8 | usize::from(*section.get(11)?);
+
+    let mut services = Vec::new();
+    let mut i = 12 + program_info_length;
+    while i + 5 <= section.len() {
+        let pid = u16::from(section[i + 1] & 0x1f) << 8 | u16::from(section[i + 2]);
+        let es_info_length = usize::from(section[i + 3] & 0x0f) << 8 | usize::from(section[i + 4]);
+        let mut descriptors = section.get(i + 5..i + 5 + es_info_length)?;
+        while descriptors.len() >= 2 {
+            let (tag, length) = (descriptors[0], descriptors[1] as usize);
+            let body = descriptors.get(2..2 + length)?;
+            // Subtitling descriptor.
+            if tag == 0x59 {
+                for entry in body.chunks_exact(8) {
+                    services.push(SubtitleService {
+                        pid,
+                        pcr_pid,
+                        language: String::from_utf8_lossy(&entry[0..3]).into_owned(),
+                        composition_page: u16::from(entry[4]) << 8 | u16::from(entry[5]),
+                        ancillary_page: u16::from(entry[6]) << 8 | u16::from(entry[7]),
+                    });
+                }
+            }
+            descriptors = &descriptors[2 + length..];
+        }
+        i += 5 + es_info_length;
+    }
+    Some(services)
+}
+
+fn parse_pts(bytes: &[u8]) -> u64 {
+    u64::from(bytes[0] >> 1 & 0x07) << 30
+        | u64::from(bytes[1]) << 22
+        | u64::from(bytes[2] >> 1) << 15
+        | u64::from(bytes[3]) << 7
+        | u64::from(bytes[4] >> 1)
+}
+
+/// The three lookup tables of a CLUT; entries can be defined separately for
+/// each pixel depth.
+#[derive(Clone)]
+struct Clut {
+    two_bit: [Rgba<u8>; 4],
+    four_bit: [Rgba<u8>; 16],
+    eight_bit: [Rgba<u8>; 256],
+}
+
+impl Default for Clut {
+    /// The default CLUTs from section 10 of the specification. The 8-bit table
+    /// is only approximated by the 4-bit one, since streams that use 8-bit
+    /// regions define their colors in practice.
+    fn default() -> Self {
+        let four_bit: [Rgba<u8>; 16] = std::array::from_fn(|i| {
+            if i == 0 {
+                return Rgba([0, 0, 0, 0]);
+            }
+            let level = if i & 0x08 == 0 { 255 } else { 127 };
+            let channel = |bit: usize| if i & bit != 0 { level } else { 0 };
+            Rgba([channel(0x01), channel(0x02), channel(0x04), 255])
+        });
+        let mut eight_bit = [Rgba([0, 0, 0, 0]); 256];
+        eight_bit[..16].copy_from_slice(&four_bit);
+        Self {
+            two_bit: [
+                Rgba([0, 0, 0, 0]),
+                Rgba([255, 255, 255, 255]),
+                Rgba([0, 0, 0, 255]),
+                Rgba([127, 127, 127, 255]),
+            ],
+            four_bit,
+            eight_bit,
+        }
+    }
+}
+
+impl Clut {
+    fn color(&self, depth: Depth, code: u8) -> Rgba<u8> {
+        match depth {
+            Depth::Two => self.two_bit[code as usize & 0x03],
+            Depth::Four => self.four_bit[code as usize & 0x0f],
+            Depth::Eight => self.eight_bit[code as usize],
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Depth {
+    Two,
+    Four,
+    Eight,
+}
+
+struct ObjectPlacement {
+    object_id: u16,
+    x: usize,
+    y: usize,
+}
+
+struct Region {
+    width: usize,
+    height: usize,
+    depth: Depth,
+    clut_id: u8,
+    /// Pixel codes at the region's depth.
+    pixels: Vec<u8>,
+    objects: Vec<ObjectPlacement>,
+}
+
+struct Page {
+    start: u64,
+    timeout: u64,
+    /// Visible regions and their positions on the page.
+    regions: Vec<(u8, usize, usize)>,
+}
+
+/// A rendered page waiting for the next page to learn its end time.
+struct PendingBitmap {
+    start: u64,
+    timeout: u64,
+    bitmap: SubtitleBitmap,
+}
+
+#[derive(Default)]
+struct Decoder {
+    composition_page: u16,
+    ancillary_page: u16,
+    cluts: HashMap<u8, Clut>,
+    regions: HashMap<u8, Region>,
+    page: Option<Page>,
+    /// Size of the display the page is composed for, if signalled.
+    display_size: Option<(u32, u32)>,
+    pending: Option<PendingBitmap>,
+    bitmaps: Vec<SubtitleBitmap>,
+}
+
+impl Decoder {
+    fn decode_pes(&mut self, pes: &[u8], base_time: Option<u64>) {
+        if pes.len() < 9 || pes[0..4] != [0x00, 0x00, 0x01, 0xbd] {
+            return;
+        }
+        let header_length = pes[8] as usize;
+        let pts = if pes[7] & 0x80 != 0 && header_length >= 5 && pes.len() >= 9 + header_length {
+            parse_pts(&pes[9..14])
+        } else {
+            return;
+        };
+        // Times are relative to the start of the recording.
+        let pts = pts.wrapping_sub(base_time.unwrap_or(0)) & CLOCK_MASK;
+
+        // Skip the data identifier and subtitle stream ID.
+        let mut segments = match pes.get(9 + header_length + 2..) {
+            Some(segments) => segments,
+            None => return,
+        };
+        while segments.len() >= 6 && segments[0] == 0x0f {
+            let segment_type = segments[1];
+            let page_id = u16::from(segments[2]) << 8 | u16::from(segments[3]);
+            let length = usize::from(segments[4]) << 8 | usize::from(segments[5]);
+            let data = match segments.get(6..6 + length) {
+                Some(data) => data,
+                None => {
+                    warn!("Truncated DVB subtitle segment");
+                    return;
+                }
+            };
+            if page_id == self.composition_page || page_id == self.ancillary_page {
+                match segment_type {
+                    0x10 => self.page_composition(data, pts),
+                    0x11 => self.region_composition(data),
+                    0x12 => self.clut_definition(data),
+                    0x13 => self.object_data(data),
+                    0x14 => self.display_definition(data),
+                    0x80 => self.end_of_display_set(),
+                    _ => {}
+                }
+            }
+            segments = &segments[6 + length..];
+        }
+    }
+
+    fn page_composition(&mut self, data: &[u8], pts: u64) {
+        if data.len() < 2 {
+            return;
+        }
+        // Streams don't always mark the end of a display set, so make sure the
+        // previous page has been rendered.
+        self.end_of_display_set();
+        self.close_pending(pts);
+
+        // A mode change starts a new epoch, discarding all decoder state.
+        let page_state = (data[1] >> 2) & 0x03;
+        if page_state == 2 {
+            self.cluts.clear();
+            self.regions.clear();
+        }
+        self.page = Some(Page {
+            start: pts,
+            timeout: u64::from(data[0]) * 90_000,
+            regions: data[2..]
+                .chunks_exact(6)
+                .map(|entry| {
+                    (
+                        entry[0],
+                        usize::from(entry[2]) << 8 | usize::from(entry[3]),
+                        usize::from(entry[4]) << 8 | usize::from(entry[5]),
+                    )
+                })
+                .collect(),
+        });
+    }
+
+    fn region_composition(&mut self, data: &[u8]) {
+        if data.len() < 10 {
+            return;
+        }
+        let region_id = data[0];
+        let fill = data[1] & 0x08 != 0;
+        let width = usize::from(data[2]) << 8 | usize::from(data[3]);
+        let height = usize::from(data[4]) << 8 | usize::from(data[5]);
+        let depth = match (data[6] >> 2) & 0x07 {
+            1 => Depth::Two,
+            2 => Depth::Four,
+            _ => Depth::Eight,
+        };
+        let clut_id = data[7];
+        let background = match depth {
+            Depth::Two => (data[9] >> 2) & 0x03,
+            Depth::Four => data[9] >> 4,
+            Depth::Eight => data[8],
+        };
+
+        let mut objects = Vec::new();
+        let mut i = 10;
+        while i + 6 <= data.len() {
+            let object_type = data[i + 2] >> 6;
+            objects.push(ObjectPlacement {
+                object_id: u16::from(data[i]) << 8 | u16::from(data[i + 1]),
+                x: usize::from(data[i + 2] & 0x0f) << 8 | usize::from(data[i + 3]),
+                y: usize::from(data[i + 4] & 0x0f) << 8 | usize::from(data[i + 5]),
+            });
+            // Character objects carry foreground and background codes.
+            i += if object_type == 1 || object_type == 2 {
+                8
+            } else {
+                6
+            };
+        }
+
+        let region = self.regions.entry(region_id).or_insert_with(|| Region {
+            width,
+            height,
+            depth,
+            clut_id,
+            pixels: vec![background; width * height],
+            objects: Vec::new(),
+        });
+        if region.width != width || region.height != height || region.depth != depth {
+            region.width = width;
+            region.height = height;
+            region.depth = depth;
+            region.pixels = vec![background; width * height];
+        } else if fill {
+            region.pixels.iter_mut().for_each(|p| *p = background);
+        }
+        region.clut_id = clut_id;
+        region.objects = objects;
+    }
+
+    fn clut_definition(&mut self, data: &[u8]) {
+        if data.len() < 2 {
+            return;
+        }
+        let clut = self.cluts.entry(data[0]).or_default();
+        let mut i = 2;
+        while i + 2 <= data.len() {
+            let entry_id = data[i];
+            let flags = data[i + 1];
+            let (y, cr, cb, t) = if flags & 0x01 != 0 {
+                match data.get(i + 2..i + 6) {
+                    Some(v) => {
+                        i += 6;
+                        (v[0], v[1], v[2], v[3])
+                    }
+                    None => return,
+                }
+            } else {
+                match data.get(i + 2..i + 4) {
+                    Some(v) => {
+                        i += 4;
+                        (
+                            v[0] & 0xfc,
+                            (v[0] & 0x03) << 6 | (v[1] & 0xc0) >> 2,
+                            (v[1] & 0x3c) << 2,
+                            (v[1] & 0x03) << 6,
+                        )
+                    }
+                    None => return,
+                }
+            };
+            // A luma of zero signals full transparency.
+            let color = if y == 0 {
+                Rgba([0, 0, 0, 0])
+            } else {
+                let [r, g, b] = ycrcb_to_rgb(y, cr, cb);
+                Rgba([r, g, b, 255 - t])
+            };
+            if flags & 0x80 != 0 {
+                clut.two_bit[entry_id as usize & 0x03] = color;
+            }
+            if flags & 0x40 != 0 {
+                clut.four_bit[entry_id as usize & 0x0f] = color;
+            }
+            if flags & 0x20 != 0 {
+                clut.eight_bit[entry_id as usize] = color;
+            }
+        }
+    }
+
+    fn object_data(&mut self, data: &[u8]) {
+        if data.len() < 7 {
+            return;
+        }
+        let object_id = u16::from(data[0]) << 8 | u16::from(data[1]);
+        let coding_method = (data[2] >> 2) & 0x03;
+        let non_modifying_color = data[2] & 0x02 != 0;
+        // Only bitmap objects are supported; character objects have no pixel
+        // data to OCR.
+        if coding_method != 0 {
+            return;
+        }
+        let top_length = usize::from(data[3]) << 8 | usize::from(data[4]);
+        let bottom_length = usize::from(data[5]) << 8 | usize::from(data[6]);
+        let top = match data.get(7..7 + top_length) {
+            Some(top) => top,
+            None => return,
+        };
+        // An empty bottom field repeats the top field.
+        let bottom = match bottom_length {
+            0 => top,
+            _ => match data.get(7 + top_length..7 + top_length + bottom_length) {
+                Some(bottom) => bottom,
+                None => return,
+            },
+        };
+
+        for region in self.regions.values_mut() {
+            let placements: Vec<(usize, usize)> = region
+                .objects
+                .iter()
+                .filter(|o| o.object_id == object_id)
+                .map(|o| (o.x, o.y))
+                .collect();
+            for (x, y) in placements {
+                draw_field(region, x, y, top, non_modifying_color);
+                draw_field(region, x, y + 1, bottom, non_modifying_color);
+            }
+        }
+    }
+
+    fn display_definition(&mut self, data: &[u8]) {
+        if data.len() < 5 {
+            return;
+        }
+        let width = u32::from(data[1]) << 8 | u32::from(data[2]);
+        let height = u32::from(data[3]) << 8 | u32::from(data[4]);
+        self.display_size = Some((width + 1, height + 1));
+    }
+
+    fn end_of_display_set(&mut self) {
+        let page = match self.page.take() {
+            Some(page) => page,
+            None => return,
+        };
+        self.close_pending(page.start);
+        if let Some(bitmap) = self.render(&page) {
+            self.pending = Some(PendingBitmap {
+                start: page.start,
+                timeout: page.timeout,
+                bitmap,
+            });
+        }
+    }
+
+    /// Compose the visible regions of a page into a single bitmap covering
+    /// their bounding box, or `None` if nothing visible is on the page.
+    fn render(&self, page: &Page) -> Option<SubtitleBitmap> {
+        let placed: Vec<(&Region, usize, usize)> = page
+            .regions
+            .iter()
+            .filter_map(|(id, x, y)| self.regions.get(id).map(|r| (r, *x, *y)))
+            .collect();
+        let left = placed.iter().map(|(_, x, _)| *x).min()?;
+        let top = placed.iter().map(|(_, _, y)| *y).min()?;
+        let right = placed.iter().map(|(r, x, _)| x + r.width).max()?;
+        let bottom = placed.iter().map(|(r, _, y)| y + r.height).max()?;
+        let width = right - left;
+        let height = bottom - top;
+
+        // Entry 0 is reserved for the transparent background. Regions may use
+        // different CLUTs and depths, so colors are merged by value.
+        let mut palette = vec![Rgba([0, 0, 0, 0])];
+        let mut palette_lookup: HashMap<Rgba<u8>, u8> = HashMap::new();
+        let mut pixels = vec![0u8; width * height];
+        let default_clut = Clut::default();
+        let mut visible = false;
+        for (region, x, y) in placed {
+            let clut = self.cluts.get(&region.clut_id).unwrap_or(&default_clut);
+            for (row, codes) in region.pixels.chunks(region.width.max(1)).enumerate() {
+                let offset = (y - top + row) * width + (x - left);
+                for (pixel, &code) in pixels[offset..offset + codes.len()].iter_mut().zip(codes) {
+                    let color = clut.color(region.depth, code);
+                    if color[3] == 0 {
+                        continue;
+                    }
+                    visible = true;
+                    *pixel = *palette_lookup.entry(color).or_insert_with(|| {
+                        if palette.len() < 256 {
+                            palette.push(color);
+                            (palette.len() - 1) as u8
+                        } else {
+                            warn!("DVB subtitle page uses more than 255 colors");
+                            0
+                        }
+                    });
+                }
+            }
+        }
+        if !visible {
+            return None;
+        }
+
+        Some(SubtitleBitmap {
+            // Filled in once the end time is known.
+            time_span: TimeSpan::new(TimePoint::from_msecs(0), TimePoint::from_msecs(0)),
+            force: false,
+            x: left as u32,
+            y: top as u32,
+            // Without a display definition segment the display is SD.
+            screen_size: self.display_size.unwrap_or((720, 576)),
+            width,
+            height,
+            pixels,
+            palette,
+        })
+    }
+
+    /// Emit the pending bitmap, ending it at the given time or its timeout,
+    /// whichever comes first.
+    fn close_pending(&mut self, end: u64) {
+        if let Some(pending) = self.pending.take() {
+            let end = end.min(pending.start + pending.timeout).max(pending.start);
+            let mut bitmap = pending.bitmap;
+            bitmap.time_span =
+                TimeSpan::new(clock_to_time_point(pending.start), clock_to_time_point(end));
+            self.bitmaps.push(bitmap);
+        }
+    }
+
+    fn finish(&mut self) {
+        self.end_of_display_set();
+        self.close_pending(u64::MAX);
+    }
+}
+
+fn clock_to_time_point(ticks: u64) -> TimePoint {
+    TimePoint::from_msecs((ticks / 90) as i64)
+}
+
+/// Decode one field of an object's pixel data into a region, starting at the
+/// given position and advancing two lines per end-of-line code.
+fn draw_field(region: &mut Region, x0: usize, y0: usize, data: &[u8], non_modifying_color: bool) {
+    let mut map_2_to_4: [u8; 4] = [0x0, 0x7, 0x8, 0xf];
+    let mut map_2_to_8: [u8; 4] = [0x00, 0x77, 0x88, 0xff];
+    let mut map_4_to_8: [u8; 16] = std::array::from_fn(|i| (i * 0x11) as u8);
+
+    let (mut x, mut y) = (x0, y0);
+    let mut codes = Vec::new();
+    let mut i = 0;
+    while i < data.len() {
+        let data_type = data[i];
+        i += 1;
+        codes.clear();
+        let source_depth = match data_type {
+            0x10 => {
+                i += decode_2bit_string(&data[i..], &mut codes);
+                Depth::Two
+            }
+            0x11 => {
+                i += decode_4bit_string(&data[i..], &mut codes);
+                Depth::Four
+            }
+            0x12 => {
+                i += decode_8bit_string(&data[i..], &mut codes);
+                Depth::Eight
+            }
+            0x20 => {
+                if let Some(table) = data.get(i..i + 2) {
+                    map_2_to_4 = [
+                        table[0] >> 4,
+                        table[0] & 0x0f,
+                        table[1] >> 4,
+                        table[1] & 0x0f,
+                    ];
+                }
+                i += 2;
+                continue;
+            }
+            0x21 => {
+                if let Some(table) = data.get(i..i + 4) {
+                    map_2_to_8.copy_from_slice(table);
+                }
+                i += 4;
+                continue;
+            }
+            0x22 => {
+                if let Some(table) = data.get(i..i + 16) {
+                    map_4_to_8.copy_from_slice(table);
+                }
+                i += 16;
+                continue;
+            }
+            0xf0 => {
+                x = x0;
+                y += 2;
+                continue;
+            }
+            _ => return,
+        };
+
+        for &code in &codes {
+            let code = match (source_depth, region.depth) {
+                (Depth::Two, Depth::Four) => map_2_to_4[code as usize],
+                (Depth::Two, Depth::Eight) => map_2_to_8[code as usize],
+                (Depth::Four, Depth::Eight) => map_4_to_8[code as usize],
+                (Depth::Four, Depth::Two) => code >> 2,
+                (Depth::Eight, Depth::Two) => code >> 6,
+                (Depth::Eight, Depth::Four) => code >> 4,
+                _ => code,
+            };
+            // The non-modifying color leaves the underlying pixel alone.
+            if x < region.width && y < region.height && !(non_modifying_color && code == 1) {
+                region.pixels[y * region.width + x] = code;
+            }
+            x += 1;
+        }
+    }
+}
+
+/// Reads big-endian bit fields, returning zeros past the end of the data.
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    fn read(&mut self, bits: usize) -> u8 {
+        let mut value = 0u16;
+        for _ in 0..bits {
+            let bit = self
+                .data
+                .get(self.position / 8)
+                .map_or(0, |byte| (byte >> (7 - self.position % 8)) & 1);
+            value = value << 1 | u16::from(bit);
+            self.position += 1;
+        }
+        value as u8
+    }
+
+    fn exhausted(&self) -> bool {
+        self.position >= self.data.len() * 8
+    }
+
+    /// The number of whole bytes consumed so far.
+    fn bytes_consumed(&self) -> usize {
+        self.position.div_ceil(8)
+    }
+}
+
+fn push_run(codes: &mut Vec<u8>, code: u8, run: usize) {
+    codes.resize(codes.len() + run, code);
+}
+
+fn decode_2bit_string(data: &[u8], codes: &mut Vec<u8>) -> usize {
+    let mut bits = BitReader::new(data);
+    while !bits.exhausted() {
+        let code = bits.read(2);
+        if code != 0 {
+            codes.push(code);
+        } else if bits.read(1) == 1 {
+            let run = bits.read(3) as usize + 3;
+            let code = bits.read(2);
+            push_run(codes, code, run);
+        } else if bits.read(1) == 1 {
+            codes.push(0);
+        } else {
+            match bits.read(2) {
+                0 => break,
+                1 => push_run(codes, 0, 2),
+                2 => {
+                    let run = bits.read(4) as usize + 12;
+                    let code = bits.read(2);
+                    push_run(codes, code, run);
+                }
+                _ => {
+                    let run = bits.read(8) as usize + 29;
+                    let code = bits.read(2);
+                    push_run(codes, code, run);
+                }
+            }
+        }
+    }
+    bits.bytes_consumed()
+}
+
+fn decode_4bit_string(data: &[u8], codes: &mut Vec<u8>) -> usize {
+    let mut bits = BitReader::new(data);
+    while !bits.exhausted() {
+        let code = bits.read(4);
+        if code != 0 {
+            codes.push(code);
+        } else if bits.read(1) == 0 {
+            match bits.read(3) {
+                0 => break,
+                run => push_run(codes, 0, run as usize + 2),
+            }
+        } else if bits.read(1) == 0 {
+            let run = bits.read(2) as usize + 4;
+            let code = bits.read(4);
+            push_run(codes, code, run);
+        } else {
+            match bits.read(2) {
+                0 => codes.push(0),
+                1 => push_run(codes, 0, 2),
+                2 => {
+                    let run = bits.read(4) as usize + 9;
+                    let code = bits.read(4);
+                    push_run(codes, code, run);
+                }
+                _ => {
+                    let run = bits.read(8) as usize + 25;
+                    let code = bits.read(4);
+                    push_run(codes, code, run);
+                }
+            }
+        }
+    }
+    bits.bytes_consumed()
+}
+
+fn decode_8bit_string(data: &[u8], codes: &mut Vec<u8>) -> usize {
+    let mut bits = BitReader::new(data);
+    while !bits.exhausted() {
+        let code = bits.read(8);
+        if code != 0 {
+            codes.push(code);
+        } else if bits.read(1) == 0 {
+            match bits.read(7) {
+                0 => break,
+                run => push_run(codes, 0, run as usize),
+            }
+        } else {
+            let run = bits.read(7) as usize;
+            let code = bits.read(8);
+            push_run(codes, code, run);
+        }
+    }
+    bits.bytes_consumed()
+}
@@ -0,0 +1,161 @@
+//! Reading subtitles straight out of a DVD disc image or video objects.
+
+use std::{fs, io::Read, path::Path};
+
+use crate::{
+    ifo,
+    iso9660::{self, IsoImage},
+    mpeg2,
+    opt::StreamSelector,
+    preprocessor::VobSubStream,
+};
+use snafu::{OptionExt, ResultExt, Snafu};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("{}", source))]
+    Iso { source: iso9660::Error },
+
+    #[snafu(display("Could not parse {}: {}", filename, source))]
+    Ifo {
+        filename: String,
+        source: ifo::Error,
+    },
+
+    #[snafu(display("Disc image has no title set {}", title))]
+    TitleNotFound { title: u32 },
+
+    #[snafu(display("Title set {} has no subpicture stream matching {}", title, stream))]
+    StreamNotFound { title: u32, stream: StreamSelector },
+
+    #[snafu(display("No subpicture stream matching {}", stream))]
+    VobStreamNotFound { stream: StreamSelector },
+
+    #[snafu(display("Could not read {}: {}", filename, source))]
+    ReadVob {
+        filename: String,
+        source: std::io::Error,
+    },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Extract a subpicture stream and its palette from a title set of an ISO
+/// image. If no stream is selected, the first one is used.
+pub fn read_iso(
+    path: impl AsRef<Path>,
+    title: u32,
+    stream: Option<&StreamSelector>,
+) -> Result<VobSubStream> {
+    let mut image = IsoImage::open(path).context(IsoSnafu {})?;
+    let video_ts = image
+        .find("VIDEO_TS")
+        .context(IsoSnafu {})?
+        .context(TitleNotFoundSnafu { title })?;
+    let files = image.read_dir(&video_ts).context(IsoSnafu {})?;
+
+    let ifo_name = format!("VTS_{:02}_0.IFO", title);
+    let ifo_entry = files
+        .iter()
+        .find(|e| e.name.eq_ignore_ascii_case(&ifo_name))
+        .context(TitleNotFoundSnafu { title })?;
+    let mut ifo_data = Vec::new();
+    image
+        .open_file(ifo_entry)
+        .context(IsoSnafu {})?
+        .read_to_end(&mut ifo_data)
+        .context(ReadVobSnafu {
+            filename: ifo_name.clone(),
+        })?;
+    let vts = ifo::parse_vts_ifo(&ifo_data).context(IfoSnafu { filename: ifo_name })?;
+
+    let selected = select_stream(&vts, stream).context(StreamNotFoundSnafu {
+        title,
+        stream: stream.cloned().unwrap_or(StreamSelector::Index(0)),
+    })?;
+
+    // The title's video is split across VTS_xx_1.VOB, VTS_xx_2.VOB, ...;
+    // VTS_xx_0.VOB is the menu and is skipped.
+    let mut data = Vec::new();
+    for n in 1..=9 {
+        let vob_name = format!("VTS_{:02}_{}.VOB", title, n);
+        let vob_entry = match files
+            .iter()
+            .find(|e| e.name.eq_ignore_ascii_case(&vob_name))
+        {
+            Some(entry) => entry,
+            None => break,
+        };
+        let reader = image.open_file(vob_entry).context(IsoSnafu {})?;
+        mpeg2::extract_subpicture_stream(reader, selected.physical_stream, &mut data)
+            .context(ReadVobSnafu { filename: vob_name })?;
+    }
+
+    Ok(VobSubStream {
+        palette: Some(vts.palette),
+        screen_size: Some(vts.screen_size),
+        language: selected.language.clone(),
+        data,
+    })
+}
+
+/// Read the IFO file of a title set.
+pub fn read_ifo(path: impl AsRef<Path>) -> Result<ifo::VideoTitleSet> {
+    let path = path.as_ref();
+    let filename = path.display().to_string();
+    let data = fs::read(path).context(ReadVobSnafu {
+        filename: filename.clone(),
+    })?;
+    ifo::parse_vts_ifo(&data).context(IfoSnafu { filename })
+}
+
+/// Extract a subpicture stream from a VOB file. Streams are looked up in the
+/// title set's IFO if there is one; otherwise they can only be selected by
+/// their physical stream number, and stream 0 is used by default.
+pub fn read_vob(
+    path: impl AsRef<Path>,
+    vts: Option<&ifo::VideoTitleSet>,
+    stream: Option<&StreamSelector>,
+) -> Result<VobSubStream> {
+    let path = path.as_ref();
+    let selected = vts.and_then(|vts| select_stream(vts, stream));
+    let physical_stream = match (vts, stream) {
+        (Some(_), _) => selected.map(|s| s.physical_stream),
+        (None, None) => Some(0),
+        (None, Some(StreamSelector::Index(i))) => Some(*i as u8).filter(|&i| i < 32),
+        (None, Some(StreamSelector::Language(_))) => None,
+    }
+    .context(VobStreamNotFoundSnafu {
+        stream: stream.cloned().unwrap_or(StreamSelector::Index(0)),
+    })?;
+
+    let filename = path.display().to_string();
+    let file = fs::File::open(path).context(ReadVobSnafu {
+        filename: filename.clone(),
+    })?;
+    let mut data = Vec::new();
+    mpeg2::extract_subpicture_stream(std::io::BufReader::new(file), physical_stream, &mut data)
+        .context(ReadVobSnafu { filename })?;
+
+    Ok(VobSubStream {
+        palette: vts.map(|vts| vts.palette),
+        screen_size: vts.map(|vts| vts.screen_size),
+        language: selected.and_then(|s| s.language.clone()),
+        data,
+    })
+}
+
+/// Pick a subpicture stream from the IFO, defaulting to the first.
+fn select_stream<'a>(
+    vts: &'a ifo::VideoTitleSet,
+    stream: Option<&StreamSelector>,
+) -> Option<&'a ifo::SubpictureStream> {
+    match stream {
+        None => vts.subpicture_streams.first(),
+        Some(StreamSelector::Index(i)) => vts.subpicture_streams.get(*i),
+        Some(StreamSelector::Language(lang)) => vts
+            .subpicture_streams
+            .iter()
+            .find(|s| matches!(&s.language, Some(l) if l.eq_ignore_ascii_case(lang))),
+    }
+}
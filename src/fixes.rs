@@ -0,0 +1,362 @@
+//! Fixing common OCR mistakes in the recognized text, with the built-in
+//! tables for `--fix-ocr-errors`, the heuristics for `--fix-confusions` and
+//! SubtitleEdit's OCR fix lists for `--fix-list`.
+//!
+//! SubtitleEdit's lists, like `eng_OCRFixReplaceList.xml`, are XML files with
+//! sections of `from` and `to` pairs. Its partial words are only fixed when
+//! they aren't in its spelling dictionary, so they are skipped, as are regular
+//! expressions using syntax .NET has but the `regex` crate doesn't, like
+//! lookarounds.
+
+use crate::opt::Confusion;
+use clap::ValueEnum;
+use std::{fs, io, path::PathBuf};
+
+use log::warn;
+use regex::Regex;
+use snafu::{ResultExt, Snafu};
+
+/// Words which OCR reads `I` and `l` the wrong way round in, or `m` as `rn`,
+/// for each language.
+const ENGLISH: &[(&str, &str)] = &[
+    ("l", "I"),
+    ("l'm", "I'm"),
+    ("l'll", "I'll"),
+    ("l've", "I've"),
+    ("l'd", "I'd"),
+    ("Im", "I'm"),
+    ("Iam", "I am"),
+    ("lt", "It"),
+    ("lt's", "It's"),
+    ("lf", "If"),
+    ("ls", "Is"),
+    ("lsn't", "Isn't"),
+    ("ln", "In"),
+    ("Iike", "like"),
+    ("Iook", "look"),
+    ("Iet", "let"),
+    ("Iet's", "let's"),
+    ("aII", "all"),
+    ("caII", "call"),
+    ("weII", "well"),
+    ("teII", "tell"),
+    ("wiII", "will"),
+    ("stiII", "still"),
+    ("rne", "me"),
+    ("rny", "my"),
+    ("rnan", "man"),
+    ("rnore", "more"),
+    ("frorn", "from"),
+    ("sorne", "some"),
+    ("corne", "come"),
+    ("tirne", "time"),
+    ("narne", "name"),
+    ("horne", "home"),
+];
+
+const FRENCH: &[(&str, &str)] = &[
+    ("ll", "Il"),
+    ("lls", "Ils"),
+    ("Ia", "la"),
+    ("Ie", "le"),
+    ("Ies", "les"),
+    ("Ià", "là"),
+    ("Iui", "lui"),
+    ("Ieur", "leur"),
+];
+
+const GERMAN: &[(&str, &str)] = &[
+    ("lch", "Ich"),
+    ("lhr", "Ihr"),
+    ("lhn", "Ihn"),
+    ("lhm", "Ihm"),
+    ("lhnen", "Ihnen"),
+    ("lst", "Ist"),
+    ("ln", "In"),
+];
+
+const SPANISH: &[(&str, &str)] = &[
+    ("Io", "lo"),
+    ("Ia", "la"),
+    ("Ios", "los"),
+    ("Ias", "las"),
+    ("eI", "el"),
+    ("deI", "del"),
+    ("aI", "al"),
+];
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Could not read OCR fix list {}: {}", filename.display(), source))]
+    Read {
+        filename: PathBuf,
+        source: io::Error,
+    },
+
+    #[snafu(display("Could not parse OCR fix list {}: {}", filename.display(), source))]
+    Xml {
+        filename: PathBuf,
+        source: roxmltree::Error,
+    },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[derive(Debug, Clone)]
+enum Fix {
+    /// Replace whole words, or runs of them.
+    Words(String, String),
+    /// Replace text wherever it appears.
+    Always(String, String),
+    /// Replace the start of a line.
+    Beginning(String, String),
+    /// Replace the end of a line.
+    Ending(String, String),
+    /// Replace a whole line.
+    Line(String, String),
+    Pattern(Regex, String),
+    /// Fix characters OCR confuses with each other, judging from the
+    /// characters around them.
+    Confusion(Confusion),
+}
+
+/// Fixes applied in order to each line of text.
+#[derive(Debug, Clone)]
+pub struct FixList(Vec<Fix>);
+
+impl FixList {
+    /// The built-in fixes for the OCR languages, warning about languages
+    /// without a table of words.
+    pub fn builtin(languages: &[String]) -> Self {
+        let mut fixes = vec![Fix::Confusion(Confusion::Zeros)];
+        for language in languages {
+            let words = match language.as_str() {
+                "eng" => ENGLISH,
+                "fra" => FRENCH,
+                "deu" => GERMAN,
+                "spa" => SPANISH,
+                _ => {
+                    warn!("No built-in OCR fixes for the words of `{}`", language);
+                    continue;
+                }
+            };
+            fixes.extend(
+                words
+                    .iter()
+                    .map(|&(from, to)| Fix::Words(from.to_owned(), to.to_owned())),
+            );
+        }
+        Self(fixes)
+    }
+
+    /// Fixes for each kind of confused characters, in an order where earlier
+    /// fixes don't get in the way of later ones.
+    pub fn confusions(kinds: &[Confusion]) -> Self {
+        Self(
+            Confusion::value_variants()
+                .iter()
+                .filter(|kind| kinds.contains(kind))
+                .map(|&kind| Fix::Confusion(kind))
+                .collect(),
+        )
+    }
+
+    /// Read a SubtitleEdit OCR fix list.
+    pub fn read(filename: &str) -> Result<Self> {
+        let xml = fs::read_to_string(filename).context(ReadSnafu { filename })?;
+        let document = roxmltree::Document::parse(&xml).context(XmlSnafu { filename })?;
+        let mut fixes = Vec::new();
+        let mut unsupported = 0;
+        for section in document
+            .root_element()
+            .children()
+            .filter(|n| n.is_element())
+        {
+            for entry in section.children().filter(|n| n.is_element()) {
+                // Skip entries with nothing to replace, which would match
+                // everywhere.
+                let pair = |from, to| match (entry.attribute(from), entry.attribute(to)) {
+                    (Some(from), Some(to)) if !from.is_empty() => {
+                        Some((from.to_owned(), to.to_owned()))
+                    }
+                    _ => None,
+                };
+                let fix = match section.tag_name().name() {
+                    "WholeWords" | "PartialLines" => {
+                        pair("from", "to").map(|(from, to)| Fix::Words(from, to))
+                    }
+                    "PartialWordsAlways" | "PartialLinesAlways" => {
+                        pair("from", "to").map(|(from, to)| Fix::Always(from, to))
+                    }
+                    "BeginLines" => pair("from", "to").map(|(from, to)| Fix::Beginning(from, to)),
+                    "EndLines" => pair("from", "to").map(|(from, to)| Fix::Ending(from, to)),
+                    "WholeLines" => pair("from", "to").map(|(from, to)| Fix::Line(from, to)),
+                    "RegularExpressions" => {
+                        pair("find", "replaceWith").and_then(|(find, to)| match Regex::new(&find) {
+                            Ok(pattern) => Some(Fix::Pattern(pattern, to)),
+                            Err(_) => {
+                                unsupported += 1;
+                                None
+                            }
+                        })
+                    }
+                    _ => None,
+                };
+                fixes.extend(fix);
+            }
+        }
+        if unsupported > 0 {
+            warn!(
+                "Skipping {} regular expressions in {} which aren't supported",
+                unsupported, filename
+            );
+        }
+        Ok(Self(fixes))
+    }
+
+    /// Apply every fix to a line of text.
+    pub fn apply(&self, line: &str) -> String {
+        self.0.iter().fold(line.to_owned(), |line, fix| match fix {
+            Fix::Words(from, to) => replace_words(&line, from, to),
+            Fix::Always(from, to) => line.replace(from.as_str(), to),
+            Fix::Beginning(from, to) => match line.strip_prefix(from.as_str()) {
+                Some(rest) => format!("{}{}", to, rest),
+                None => line,
+            },
+            Fix::Ending(from, to) => match line.strip_suffix(from.as_str()) {
+                Some(rest) => format!("{}{}", rest, to),
+                None => line,
+            },
+            Fix::Line(from, to) if line == *from => to.clone(),
+            Fix::Line(..) => line,
+            Fix::Pattern(pattern, to) => pattern.replace_all(&line, to.as_str()).into_owned(),
+            Fix::Confusion(Confusion::Zeros) => replace_zeros(&line),
+            Fix::Confusion(kind) => map_words(&line, |word| fix_confusion(word, *kind)),
+        })
+    }
+}
+
+/// Replace `from` where it isn't part of a longer word.
+fn replace_words(line: &str, from: &str, to: &str) -> String {
+    let mut fixed = String::with_capacity(line.len());
+    let mut copied = 0;
+    for (start, _) in line.match_indices(from) {
+        let end = start + from.len();
+        let before = line[..start].chars().next_back();
+        let after = line[end..].chars().next();
+        if matches!(before, Some(c) if c.is_alphanumeric())
+            || matches!(after, Some(c) if c.is_alphanumeric())
+        {
+            continue;
+        }
+        fixed.push_str(&line[copied..start]);
+        fixed.push_str(to);
+        copied = end;
+    }
+    fixed.push_str(&line[copied..]);
+    fixed
+}
+
+/// Replace zeros in words of letters with an uppercase O in words of capitals,
+/// and a lowercase one otherwise, leaving numbers alone.
+fn replace_zeros(line: &str) -> String {
+    let chars: Vec<char> = line.chars().collect();
+    let mut fixed = String::with_capacity(line.len());
+    let mut start = 0;
+    while start < chars.len() {
+        let end = start
+            + chars[start..]
+                .iter()
+                .position(|&c| c.is_alphanumeric() != chars[start].is_alphanumeric())
+                .unwrap_or(chars.len() - start);
+        let word = &chars[start..end];
+        let letters = word.iter().filter(|c| c.is_alphabetic());
+        let is_word = word.contains(&'0')
+            && letters.clone().next().is_some()
+            && word.iter().all(|&c| c.is_alphabetic() || c == '0');
+        if is_word {
+            let zero = if letters.clone().all(|c| c.is_uppercase()) {
+                'O'
+            } else {
+                'o'
+            };
+            fixed.extend(word.iter().map(|&c| if c == '0' { zero } else { c }));
+        } else {
+            fixed.extend(word);
+        }
+        start = end;
+    }
+    fixed
+}
+
+/// Fix a word with a kind of confused characters other than zeros.
+fn fix_confusion(word: &mut [char], kind: Confusion) {
+    match kind {
+        Confusion::Pipes => {
+            for i in 0..word.len() {
+                if word[i] == '|' {
+                    let after_lowercase = i > 0 && word[i - 1].is_lowercase();
+                    word[i] = if after_lowercase { 'l' } else { 'I' };
+                }
+            }
+        }
+        Confusion::Numbers => {
+            let is_number = word.iter().any(|c| c.is_ascii_digit())
+                && word
+                    .iter()
+                    .all(|&c| c.is_ascii_digit() || "OoIl".contains(c));
+            if is_number {
+                for c in word {
+                    *c = match *c {
+                        'O' | 'o' => '0',
+                        'I' | 'l' => '1',
+                        c => c,
+                    };
+                }
+            }
+        }
+        Confusion::InitialL => {
+            // No word starts with `l` and a consonant other than `l` or `y`,
+            // and English contractions of `I` never mean `l'`.
+            let is_i = match word {
+                ['l'] => true,
+                ['l', '\'' | '’', rest @ ..] => {
+                    matches!(rest, ['m'] | ['l', 'l'] | ['v', 'e'] | ['d'])
+                }
+                ['l', next, ..] => next.is_ascii_lowercase() && !"aeiouly".contains(*next),
+                _ => false,
+            };
+            if is_i {
+                word[0] = 'I';
+            }
+        }
+        Confusion::MedialI => {
+            for i in 1..word.len() {
+                if word[i] == 'I' && word[i - 1].is_lowercase() {
+                    word[i] = 'l';
+                }
+            }
+        }
+        Confusion::Zeros => {}
+    }
+}
+
+/// Apply `fix` to each word of a line, taking pipes and apostrophes as part of
+/// words.
+fn map_words(line: &str, fix: impl Fn(&mut [char])) -> String {
+    let is_word = |c: char| c.is_alphanumeric() || matches!(c, '|' | '\'' | '’');
+    let mut chars: Vec<char> = line.chars().collect();
+    let mut start = 0;
+    while start < chars.len() {
+        let end = start
+            + chars[start..]
+                .iter()
+                .position(|&c| is_word(c) != is_word(chars[start]))
+                .unwrap_or(chars.len() - start);
+        if is_word(chars[start]) {
+            fix(&mut chars[start..end]);
+        }
+        start = end;
+    }
+    chars.into_iter().collect()
+}
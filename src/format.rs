@@ -0,0 +1,21 @@
+//! Formatting times and colors the same way in every report and output which
+//! shows them.
+
+use subparse::timetypes::TimePoint;
+
+/// Format a time as `HH:MM:SS.mmm`.
+pub fn format_time(time: TimePoint) -> String {
+    let msecs = time.msecs().max(0);
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        msecs / 3_600_000,
+        msecs / 60_000 % 60,
+        msecs / 1000 % 60,
+        msecs % 1000
+    )
+}
+
+/// Format a color as hex RGB, like `ff8000`.
+pub fn hex_color([r, g, b]: [u8; 3]) -> String {
+    format!("{:02x}{:02x}{:02x}", r, g, b)
+}
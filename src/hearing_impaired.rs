@@ -0,0 +1,59 @@
+//! Removing the annotations of subtitles for the deaf and hard of hearing,
+//! for `--remove-hi`: sounds in brackets or parentheses, like `[door slams]`
+//! or `(SIGHS)`, and speaker labels, like `JOHN:`.
+
+use crate::{ocr::Subtitle, punctuation::DASHES};
+use log::info;
+use regex::Regex;
+
+/// Remove the annotations from each subtitle, dropping the lines they leave
+/// without text and the subtitles left without lines.
+pub fn remove(subtitles: Vec<Subtitle>) -> Vec<Subtitle> {
+    // A label in capitals at the start of a line, after any dialogue dash.
+    let label = Regex::new(r"^([-–—]\s*)?\p{Lu}[\p{Lu}\d .'’&-]*:(\s+|$)").unwrap();
+    subtitles
+        .into_iter()
+        .filter_map(|mut subtitle| {
+            let lines = subtitle.lines.len();
+            let mut closing = None;
+            subtitle.lines.retain_mut(|line| {
+                let text = remove_brackets(&line.text, &mut closing);
+                let text = label.replace(&text, "$1");
+                if text == line.text {
+                    return true;
+                }
+                line.text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+                line.text.chars().any(char::is_alphanumeric)
+            });
+            // A dash is only needed to tell several speakers apart.
+            if subtitle.lines.len() == 1 && lines > 1 {
+                let line = &mut subtitle.lines[0];
+                line.text = line.text.trim_start_matches(DASHES).trim_start().to_owned();
+            }
+            if subtitle.lines.is_empty() {
+                info!(
+                    "Dropping subtitle at {} left empty by removing annotations",
+                    subtitle.time_span.start
+                );
+                return None;
+            }
+            Some(subtitle)
+        })
+        .collect()
+}
+
+/// Remove text in brackets or parentheses from a line, where `closing` is the
+/// bracket which closes one left open on a previous line.
+fn remove_brackets(line: &str, closing: &mut Option<char>) -> String {
+    let mut kept = String::with_capacity(line.len());
+    for c in line.chars() {
+        match (*closing, c) {
+            (Some(close), c) if c == close => *closing = None,
+            (Some(_), _) => {}
+            (None, '[') => *closing = Some(']'),
+            (None, '(') => *closing = Some(')'),
+            (None, c) => kept.push(c),
+        }
+    }
+    kept
+}
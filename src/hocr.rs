@@ -0,0 +1,105 @@
+//! Writing hOCR, Tesseract's HTML format for OCR results with the bounding
+//! box of every line and word.
+//!
+//! Tesseract produces a page for each line image. Those are unwrapped and
+//! moved to where the line was on the screen, and every subtitle becomes a
+//! page of its own, with the subtitle's times as `x_start` and `x_end`
+//! properties in milliseconds.
+
+use std::fmt::Write;
+
+use crate::{
+    ocr::Subtitle,
+    writer::{Result, SubtitleWriter},
+};
+
+const HEADER: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE html PUBLIC "-//W3C//DTD XHTML 1.0 Transitional//EN"
+    "http://www.w3.org/TR/xhtml1/DTD/xhtml1-transitional.dtd">
+<html xmlns="http://www.w3.org/1999/xhtml" xml:lang="en" lang="en">
+ <head>
+  <title></title>
+  <meta http-equiv="Content-Type" content="text/html;charset=utf-8"/>
+  <meta name="ocr-system" content="vobsubocr"/>
+  <meta name="ocr-capabilities" content="ocr_page ocr_carea ocr_par ocr_line ocrx_word"/>
+ </head>
+ <body>
+"#;
+
+const FOOTER: &str = " </body>\n</html>\n";
+
+/// Take the contents of a page of Tesseract's hOCR for a line image whose top
+/// left corner was at `origin` on the screen, moving its bounding boxes there.
+pub fn place_line(page: &str, origin: (i32, i32)) -> String {
+    let start = page.find('>').map_or(0, |i| i + 1);
+    let end = page.rfind("</div>").filter(|&end| end >= start);
+    shift_bboxes(&page[start..end.unwrap_or(page.len())], origin)
+}
+
+/// Writes subtitles as an hOCR document with a page per subtitle.
+pub struct HocrWriter;
+
+impl SubtitleWriter for HocrWriter {
+    fn write(&self, subtitles: &[Subtitle]) -> Result<Vec<u8>> {
+        let mut html = String::from(HEADER);
+        for (i, subtitle) in subtitles.iter().enumerate() {
+            let placement = &subtitle.placement;
+            writeln!(
+                html,
+                "  <div class='ocr_page' id='page_{}' title='bbox 0 0 {} {}; ppageno {}; \
+                 x_start {}; x_end {}'>",
+                i + 1,
+                placement.screen_width,
+                placement.screen_height,
+                i,
+                subtitle.time_span.start.msecs(),
+                subtitle.time_span.end.msecs(),
+            )
+            .unwrap();
+            for (j, line) in subtitle
+                .lines
+                .iter()
+                .filter_map(|l| l.markup.as_ref())
+                .enumerate()
+            {
+                // Tesseract numbers its elements per page, so make them unique.
+                let prefix = format!("id='s{}_l{}_", i + 1, j + 1);
+                html.push_str(&line.replace("id='", &prefix));
+            }
+            html.push_str("  </div>\n");
+        }
+        html.push_str(FOOTER);
+        Ok(html.into_bytes())
+    }
+}
+
+/// Add an offset to every `bbox x0 y0 x1 y1` property.
+fn shift_bboxes(hocr: &str, (dx, dy): (i32, i32)) -> String {
+    let mut output = String::with_capacity(hocr.len());
+    let mut rest = hocr;
+    while let Some(i) = rest.find("bbox ") {
+        output.push_str(&rest[..i + 5]);
+        rest = &rest[i + 5..];
+        let len = rest
+            .find(|c: char| !c.is_ascii_digit() && c != ' ' && c != '-')
+            .unwrap_or(rest.len());
+        let coordinates = rest[..len]
+            .split_whitespace()
+            .map(|c| c.parse::<i32>().ok())
+            .collect::<Option<Vec<_>>>();
+        if let Some(c) = coordinates.filter(|c| c.len() == 4) {
+            write!(
+                output,
+                "{} {} {} {}",
+                c[0] + dx,
+                c[1] + dy,
+                c[2] + dx,
+                c[3] + dy
+            )
+            .unwrap();
+            rest = &rest[rest[..len].trim_end().len()..];
+        }
+    }
+    output.push_str(rest);
+    output
+}
@@ -0,0 +1,514 @@
+//! Parsing of VobSub `.idx` files.
+//!
+//! The `vobsub` crate only looks at the palette, so multi-track files end up
+//! with every track's subtitles interleaved. Here we also read the track list,
+//! so that a single track can be demuxed from the `.sub` file.
+//!
+//! A `.sub` file can also be read without its idx file, since the subtitle
+//! packets carry their own timestamps. Only the palette is lost.
+//!
+//! A `.sub` file split into volumes, such as `movie.cd1.sub` and
+//! `movie.cd2.sub`, is joined back together.
+//!
+//! In lenient mode, problems with the idx file are logged and worked around
+//! instead of aborting the run.
+
+use std::{
+    io::{self, BufRead, BufReader, Read},
+    path::{Path, PathBuf},
+};
+
+use crate::{compression, mpeg2, opt::StreamSelector, preprocessor::VobSubStream};
+use log::warn;
+use snafu::{OptionExt, ResultExt, Snafu};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Could not read {}: {}", filename.display(), source))]
+    Io {
+        filename: PathBuf,
+        source: io::Error,
+    },
+
+    #[snafu(display("Invalid palette on line {}", line))]
+    InvalidPalette { line: usize },
+
+    #[snafu(display("Invalid size on line {}", line))]
+    InvalidSize { line: usize },
+
+    #[snafu(display("Invalid track id on line {}", line))]
+    InvalidTrack { line: usize },
+
+    #[snafu(display("Invalid timestamp on line {}", line))]
+    InvalidTimestamp { line: usize },
+
+    #[snafu(display("Invalid delay on line {}", line))]
+    InvalidDelay { line: usize },
+
+    #[snafu(display("No palette found; use --palette to supply one"))]
+    MissingPalette,
+
+    #[snafu(display("No track matching {}", track))]
+    TrackNotFound { track: StreamSelector },
+
+    #[snafu(display("No subtitle packets found in {}", filename.display()))]
+    NoSubtitles { filename: PathBuf },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Keys written by VobSub which we have no use for.
+const IGNORED_KEYS: &[&str] = &[
+    "org",
+    "scale",
+    "alpha",
+    "smooth",
+    "fadein/out",
+    "align",
+    "time offset",
+    "forced subs",
+    "custom colors",
+];
+
+/// A subtitle track declared by an `id:` line.
+#[derive(Debug)]
+pub struct Track {
+    /// ISO 639-1 language code.
+    pub language: String,
+    /// Subpicture stream number in the `.sub` file.
+    pub index: u8,
+    /// Start time in seconds of each subtitle, from the `timestamp:` lines.
+    pub timestamps: Vec<f64>,
+    /// Seconds players shift the subtitles by, from the `delay:` line.
+    pub delay: f64,
+}
+
+#[derive(Debug)]
+pub struct Index {
+    pub palette: Option<[[u8; 3]; 16]>,
+    /// Frame size from the `size:` line.
+    pub size: Option<(u32, u32)>,
+    pub tracks: Vec<Track>,
+    /// The track selected by default, from the `langidx:` line.
+    pub default_track: Option<u8>,
+}
+
+impl Index {
+    /// Parse an idx file. If `lenient`, malformed lines are skipped with a
+    /// warning rather than returning an error.
+    pub fn open(path: impl AsRef<Path>, lenient: bool) -> Result<Self> {
+        let path = path.as_ref();
+        let file = compression::open(path).context(IoSnafu { filename: path })?;
+
+        let mut palette = None;
+        let mut size = None;
+        let mut tracks: Vec<Track> = Vec::new();
+        let mut default_track = None;
+        for (i, line) in file.lines().enumerate() {
+            let line = line.context(IoSnafu { filename: path })?;
+            let line_number = i + 1;
+            let (key, value) = match line.split_once(':') {
+                Some((key, value)) if !key.starts_with('#') => (key.trim(), value.trim()),
+                _ => {
+                    if lenient && !line.trim().is_empty() && !line.starts_with('#') {
+                        warn!("{}:{}: ignoring line", path.display(), line_number);
+                    }
+                    continue;
+                }
+            };
+            let result = match key {
+                "palette" => parse_palette(value)
+                    .map(|p| palette = Some(p))
+                    .context(InvalidPaletteSnafu { line: line_number }),
+                "size" => parse_size(value)
+                    .map(|s| size = Some(s))
+                    .context(InvalidSizeSnafu { line: line_number }),
+                "langidx" => {
+                    default_track = value.parse().ok();
+                    Ok(())
+                }
+                "id" => match parse_track(value) {
+                    Some(track) => {
+                        tracks.push(track);
+                        Ok(())
+                    }
+                    // VobSub numbers tracks in order, so a missing index can
+                    // be recovered, unless the last one was already 255.
+                    None if lenient && !value.is_empty() => tracks
+                        .last()
+                        .map_or(Some(0), |t| t.index.checked_add(1))
+                        .map(|index| {
+                            warn!(
+                                "{}:{}: track has no index; assuming {}",
+                                path.display(),
+                                line_number,
+                                index
+                            );
+                            tracks.push(Track {
+                                language: value.split(',').next().unwrap_or("").trim().to_owned(),
+                                index,
+                                timestamps: Vec::new(),
+                                delay: 0.0,
+                            });
+                        })
+                        .context(InvalidTrackSnafu { line: line_number }),
+                    None => InvalidTrackSnafu { line: line_number }.fail(),
+                },
+                "timestamp" => parse_timestamp(value)
+                    .map(|time| {
+                        if let Some(track) = tracks.last_mut() {
+                            track.timestamps.push(time);
+                        }
+                    })
+                    .context(InvalidTimestampSnafu { line: line_number }),
+                "delay" => parse_delay(value)
+                    .map(|delay| {
+                        if let Some(track) = tracks.last_mut() {
+                            track.delay = delay;
+                        }
+                    })
+                    .context(InvalidDelaySnafu { line: line_number }),
+                _ => {
+                    if lenient && !IGNORED_KEYS.contains(&key) {
+                        warn!(
+                            "{}:{}: ignoring unknown key `{}`",
+                            path.display(),
+                            line_number,
+                            key
+                        );
+                    }
+                    Ok(())
+                }
+            };
+            match result {
+                Err(e) if lenient => warn!("{}: {}; ignoring", path.display(), e),
+                result => result?,
+            }
+        }
+
+        Ok(Self {
+            palette,
+            size,
+            tracks,
+            default_track,
+        })
+    }
+
+    /// Return the subpicture stream number of the selected track, falling back
+    /// to the `langidx` default. Returns `None` if the index does not list any
+    /// tracks, in which case the whole `.sub` file should be used.
+    pub fn select_track(&self, track: Option<&StreamSelector>) -> Result<Option<u8>> {
+        if self.tracks.is_empty() {
+            return Ok(None);
+        }
+        let found = match track {
+            None => self
+                .tracks
+                .iter()
+                .find(|t| Some(t.index) == self.default_track)
+                .or_else(|| self.tracks.first()),
+            Some(StreamSelector::Index(i)) => self.tracks.iter().find(|t| t.index as usize == *i),
+            Some(StreamSelector::Language(lang)) => self
+                .tracks
+                .iter()
+                .find(|t| t.language.eq_ignore_ascii_case(lang)),
+        };
+        found.map(|t| Some(t.index)).context(TrackNotFoundSnafu {
+            track: track.cloned().unwrap_or(StreamSelector::Index(0)),
+        })
+    }
+
+    /// Return the language code of the selected track, if the index declares
+    /// one.
+    pub fn track_language(&self, track: Option<&StreamSelector>) -> Option<String> {
+        let index = self.select_track(track).ok()??;
+        self.tracks
+            .iter()
+            .find(|t| t.index == index)
+            .map(|t| t.language.clone())
+            .filter(|language| !language.is_empty())
+    }
+}
+
+/// Read an idx file and its `.sub` file, keeping only the selected track.
+///
+/// The `.sub` file is the one next to the idx file unless `sub_paths` is
+/// given, in which case it may also be split into several volumes. If a
+/// palette is given, it is used instead of the idx file's.
+pub fn read_vobsub(
+    path: impl AsRef<Path>,
+    sub_paths: &[PathBuf],
+    track: Option<&StreamSelector>,
+    palette: Option<&[[u8; 3]; 16]>,
+    lenient: bool,
+) -> Result<VobSubStream> {
+    let path = path.as_ref();
+    let index = Index::open(path, lenient)?;
+    let sub_path = compression::strip_extension(path).with_extension("sub");
+    let sub_data = match (sub_paths, compression::find(&sub_path)) {
+        ([sub_path], _) => read_file(sub_path)?,
+        ([_, _, ..], _) => {
+            let stream = index.select_track(track)?;
+            concatenate_volumes(sub_paths, &index, stream)?
+        }
+        ([], Some(sub_path)) => read_file(&sub_path)?,
+        ([], None) => {
+            let volumes = find_volumes(&sub_path);
+            if volumes.is_empty() {
+                // Report the file we were hoping to find.
+                read_file(&sub_path)?
+            } else {
+                let stream = index.select_track(track)?;
+                concatenate_volumes(&volumes, &index, stream)?
+            }
+        }
+    };
+    let sub_path = sub_paths.first().unwrap_or(&sub_path);
+    demux_sub(Some(&index), sub_data, sub_path, track, palette, lenient)
+}
+
+/// Find the parts of a `.sub` file split across CDs, named like
+/// `movie.cd1.sub`, `movie.cd2.sub`, and so on.
+fn find_volumes(sub_path: &Path) -> Vec<PathBuf> {
+    let stem = sub_path.file_stem().unwrap_or_default().to_string_lossy();
+    (1..)
+        .map(|n| compression::find(&sub_path.with_file_name(format!("{}.cd{}.sub", stem, n))))
+        .take_while(Option::is_some)
+        .flatten()
+        .collect()
+}
+
+/// Join the volumes of a split `.sub` file. Each volume's timestamps usually
+/// restart from zero, so they are shifted to line up with the idx file's
+/// `timestamp:` lines, which run continuously.
+fn concatenate_volumes(volumes: &[PathBuf], index: &Index, stream: Option<u8>) -> Result<Vec<u8>> {
+    let timestamps = index
+        .tracks
+        .iter()
+        .find(|t| Some(t.index) == stream)
+        .map_or(&[][..], |t| &t.timestamps[..]);
+    let is_selected = |pack_stream: u8| stream.is_none() || stream == Some(pack_stream);
+
+    let mut data = Vec::new();
+    let mut subtitle_count = 0;
+    let mut last_pts = 0;
+    for (i, volume_path) in volumes.iter().enumerate() {
+        let mut volume = read_file(volume_path)?;
+        let first_pts = volume
+            .chunks(mpeg2::SECTOR_SIZE)
+            .filter_map(mpeg2::subpicture_pts)
+            .find(|&(s, _)| is_selected(s))
+            .map(|(_, pts)| pts);
+        match first_pts {
+            Some(first_pts) if i > 0 && first_pts < last_pts => {
+                let start = match timestamps.get(subtitle_count) {
+                    Some(&seconds) => (seconds * 90000.0) as u64,
+                    None => {
+                        warn!(
+                            "{}: no idx timestamp for its first subtitle; placing it after the previous volume",
+                            volume_path.display()
+                        );
+                        last_pts
+                    }
+                };
+                let offset = start as i64 - first_pts as i64;
+                for pack in volume.chunks_mut(mpeg2::SECTOR_SIZE) {
+                    mpeg2::shift_subpicture_pts(pack, offset);
+                }
+            }
+            _ => {}
+        }
+        for (_, pts) in volume
+            .chunks(mpeg2::SECTOR_SIZE)
+            .filter_map(mpeg2::subpicture_pts)
+            .filter(|&(s, _)| is_selected(s))
+        {
+            subtitle_count += 1;
+            last_pts = pts;
+        }
+        data.extend_from_slice(&volume);
+    }
+    Ok(data)
+}
+
+fn read_file(path: &Path) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    compression::open(path)
+        .and_then(|mut file| file.read_to_end(&mut data))
+        .context(IoSnafu { filename: path })?;
+    Ok(data)
+}
+
+/// Read a `.sub` file, or standard input if the path is `-`.
+///
+/// The idx file is taken from `idx_path`, or from next to the `.sub` file if
+/// there is one there. Otherwise, the file is scanned for subpicture packs
+/// directly. Tracks can then only be selected by index, and if no palette is
+/// given, it is left up to the decoder to guess one.
+pub fn read_sub(
+    path: impl AsRef<Path>,
+    idx_path: Option<&Path>,
+    track: Option<&StreamSelector>,
+    palette: Option<&[[u8; 3]; 16]>,
+    lenient: bool,
+) -> Result<VobSubStream> {
+    let path = path.as_ref();
+    let stdin = path == Path::new("-");
+    let idx_path = match idx_path {
+        Some(idx_path) => Some(idx_path.to_owned()),
+        None if !stdin => {
+            compression::find(&compression::strip_extension(path).with_extension("idx"))
+        }
+        None => None,
+    };
+    let index = match idx_path {
+        Some(idx_path) => Some(Index::open(idx_path, lenient)?),
+        None => None,
+    };
+    if stdin {
+        let mut sub_data = Vec::new();
+        compression::decompress(BufReader::new(io::stdin()))
+            .and_then(|mut reader| reader.read_to_end(&mut sub_data))
+            .context(IoSnafu {
+                filename: "<stdin>",
+            })?;
+        demux_sub(
+            index.as_ref(),
+            sub_data,
+            Path::new("<stdin>"),
+            track,
+            palette,
+            lenient,
+        )
+    } else {
+        let sub_data = read_file(path)?;
+        demux_sub(index.as_ref(), sub_data, path, track, palette, lenient)
+    }
+}
+
+/// Read the selected track out of `.sub` data.
+fn demux_sub(
+    index: Option<&Index>,
+    sub_data: Vec<u8>,
+    filename: &Path,
+    track: Option<&StreamSelector>,
+    palette: Option<&[[u8; 3]; 16]>,
+    lenient: bool,
+) -> Result<VobSubStream> {
+    let (stream, palette) = match index {
+        Some(index) => {
+            let palette = match palette.copied().or(index.palette) {
+                Some(palette) => Some(palette),
+                None if lenient => {
+                    warn!("{}", MissingPaletteSnafu {}.build());
+                    None
+                }
+                None => return MissingPaletteSnafu {}.fail(),
+            };
+            // Demuxing assumes the `.sub` file is sector-aligned like the VOB
+            // it was ripped from, so don't bother unless there is something to
+            // separate.
+            match index.select_track(track)? {
+                Some(stream) if index.tracks.len() > 1 => (stream, palette),
+                _ => {
+                    return Ok(VobSubStream {
+                        palette,
+                        screen_size: index.size,
+                        language: index.track_language(track),
+                        data: sub_data,
+                    })
+                }
+            }
+        }
+        None => {
+            let stream = match track {
+                Some(StreamSelector::Index(i)) => *i as u8,
+                Some(selector @ StreamSelector::Language(_)) => {
+                    return TrackNotFoundSnafu {
+                        track: selector.clone(),
+                    }
+                    .fail()
+                }
+                None => sub_data
+                    .chunks(mpeg2::SECTOR_SIZE)
+                    .find_map(mpeg2::subpicture_stream)
+                    .context(NoSubtitlesSnafu { filename })?,
+            };
+            (stream, palette.copied())
+        }
+    };
+
+    let mut data = Vec::new();
+    mpeg2::extract_subpicture_stream(&sub_data[..], stream, &mut data)
+        .context(IoSnafu { filename })?;
+    if data.is_empty() {
+        return TrackNotFoundSnafu {
+            track: StreamSelector::Index(stream as usize),
+        }
+        .fail();
+    }
+
+    Ok(VobSubStream {
+        palette,
+        screen_size: index.and_then(|index| index.size),
+        language: index.and_then(|index| index.track_language(track)),
+        data,
+    })
+}
+
+/// Parse a comma-separated list of 16 hex RGB colors.
+pub fn parse_palette(value: &str) -> Option<[[u8; 3]; 16]> {
+    let mut palette = [[0u8; 3]; 16];
+    let mut colors = value.split(',').map(str::trim);
+    for entry in palette.iter_mut() {
+        let color = u32::from_str_radix(colors.next()?, 16).ok()?;
+        *entry = [(color >> 16) as u8, (color >> 8) as u8, color as u8];
+    }
+    if colors.next().is_some() {
+        return None;
+    }
+    Some(palette)
+}
+
+/// Parse the value of a `size: 720x480` line.
+fn parse_size(value: &str) -> Option<(u32, u32)> {
+    let (width, height) = value.split_once('x')?;
+    Some((width.trim().parse().ok()?, height.trim().parse().ok()?))
+}
+
+/// Parse the value of an `id: en, index: 0` line.
+fn parse_track(value: &str) -> Option<Track> {
+    let (language, index) = value.split_once(',')?;
+    let index = index.trim().strip_prefix("index:")?.trim().parse().ok()?;
+    Some(Track {
+        language: language.trim().to_owned(),
+        index,
+        timestamps: Vec::new(),
+        delay: 0.0,
+    })
+}
+
+/// Parse a `delay: -00:00:01:500` line, which may also be just `0`.
+fn parse_delay(value: &str) -> Option<f64> {
+    if value == "0" {
+        return Some(0.0);
+    }
+    match value.strip_prefix('-') {
+        Some(time) => parse_timestamp(time).map(|delay| -delay),
+        None => parse_timestamp(value),
+    }
+}
+
+/// Parse the time of a `timestamp: 00:01:02:345, filepos: 000000000` line.
+fn parse_timestamp(value: &str) -> Option<f64> {
+    let time = value.split(',').next()?.trim();
+    let mut fields = time.split(':').map(|field| field.parse::<u32>().ok());
+    let hours = fields.next()??;
+    let minutes = fields.next()??;
+    let seconds = fields.next()??;
+    let millis = fields.next()??;
+    if fields.next().is_some() {
+        return None;
+    }
+    Some((hours * 3600 + minutes * 60 + seconds) as f64 + millis as f64 / 1000.0)
+}
@@ -0,0 +1,150 @@
+//! Parsing of DVD video title set information (`VTS_xx_0.IFO`) files.
+//!
+//! Only the parts relevant to subtitles are read: the subpicture stream
+//! attributes and the color lookup table of the first program chain. See
+//! <http://dvd.sourceforge.net/dvdinfo/ifo.html> for the layout.
+
+use std::convert::TryInto;
+
+use snafu::{ensure, OptionExt, Snafu};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Not a video title set IFO file"))]
+    NotVtsIfo,
+
+    #[snafu(display("IFO file is truncated"))]
+    Truncated,
+
+    #[snafu(display("IFO file has no program chains"))]
+    NoProgramChains,
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// A subpicture stream as described by the IFO.
+#[derive(Debug)]
+pub struct SubpictureStream {
+    /// ISO 639-1 language code, if the stream declares one.
+    pub language: Option<String>,
+    /// The stream number used in the video object's private stream 1 packs.
+    pub physical_stream: u8,
+}
+
+#[derive(Debug)]
+pub struct VideoTitleSet {
+    /// Subpicture streams, in logical (menu) order.
+    pub subpicture_streams: Vec<SubpictureStream>,
+    /// The 16-color sRGB palette used by the subpictures.
+    pub palette: [[u8; 3]; 16],
+    /// Frame size that subpicture coordinates are relative to.
+    pub screen_size: (u32, u32),
+}
+
+pub fn parse_vts_ifo(data: &[u8]) -> Result<VideoTitleSet> {
+    ensure!(data.starts_with(b"DVDVIDEO-VTS"), NotVtsIfoSnafu {});
+
+    // Video attributes; bits 5-4 of the first byte are the video standard,
+    // where 1 means PAL, and bits 3-2 are the aspect ratio, where 3 means 16:9.
+    let video_attributes = byte(data, 0x200)?;
+    let screen_size = if video_attributes & 0x30 == 0x10 {
+        (720, 576)
+    } else {
+        (720, 480)
+    };
+    let widescreen = video_attributes & 0x0c == 0x0c;
+
+    // The program chain holds both the physical stream mapping and the
+    // palette. Titles can have several, but in practice they share a palette,
+    // so the first one will do.
+    let pgci_offset = be_u32(data, 0xcc)? as usize * 2048;
+    ensure!(be_u16(data, pgci_offset)? > 0, NoProgramChainsSnafu {});
+    let pgc_offset = pgci_offset + be_u32(data, pgci_offset + 12)? as usize;
+
+    let subpicture_stream_count = be_u16(data, 0x254)?.min(32) as usize;
+    let subpicture_streams = (0..subpicture_stream_count)
+        .map(|i| {
+            let attributes = data
+                .get(0x256 + i * 6..0x256 + i * 6 + 6)
+                .context(TruncatedSnafu {})?;
+            // Bits 1-0 of the first byte say whether a language code is
+            // present.
+            let language = if attributes[0] & 0x03 == 1 && attributes[2].is_ascii_alphabetic() {
+                Some(String::from_utf8_lossy(&attributes[2..4]).to_ascii_lowercase())
+            } else {
+                None
+            };
+            // Each stream has a separate physical stream for every display
+            // mode; we only care about 4:3 and wide.
+            let control = data
+                .get(pgc_offset + 0x1c + i * 4..pgc_offset + 0x20 + i * 4)
+                .context(TruncatedSnafu {})?;
+            let physical_stream = if widescreen { control[1] } else { control[0] } & 0x1f;
+            Ok(SubpictureStream {
+                language,
+                physical_stream,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut palette = [[0u8; 3]; 16];
+    for (i, color) in palette.iter_mut().enumerate() {
+        let entry = data
+            .get(pgc_offset + 0xa4 + i * 4..pgc_offset + 0xa8 + i * 4)
+            .context(TruncatedSnafu {})?;
+        *color = ycrcb_to_rgb(entry[1], entry[2], entry[3]);
+    }
+
+    Ok(VideoTitleSet {
+        subpicture_streams,
+        palette,
+        screen_size,
+    })
+}
+
+/// Convert a DVD palette entry from Y'CrCb to sRGB.
+pub fn ycrcb_to_rgb(y: u8, cr: u8, cb: u8) -> [u8; 3] {
+    let y = y as f32;
+    let cr = cr as f32 - 128.0;
+    let cb = cb as f32 - 128.0;
+    [
+        (y + 1.402 * cr).round().clamp(0.0, 255.0) as u8,
+        (y - 0.344_136 * cb - 0.714_136 * cr)
+            .round()
+            .clamp(0.0, 255.0) as u8,
+        (y + 1.772 * cb).round().clamp(0.0, 255.0) as u8,
+    ]
+}
+
+/// Convert an sRGB color to Y'CrCb, the inverse of [`ycrcb_to_rgb`].
+pub fn rgb_to_ycrcb([r, g, b]: [u8; 3]) -> [u8; 3] {
+    let (r, g, b) = (r as f32, g as f32, b as f32);
+    let y = 0.299 * r + 0.587 * g + 0.114 * b;
+    [
+        y.round().clamp(0.0, 255.0) as u8,
+        ((r - y) / 1.402 + 128.0).round().clamp(0.0, 255.0) as u8,
+        ((b - y) / 1.772 + 128.0).round().clamp(0.0, 255.0) as u8,
+    ]
+}
+
+fn byte(data: &[u8], offset: usize) -> Result<u8> {
+    data.get(offset).copied().context(TruncatedSnafu {})
+}
+
+fn be_u16(data: &[u8], offset: usize) -> Result<u16> {
+    Ok(u16::from_be_bytes(
+        data.get(offset..offset + 2)
+            .context(TruncatedSnafu {})?
+            .try_into()
+            .unwrap(),
+    ))
+}
+
+fn be_u32(data: &[u8], offset: usize) -> Result<u32> {
+    Ok(u32::from_be_bytes(
+        data.get(offset..offset + 4)
+            .context(TruncatedSnafu {})?
+            .try_into()
+            .unwrap(),
+    ))
+}
@@ -0,0 +1,196 @@
+//! Describing the tracks of inputs, for the `info` subcommand.
+//!
+//! Every track of an idx file is listed from the idx file alone, without
+//! decoding any images. Other inputs are read to describe the selected track.
+
+use std::{
+    fmt::Write as _,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    compression,
+    format::{format_time, hex_color},
+    idx::{self, Index},
+    opt::InfoOpt,
+    preprocessor,
+};
+use serde::Serialize;
+use snafu::{ResultExt, Snafu};
+use subparse::timetypes::TimePoint;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Could not read {}: {}", filename.display(), source))]
+    Index {
+        filename: PathBuf,
+        source: idx::Error,
+    },
+
+    #[snafu(display("Could not read {}: {}", filename.display(), source))]
+    Subtitles {
+        filename: PathBuf,
+        source: preprocessor::Error,
+    },
+
+    #[snafu(display("Could not write JSON: {}", source))]
+    Json { source: serde_json::Error },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// What is known about an input.
+#[derive(Serialize)]
+struct Input {
+    path: String,
+    /// Width and height of the video.
+    size: Option<(u32, u32)>,
+    /// The 16-color palette, as hex RGB colors.
+    palette: Option<Vec<String>>,
+    tracks: Vec<Track>,
+}
+
+/// What is known about one of an input's tracks.
+#[derive(Serialize)]
+struct Track {
+    /// Subpicture stream number, if the input has several.
+    index: Option<u8>,
+    language: Option<String>,
+    /// Whether the input marks this track as the one to show by default.
+    default: bool,
+    subtitles: usize,
+    /// How many subtitles are forced, if known without decoding them.
+    forced: Option<usize>,
+    /// Times in milliseconds of the start of the first subtitle and of the end
+    /// of the last one, or of its start if its end isn't known.
+    start: Option<i64>,
+    end: Option<i64>,
+    /// Milliseconds players shift the subtitles by.
+    delay: i64,
+}
+
+/// Describe the tracks of each input, as JSON or as text.
+pub fn describe(opt: &InfoOpt) -> Result<String> {
+    let inputs = opt
+        .input
+        .inputs
+        .iter()
+        .map(|path| read_input(path, opt))
+        .collect::<Result<Vec<_>>>()?;
+    if opt.json {
+        let mut json = serde_json::to_string_pretty(&inputs).context(JsonSnafu {})?;
+        json.push('\n');
+        return Ok(json);
+    }
+    let mut text = String::new();
+    for input in &inputs {
+        writeln!(text, "{}", input.path).unwrap();
+        if let Some((width, height)) = input.size {
+            writeln!(text, "  Size: {}x{}", width, height).unwrap();
+        }
+        if let Some(palette) = &input.palette {
+            writeln!(text, "  Palette: {}", palette.join(", ")).unwrap();
+        }
+        for track in &input.tracks {
+            let mut line = match track.index {
+                Some(index) => format!("  Track {}:", index),
+                None => "  Track:".to_owned(),
+            };
+            write!(
+                line,
+                " {}, {} subtitles",
+                track.language.as_deref().unwrap_or("unknown language"),
+                track.subtitles
+            )
+            .unwrap();
+            if let Some(forced) = track.forced {
+                write!(line, " ({} forced)", forced).unwrap();
+            }
+            if let (Some(start), Some(end)) = (track.start, track.end) {
+                write!(
+                    line,
+                    ", {} to {}",
+                    format_time(TimePoint::from_msecs(start)),
+                    format_time(TimePoint::from_msecs(end))
+                )
+                .unwrap();
+            }
+            if track.delay != 0 {
+                let sign = if track.delay < 0 { "-" } else { "" };
+                write!(
+                    line,
+                    ", delay {}{}",
+                    sign,
+                    format_time(TimePoint::from_msecs(track.delay.abs()))
+                )
+                .unwrap();
+            }
+            if track.default {
+                line.push_str(", default");
+            }
+            writeln!(text, "{}", line).unwrap();
+        }
+    }
+    Ok(text)
+}
+
+/// Describe every track of an idx file, or the selected track of any other
+/// input.
+fn read_input(path: &Path, opt: &InfoOpt) -> Result<Input> {
+    let is_idx = matches!(
+        compression::strip_extension(path)
+            .extension()
+            .and_then(|ext| ext.to_str()),
+        Some(ext) if ext.eq_ignore_ascii_case("idx")
+    );
+    if is_idx {
+        let index = Index::open(path, opt.input.lenient).context(IndexSnafu { filename: path })?;
+        let tracks = index
+            .tracks
+            .iter()
+            .map(|track| {
+                let msecs = |seconds: f64| (seconds * 1000.0).round() as i64;
+                Track {
+                    index: Some(track.index),
+                    language: Some(track.language.clone()),
+                    default: index.default_track == Some(track.index),
+                    subtitles: track.timestamps.len(),
+                    forced: None,
+                    start: track.timestamps.first().copied().map(msecs),
+                    end: track.timestamps.last().copied().map(msecs),
+                    delay: msecs(track.delay),
+                }
+            })
+            .collect();
+        return Ok(Input {
+            path: path.display().to_string(),
+            size: index.size,
+            palette: index.palette.as_ref().map(hex_colors),
+            tracks,
+        });
+    }
+
+    let track =
+        preprocessor::read_bitmaps(&opt.input, path).context(SubtitlesSnafu { filename: path })?;
+    let bitmaps = &track.bitmaps;
+    let size = bitmaps.first().map(|bitmap| bitmap.screen_size);
+    Ok(Input {
+        path: path.display().to_string(),
+        size,
+        palette: track.palette.as_ref().map(hex_colors),
+        tracks: vec![Track {
+            index: None,
+            language: track.language.clone(),
+            default: false,
+            subtitles: bitmaps.len(),
+            forced: Some(bitmaps.iter().filter(|bitmap| bitmap.force).count()),
+            start: bitmaps.iter().map(|b| b.time_span.start.msecs()).min(),
+            end: bitmaps.iter().map(|b| b.time_span.end.msecs()).max(),
+            delay: 0,
+        }],
+    })
+}
+
+fn hex_colors(palette: &[[u8; 3]; 16]) -> Vec<String> {
+    palette.iter().map(|&color| hex_color(color)).collect()
+}
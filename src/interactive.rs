@@ -0,0 +1,205 @@
+//! Reviewing subtitles of low confidence in the terminal before they are
+//! written, for `--interactive`.
+//!
+//! Each subtitle's image is shown with the kitty graphics protocol in
+//! terminals which support it, and as text art otherwise. The prompts are
+//! written to stderr so that they stay out of subtitles written to stdout.
+
+use std::{
+    env,
+    io::{self, BufRead, Cursor, IsTerminal, Write},
+};
+
+use crate::{
+    ocr::{self, Subtitle},
+    preprocessor::SubtitleBitmap,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use image::{imageops, DynamicImage, ImageOutputFormat, RgbaImage};
+use snafu::{ensure, ResultExt, Snafu};
+
+/// Characters of increasing brightness for drawing images as text.
+const SHADES: &[u8] = b" .:-=+*#%@";
+
+/// The widest text art drawn, in characters.
+const MAX_COLUMNS: u32 = 100;
+
+/// How much data is sent in each escape sequence of the kitty graphics
+/// protocol.
+const KITTY_CHUNK: usize = 4096;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("--interactive needs a terminal to read answers from"))]
+    NotTerminal,
+
+    #[snafu(display("Could not encode image: {}", source))]
+    EncodeImage { source: image::ImageError },
+
+    #[snafu(display("{}", source))]
+    Io { source: io::Error },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Make sure answers can be read from the terminal, before spending time on
+/// OCR.
+pub fn check_terminal() -> Result<()> {
+    ensure!(io::stdin().is_terminal(), NotTerminalSnafu {});
+    Ok(())
+}
+
+/// Show each subtitle below `threshold` with its image and let its text be
+/// accepted, edited or dropped, returning the indices of those dropped.
+/// Accepted and edited subtitles get full confidence, since they have been
+/// checked.
+pub fn review(
+    subtitles: &mut [ocr::Result<Subtitle>],
+    sources: &[&SubtitleBitmap],
+    threshold: i32,
+) -> Result<Vec<usize>> {
+    let kitty = env::var_os("KITTY_WINDOW_ID").is_some()
+        || matches!(env::var("TERM"), Ok(term) if term.contains("kitty"));
+    let low: Vec<usize> = subtitles
+        .iter()
+        .enumerate()
+        .filter(|(_, subtitle)| matches!(subtitle, Ok(s) if s.confidence() < threshold))
+        .map(|(i, _)| i)
+        .collect();
+
+    let mut stdin = io::stdin().lock();
+    let mut stderr = io::stderr().lock();
+    let mut dropped = Vec::new();
+    for (n, &i) in low.iter().enumerate() {
+        let subtitle = match &mut subtitles[i] {
+            Ok(subtitle) => subtitle,
+            Err(_) => continue,
+        };
+        writeln!(
+            stderr,
+            "\nSubtitle {} at {}, confidence {} ({} of {}):",
+            i + 1,
+            subtitle.time_span.start,
+            subtitle.confidence(),
+            n + 1,
+            low.len()
+        )
+        .context(IoSnafu {})?;
+        let image = sources[i].to_rgba();
+        if kitty {
+            show_kitty(&mut stderr, &image)?;
+        } else {
+            show_text_art(&mut stderr, &image)?;
+        }
+        for line in &subtitle.lines {
+            writeln!(stderr, "  {}", line.text).context(IoSnafu {})?;
+        }
+        write!(stderr, "[Enter] accept, [e]dit, [d]rop, [q]uit reviewing: ").context(IoSnafu {})?;
+        stderr.flush().context(IoSnafu {})?;
+
+        match read_line(&mut stdin)?.as_deref().map(str::trim) {
+            None | Some("q") => break,
+            Some("d") => {
+                dropped.push(i);
+                continue;
+            }
+            Some("e") => {
+                writeln!(stderr, "Enter the new text, then an empty line:").context(IoSnafu {})?;
+                let mut lines = Vec::new();
+                while let Some(line) = read_line(&mut stdin)? {
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                    lines.push(line);
+                }
+                if !lines.is_empty() {
+                    set_text(subtitle, lines);
+                }
+            }
+            Some(_) => {}
+        }
+        for line in &mut subtitle.lines {
+            line.confidence = 100;
+        }
+    }
+    Ok(dropped)
+}
+
+/// Read a line without its line ending, or `None` at the end of input.
+fn read_line(input: &mut impl BufRead) -> Result<Option<String>> {
+    let mut line = String::new();
+    if input.read_line(&mut line).context(IoSnafu {})? == 0 {
+        return Ok(None);
+    }
+    Ok(Some(line.trim_end_matches(&['\r', '\n'][..]).to_owned()))
+}
+
+/// Replace the text of a subtitle's lines, keeping the color and style of the
+/// line in the same place, or of the last line for lines added after them.
+fn set_text(subtitle: &mut Subtitle, text: Vec<String>) {
+    subtitle.lines.truncate(text.len());
+    for (i, text) in text.into_iter().enumerate() {
+        match subtitle.lines.get_mut(i) {
+            Some(line) => line.text = text,
+            None => {
+                let (color, italic) = match subtitle.lines.last() {
+                    Some(last) => (last.color, last.italic),
+                    None => (subtitle.text_color, false),
+                };
+                subtitle.lines.push(ocr::Line {
+                    text,
+                    confidence: 100,
+                    color,
+                    italic,
+                    markup: None,
+                });
+            }
+        }
+    }
+}
+
+/// Draw an image with the kitty graphics protocol, as a PNG sent in chunks.
+fn show_kitty(output: &mut impl Write, image: &RgbaImage) -> Result<()> {
+    let mut png = Cursor::new(Vec::new());
+    DynamicImage::ImageRgba8(image.clone())
+        .write_to(&mut png, ImageOutputFormat::Png)
+        .context(EncodeImageSnafu {})?;
+    let data = STANDARD.encode(png.get_ref());
+    let chunks: Vec<&[u8]> = data.as_bytes().chunks(KITTY_CHUNK).collect();
+    for (i, chunk) in chunks.iter().enumerate() {
+        let more = (i + 1 < chunks.len()) as u8;
+        let control = if i == 0 {
+            format!("a=T,f=100,m={}", more)
+        } else {
+            format!("m={}", more)
+        };
+        write!(output, "\x1b_G{};", control).context(IoSnafu {})?;
+        output.write_all(chunk).context(IoSnafu {})?;
+        write!(output, "\x1b\\").context(IoSnafu {})?;
+    }
+    writeln!(output).context(IoSnafu {})
+}
+
+/// Draw an image as text, with brighter characters for brighter opaque
+/// pixels, shrunk to fit the terminal. Characters are about twice as tall as
+/// they are wide, so each covers two rows of pixels for each column.
+fn show_text_art(output: &mut impl Write, image: &RgbaImage) -> Result<()> {
+    if image.width() == 0 || image.height() == 0 {
+        return Ok(());
+    }
+    let columns = image.width().min(MAX_COLUMNS);
+    let rows = (image.height() * columns / image.width() / 2).max(1);
+    let small = imageops::resize(image, columns, rows, imageops::FilterType::Triangle);
+    for row in small.rows() {
+        let line: String = row
+            .map(|pixel| {
+                let [r, g, b, a] = pixel.0;
+                let luma = (r as u32 * 299 + g as u32 * 587 + b as u32 * 114) / 1000;
+                let brightness = luma * a as u32 / 255;
+                SHADES[brightness as usize * (SHADES.len() - 1) / 255] as char
+            })
+            .collect();
+        writeln!(output, "{}", line.trim_end()).context(IoSnafu {})?;
+    }
+    Ok(())
+}
@@ -0,0 +1,140 @@
+//! A small read-only ISO 9660 filesystem reader.
+//!
+//! DVD-Video discs are required to carry an ISO 9660 filesystem alongside UDF
+//! (the "UDF bridge" format), so this is enough to locate the `VIDEO_TS` files
+//! on any compliant disc image without needing to mount it.
+
+use std::{
+    convert::TryInto,
+    fs::File,
+    io::{self, BufReader, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use snafu::{OptionExt, ResultExt, Snafu};
+
+const SECTOR_SIZE: u64 = 2048;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Could not read disc image: {}", source))]
+    Io { source: io::Error },
+
+    #[snafu(display("No ISO 9660 primary volume descriptor found"))]
+    NoPrimaryVolumeDescriptor,
+
+    #[snafu(display("Malformed directory record in sector {}", sector))]
+    MalformedDirectoryRecord { sector: u32 },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// A file or directory in the image.
+#[derive(Debug, Clone)]
+pub struct DirectoryEntry {
+    /// File name, without the `;1` version suffix.
+    pub name: String,
+    extent: u32,
+    size: u32,
+}
+
+pub struct IsoImage {
+    file: File,
+    root: DirectoryEntry,
+}
+
+impl IsoImage {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let mut file = File::open(path).context(IoSnafu {})?;
+        // Volume descriptors start at sector 16 and are terminated by a
+        // descriptor of type 255.
+        let mut sector = 16;
+        loop {
+            let mut descriptor = [0u8; SECTOR_SIZE as usize];
+            file.seek(SeekFrom::Start(sector * SECTOR_SIZE))
+                .context(IoSnafu {})?;
+            file.read_exact(&mut descriptor).context(IoSnafu {})?;
+            if &descriptor[1..6] != b"CD001" || descriptor[0] == 255 {
+                return NoPrimaryVolumeDescriptorSnafu {}.fail();
+            }
+            if descriptor[0] == 1 {
+                let root = parse_directory_record(&descriptor[156..190]).context(
+                    MalformedDirectoryRecordSnafu {
+                        sector: sector as u32,
+                    },
+                )?;
+                return Ok(Self { file, root });
+            }
+            sector += 1;
+        }
+    }
+
+    /// Look up an entry by a `/`-separated path relative to the root. Names
+    /// are matched case-insensitively.
+    pub fn find(&mut self, path: &str) -> Result<Option<DirectoryEntry>> {
+        let mut entry = self.root.clone();
+        for component in path.split('/').filter(|c| !c.is_empty()) {
+            match self
+                .read_dir(&entry)?
+                .into_iter()
+                .find(|e| e.name.eq_ignore_ascii_case(component))
+            {
+                Some(child) => entry = child,
+                None => return Ok(None),
+            }
+        }
+        Ok(Some(entry))
+    }
+
+    /// List the contents of a directory, excluding `.` and `..`.
+    pub fn read_dir(&mut self, dir: &DirectoryEntry) -> Result<Vec<DirectoryEntry>> {
+        let mut data = Vec::with_capacity(dir.size as usize);
+        self.open_file(dir)?
+            .read_to_end(&mut data)
+            .context(IoSnafu {})?;
+
+        let mut entries = Vec::new();
+        for (i, sector) in data.chunks(SECTOR_SIZE as usize).enumerate() {
+            let mut offset = 0;
+            // Records never straddle sectors; a zero length means the rest of
+            // the sector is padding.
+            while offset < sector.len() && sector[offset] != 0 {
+                let length = sector[offset] as usize;
+                let entry = sector
+                    .get(offset..offset + length)
+                    .and_then(parse_directory_record)
+                    .context(MalformedDirectoryRecordSnafu {
+                        sector: dir.extent + i as u32,
+                    })?;
+                if entry.name != "\0" && entry.name != "\u{1}" {
+                    entries.push(entry);
+                }
+                offset += length;
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Return a reader over the contents of a file.
+    pub fn open_file(&mut self, entry: &DirectoryEntry) -> Result<impl Read + '_> {
+        self.file
+            .seek(SeekFrom::Start(entry.extent as u64 * SECTOR_SIZE))
+            .context(IoSnafu {})?;
+        Ok(BufReader::new(&self.file).take(entry.size as u64))
+    }
+}
+
+fn parse_directory_record(record: &[u8]) -> Option<DirectoryEntry> {
+    let name_length = *record.get(32)? as usize;
+    let name = record.get(33..33 + name_length)?;
+    let name = String::from_utf8_lossy(name);
+    let name = match name.rfind(';') {
+        Some(pos) => &name[..pos],
+        None => &name,
+    };
+    Some(DirectoryEntry {
+        name: name.trim_end_matches('.').to_owned(),
+        extent: u32::from_le_bytes(record[2..6].try_into().ok()?),
+        size: u32::from_le_bytes(record[10..14].try_into().ok()?),
+    })
+}
@@ -0,0 +1,47 @@
+//! Writing subtitles as JSON, with everything known about each one, for
+//! consumption by other programs.
+
+use crate::{
+    ocr::Subtitle,
+    preprocessor::Placement,
+    writer::{JsonSnafu, Result, SubtitleWriter},
+};
+use serde::Serialize;
+use snafu::ResultExt;
+
+/// A subtitle as it appears in the JSON output.
+#[derive(Serialize)]
+struct Cue<'a> {
+    /// Start and end times in milliseconds.
+    start: i64,
+    end: i64,
+    text: String,
+    confidence: Vec<i32>,
+    /// Whether each line was detected to be in italics.
+    italic: Vec<bool>,
+    forced: bool,
+    placement: &'a Placement,
+}
+
+/// Writes subtitles as a JSON array of cues.
+pub struct JsonWriter;
+
+impl SubtitleWriter for JsonWriter {
+    fn write(&self, subtitles: &[Subtitle]) -> Result<Vec<u8>> {
+        let cues = subtitles
+            .iter()
+            .map(|subtitle| Cue {
+                start: subtitle.time_span.start.msecs(),
+                end: subtitle.time_span.end.msecs(),
+                text: subtitle.text(),
+                confidence: subtitle.lines.iter().map(|line| line.confidence).collect(),
+                italic: subtitle.lines.iter().map(|line| line.italic).collect(),
+                forced: subtitle.force,
+                placement: &subtitle.placement,
+            })
+            .collect::<Vec<_>>();
+        let mut json = serde_json::to_vec_pretty(&cues).context(JsonSnafu {})?;
+        json.push(b'\n');
+        Ok(json)
+    }
+}
@@ -0,0 +1,136 @@
+//! Mapping the language codes declared by subtitle tracks to the names of
+//! Tesseract's traineddata files.
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+/// Where Tesseract's traineddata files are usually installed, after
+/// `TESSDATA_PREFIX`.
+const TESSDATA_DIRS: &[&str] = &[
+    "/usr/share/tesseract-ocr/5/tessdata",
+    "/usr/share/tesseract-ocr/4.00/tessdata",
+    "/usr/share/tessdata",
+    "/usr/local/share/tessdata",
+    "/opt/homebrew/share/tessdata",
+    "C:\\Program Files\\Tesseract-OCR\\tessdata",
+];
+
+/// ISO 639-1, ISO 639-2/B and ISO 639-2/T codes, and the matching Tesseract
+/// language.
+const LANGUAGES: &[(&str, &str, &str, &str)] = &[
+    ("ar", "ara", "ara", "ara"),
+    ("be", "bel", "bel", "bel"),
+    ("bg", "bul", "bul", "bul"),
+    ("bn", "ben", "ben", "ben"),
+    ("bs", "bos", "bos", "bos"),
+    ("ca", "cat", "cat", "cat"),
+    ("cs", "cze", "ces", "ces"),
+    ("cy", "wel", "cym", "cym"),
+    ("da", "dan", "dan", "dan"),
+    ("de", "ger", "deu", "deu"),
+    ("el", "gre", "ell", "ell"),
+    ("en", "eng", "eng", "eng"),
+    ("es", "spa", "spa", "spa"),
+    ("et", "est", "est", "est"),
+    ("eu", "baq", "eus", "eus"),
+    ("fa", "per", "fas", "fas"),
+    ("fi", "fin", "fin", "fin"),
+    ("fr", "fre", "fra", "fra"),
+    ("ga", "gle", "gle", "gle"),
+    ("gl", "glg", "glg", "glg"),
+    ("he", "heb", "heb", "heb"),
+    ("hi", "hin", "hin", "hin"),
+    ("hr", "hrv", "hrv", "hrv"),
+    ("hu", "hun", "hun", "hun"),
+    ("id", "ind", "ind", "ind"),
+    ("is", "ice", "isl", "isl"),
+    ("it", "ita", "ita", "ita"),
+    ("ja", "jpn", "jpn", "jpn"),
+    ("ko", "kor", "kor", "kor"),
+    ("lt", "lit", "lit", "lit"),
+    ("lv", "lav", "lav", "lav"),
+    ("mk", "mac", "mkd", "mkd"),
+    ("ms", "may", "msa", "msa"),
+    ("nl", "dut", "nld", "nld"),
+    ("no", "nor", "nor", "nor"),
+    ("pl", "pol", "pol", "pol"),
+    ("pt", "por", "por", "por"),
+    ("ro", "rum", "ron", "ron"),
+    ("ru", "rus", "rus", "rus"),
+    ("sk", "slo", "slk", "slk"),
+    ("sl", "slv", "slv", "slv"),
+    ("sq", "alb", "sqi", "sqi"),
+    ("sr", "srp", "srp", "srp"),
+    ("sv", "swe", "swe", "swe"),
+    ("ta", "tam", "tam", "tam"),
+    ("te", "tel", "tel", "tel"),
+    ("th", "tha", "tha", "tha"),
+    ("tr", "tur", "tur", "tur"),
+    ("uk", "ukr", "ukr", "ukr"),
+    ("ur", "urd", "urd", "urd"),
+    ("vi", "vie", "vie", "vie"),
+    // Most Chinese DVDs use simplified characters; traditional ones need
+    // `-l chi_tra`.
+    ("zh", "chi", "zho", "chi_sim"),
+];
+
+/// Find the Tesseract language for a two- or three-letter language code.
+pub fn tesseract_language(code: &str) -> Option<&'static str> {
+    let code = code.trim().to_ascii_lowercase();
+    LANGUAGES
+        .iter()
+        .find(|&&(iso1, iso2b, iso2t, _)| code == iso1 || code == iso2b || code == iso2t)
+        .map(|&(_, _, _, tesseract)| tesseract)
+}
+
+/// Find the ISO 639-1 code for a Tesseract language, ignoring variants such
+/// as `chi_sim` and `chi_tra`.
+pub fn iso_639_1(tesseract_language: &str) -> Option<&'static str> {
+    LANGUAGES
+        .iter()
+        .find(|&&(_, _, _, tesseract)| same_language(tesseract, tesseract_language))
+        .map(|&(iso1, _, _, _)| iso1)
+}
+
+/// Whether Tesseract languages are the same, ignoring variants such as
+/// `chi_sim` and `chi_tra`.
+pub fn same_language(a: &str, b: &str) -> bool {
+    a.split('_').next() == b.split('_').next()
+}
+
+/// List the Tesseract languages installed in `tessdata_dir`, or in the first
+/// of the usual places which has any.
+pub fn installed(tessdata_dir: Option<&Path>) -> Vec<String> {
+    let dirs: Vec<PathBuf> = match tessdata_dir {
+        Some(dir) => vec![dir.to_owned()],
+        None => env::var_os("TESSDATA_PREFIX")
+            .map(PathBuf::from)
+            .into_iter()
+            .chain(TESSDATA_DIRS.iter().map(PathBuf::from))
+            .collect(),
+    };
+    for dir in dirs {
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        let mut languages: Vec<String> = entries
+            .filter_map(|entry| {
+                let path = entry.ok()?.path();
+                if path.extension()? != "traineddata" {
+                    return None;
+                }
+                let language = path.file_stem()?.to_str()?.to_owned();
+                // Orientation and script detection isn't a language.
+                Some(language).filter(|language| language != "osd")
+            })
+            .collect();
+        if !languages.is_empty() {
+            languages.sort();
+            return languages;
+        }
+    }
+    Vec::new()
+}
@@ -0,0 +1,46 @@
+#![doc = include_str!("../README.md")]
+
+mod alto;
+mod analyze;
+mod ass;
+mod bdn;
+mod bidi;
+mod cache;
+pub mod cli;
+#[cfg(feature = "cloud")]
+mod cloud;
+mod compression;
+mod dvb;
+mod dvd;
+mod fixes;
+mod format;
+mod hearing_impaired;
+mod hocr;
+mod idx;
+mod ifo;
+mod info;
+mod interactive;
+mod iso9660;
+mod json;
+mod language;
+mod microdvd;
+mod morph;
+mod mp4;
+mod mpeg2;
+mod mpl2;
+pub mod ocr;
+pub mod opt;
+pub mod preprocessor;
+mod punctuation;
+mod recase;
+mod replace;
+mod review;
+mod spelling;
+mod srt;
+mod stl;
+mod summary;
+mod sup;
+mod timing;
+mod tmplayer;
+mod vtt;
+pub mod writer;
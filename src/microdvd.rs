@@ -0,0 +1,43 @@
+//! Writing frame-based MicroDVD subtitles.
+
+use std::fmt::Write;
+
+use crate::{
+    ocr::Subtitle,
+    writer::{self, Result, SubtitleWriter},
+};
+use subparse::timetypes::TimePoint;
+
+/// Writes subtitles as MicroDVD lines of the form `{start}{end}text`.
+pub struct MicroDvdWriter {
+    /// Frame rate to convert times to frame numbers with.
+    pub fps: f64,
+}
+
+impl SubtitleWriter for MicroDvdWriter {
+    fn write(&self, subtitles: &[Subtitle]) -> Result<Vec<u8>> {
+        let mut output = String::new();
+        for subtitle in subtitles {
+            // Lines are separated with `|`, and the whole subtitle must fit on one
+            // line of the file.
+            let text = writer::join_lines(&subtitle.text(), "|");
+            if text.is_empty() {
+                continue;
+            }
+            writeln!(
+                output,
+                "{{{}}}{{{}}}{}",
+                to_frame(subtitle.time_span.start, self.fps),
+                to_frame(subtitle.time_span.end, self.fps),
+                text
+            )
+            .unwrap();
+        }
+        Ok(output.into_bytes())
+    }
+}
+
+/// Convert a time to the number of the frame shown at that time.
+fn to_frame(time: TimePoint, fps: f64) -> i64 {
+    (time.msecs().max(0) as f64 * fps / 1000.0).round() as i64
+}
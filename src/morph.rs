@@ -0,0 +1,49 @@
+//! Morphological operations on line images, which thin or thicken the text to
+//! remove noise or close gaps in its strokes.
+
+use crate::opt::{Morph, MorphOp};
+use image::{GrayImage, Luma};
+
+/// Apply each operation in turn to an image of dark text on a light
+/// background.
+pub fn apply(mut image: GrayImage, ops: &[Morph]) -> GrayImage {
+    for &Morph { op, radius } in ops {
+        image = match op {
+            MorphOp::Erode => erode(&image, radius),
+            MorphOp::Dilate => dilate(&image, radius),
+            MorphOp::Open => dilate(&erode(&image, radius), radius),
+            MorphOp::Close => erode(&dilate(&image, radius), radius),
+        };
+    }
+    image
+}
+
+/// Thin the text by taking the lightest pixel around each pixel.
+fn erode(image: &GrayImage, radius: u32) -> GrayImage {
+    filter(image, radius, u8::max)
+}
+
+/// Thicken the text by taking the darkest pixel around each pixel.
+fn dilate(image: &GrayImage, radius: u32) -> GrayImage {
+    filter(image, radius, u8::min)
+}
+
+/// Combine the pixels in a square of `radius` pixels around each pixel, a row
+/// and then a column at a time.
+fn filter(image: &GrayImage, radius: u32, combine: fn(u8, u8) -> u8) -> GrayImage {
+    let (width, height) = image.dimensions();
+    let rows = GrayImage::from_fn(width, height, |x, y| {
+        let start = x.saturating_sub(radius);
+        let end = (x + radius).min(width - 1);
+        Luma([(start..=end)
+            .map(|x| image.get_pixel(x, y)[0])
+            .fold(image.get_pixel(x, y)[0], combine)])
+    });
+    GrayImage::from_fn(width, height, |x, y| {
+        let start = y.saturating_sub(radius);
+        let end = (y + radius).min(height - 1);
+        Luma([(start..=end)
+            .map(|y| rows.get_pixel(x, y)[0])
+            .fold(rows.get_pixel(x, y)[0], combine)])
+    })
+}
@@ -0,0 +1,347 @@
+//! Extraction of VobSub (`mp4s`) subtitle tracks from MP4 files.
+//!
+//! Each sample of such a track is a complete subpicture unit, and the palette
+//! is stored in the track's `esds` box. The samples are repackaged into
+//! program stream packs so they can be decoded like a `.sub` file.
+
+use std::{
+    convert::TryInto,
+    fs::File,
+    io::{self, BufReader, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use crate::{ifo::ycrcb_to_rgb, mpeg2, opt::StreamSelector, preprocessor::VobSubStream};
+use snafu::{ensure, OptionExt, ResultExt, Snafu};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Could not read MP4 file: {}", source))]
+    Io { source: io::Error },
+
+    #[snafu(display("MP4 file has no movie box"))]
+    NoMovie,
+
+    #[snafu(display("Malformed {} box", name))]
+    Malformed { name: String },
+
+    #[snafu(display("MP4 file has no VobSub track matching {}", stream))]
+    StreamNotFound { stream: StreamSelector },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// A VobSub track and the location of its samples.
+#[derive(Debug, Default)]
+struct Track {
+    /// ISO 639-2 language code.
+    language: String,
+    timescale: u32,
+    palette: Option<[[u8; 3]; 16]>,
+    /// Width and height from the track header, if set.
+    size: Option<(u32, u32)>,
+    /// Duration of each sample, in `timescale` units.
+    durations: Vec<u32>,
+    sizes: Vec<u32>,
+    /// First chunk (1-based) and samples per chunk of each run of chunks.
+    chunk_runs: Vec<(u32, u32)>,
+    chunk_offsets: Vec<u64>,
+}
+
+/// Extract the selected VobSub track of an MP4 file. If no track is selected,
+/// the first one is used.
+pub fn read_mp4(path: impl AsRef<Path>, stream: Option<&StreamSelector>) -> Result<VobSubStream> {
+    let mut file = BufReader::new(File::open(path).context(IoSnafu {})?);
+    let moov = find_top_level_box(&mut file, b"moov")?.context(NoMovieSnafu {})?;
+
+    let mut tracks = Vec::new();
+    for (kind, trak) in boxes(&moov) {
+        if kind == b"trak" {
+            if let Some(track) = parse_track(trak)? {
+                tracks.push(track);
+            }
+        }
+    }
+
+    let track = match stream {
+        None => tracks.first(),
+        Some(StreamSelector::Index(i)) => tracks.get(*i),
+        Some(StreamSelector::Language(lang)) => tracks
+            .iter()
+            .find(|t| t.language.eq_ignore_ascii_case(lang)),
+    }
+    .context(StreamNotFoundSnafu {
+        stream: stream.cloned().unwrap_or(StreamSelector::Index(0)),
+    })?;
+
+    let mut data = Vec::new();
+    let mut time = 0u64;
+    let mut sample = Vec::new();
+    for (i, offset) in sample_offsets(track).into_iter().enumerate() {
+        let size = track.sizes[i] as usize;
+        // Empty samples are used to clear the screen.
+        if size > 4 {
+            sample.resize(size, 0);
+            file.seek(SeekFrom::Start(offset)).context(IoSnafu {})?;
+            file.read_exact(&mut sample).context(IoSnafu {})?;
+            let pts = time * 90000 / track.timescale as u64;
+            mpeg2::write_subpicture_packets(&mut data, 0, pts, &sample);
+        }
+        time += track.durations.get(i).copied().unwrap_or(0) as u64;
+    }
+
+    Ok(VobSubStream {
+        palette: track.palette,
+        screen_size: track.size,
+        language: Some(track.language.clone()),
+        data,
+    })
+}
+
+/// Skip through the top-level boxes until one of the given type is found, and
+/// return its contents.
+fn find_top_level_box(reader: &mut (impl Read + Seek), kind: &[u8; 4]) -> Result<Option<Vec<u8>>> {
+    loop {
+        let mut header = [0u8; 8];
+        match reader.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e).context(IoSnafu {}),
+        }
+        let mut size = u32::from_be_bytes(header[0..4].try_into().unwrap()) as u64;
+        let mut header_size = 8;
+        if size == 1 {
+            let mut large_size = [0u8; 8];
+            reader.read_exact(&mut large_size).context(IoSnafu {})?;
+            size = u64::from_be_bytes(large_size);
+            header_size = 16;
+        }
+        if &header[4..8] == kind {
+            let mut contents = Vec::new();
+            if size == 0 {
+                reader.read_to_end(&mut contents).context(IoSnafu {})?;
+            } else {
+                ensure!(size >= header_size, MalformedSnafu { name: "top-level" });
+                reader
+                    .take(size - header_size)
+                    .read_to_end(&mut contents)
+                    .context(IoSnafu {})?;
+            }
+            return Ok(Some(contents));
+        }
+        if size == 0 {
+            return Ok(None);
+        }
+        ensure!(size >= header_size, MalformedSnafu { name: "top-level" });
+        reader
+            .seek(SeekFrom::Current((size - header_size) as i64))
+            .context(IoSnafu {})?;
+    }
+}
+
+/// Iterate over the boxes contained in a box's contents. Iteration stops at
+/// the first malformed box.
+fn boxes(data: &[u8]) -> impl Iterator<Item = (&[u8], &[u8])> {
+    let mut rest = data;
+    std::iter::from_fn(move || {
+        let size = u32::from_be_bytes(rest.get(0..4)?.try_into().unwrap()) as usize;
+        let kind = rest.get(4..8)?;
+        let (start, size) = match size {
+            0 => (8, rest.len()),
+            1 => (
+                16,
+                u64::from_be_bytes(rest.get(8..16)?.try_into().unwrap()) as usize,
+            ),
+            size => (8, size),
+        };
+        let contents = rest.get(start..size)?;
+        rest = &rest[size..];
+        Some((kind, contents))
+    })
+}
+
+fn find_box<'a>(data: &'a [u8], path: &[&[u8; 4]]) -> Option<&'a [u8]> {
+    path.iter().try_fold(data, |data, kind| {
+        boxes(data)
+            .find(|(k, _)| k == kind)
+            .map(|(_, contents)| contents)
+    })
+}
+
+/// Parse a `trak` box, returning `None` if it is not a VobSub track.
+fn parse_track(trak: &[u8]) -> Result<Option<Track>> {
+    let handler = find_box(trak, &[b"mdia", b"hdlr"]);
+    if handler.and_then(|h| h.get(8..12)) != Some(b"subp") {
+        return Ok(None);
+    }
+    let stbl =
+        find_box(trak, &[b"mdia", b"minf", b"stbl"]).context(MalformedSnafu { name: "trak" })?;
+    let stsd = find_box(stbl, &[b"stsd"]).context(MalformedSnafu { name: "stbl" })?;
+    // Skip the version, flags and entry count.
+    let entry = match boxes(stsd.get(8..).unwrap_or_default()).next() {
+        Some((b"mp4s", entry)) => entry,
+        _ => return Ok(None),
+    };
+
+    let mut track = Track::default();
+
+    let mdhd = find_box(trak, &[b"mdia", b"mdhd"]).context(MalformedSnafu { name: "mdhd" })?;
+    let (timescale_offset, language_offset) = if mdhd.first() == Some(&1) {
+        (20, 32)
+    } else {
+        (12, 20)
+    };
+    track.timescale = be_u32(mdhd, timescale_offset).context(MalformedSnafu { name: "mdhd" })?;
+    ensure!(track.timescale > 0, MalformedSnafu { name: "mdhd" });
+    if let Some(language) = be_u16(mdhd, language_offset) {
+        // Three 5-bit letters, offset from 0x60.
+        track.language = (0..3)
+            .rev()
+            .map(|i| (((language >> (i * 5)) & 0x1f) as u8 + 0x60) as char)
+            .collect();
+    }
+
+    // The track header ends with the width and height in 16.16 fixed point.
+    if let Some(tkhd) = find_box(trak, &[b"tkhd"]) {
+        let width = be_u32(tkhd, tkhd.len().saturating_sub(8)).unwrap_or(0) >> 16;
+        let height = be_u32(tkhd, tkhd.len().saturating_sub(4)).unwrap_or(0) >> 16;
+        if width > 0 && height > 0 {
+            track.size = Some((width, height));
+        }
+    }
+
+    // The sample entry has 8 bytes of its own before the `esds` box.
+    track.palette = find_box(entry.get(8..).unwrap_or_default(), &[b"esds"])
+        .and_then(|esds| parse_esds_palette(esds.get(4..)?));
+
+    let stts = find_box(stbl, &[b"stts"]).context(MalformedSnafu { name: "stts" })?;
+    for i in 0..be_u32(stts, 4).unwrap_or(0) as usize {
+        let count = be_u32(stts, 8 + i * 8).context(MalformedSnafu { name: "stts" })?;
+        let delta = be_u32(stts, 12 + i * 8).context(MalformedSnafu { name: "stts" })?;
+        let len = track.durations.len();
+        track.durations.resize(len + count as usize, delta);
+    }
+
+    let stsz = find_box(stbl, &[b"stsz"]).context(MalformedSnafu { name: "stsz" })?;
+    let sample_size = be_u32(stsz, 4).context(MalformedSnafu { name: "stsz" })?;
+    let sample_count = be_u32(stsz, 8).context(MalformedSnafu { name: "stsz" })? as usize;
+    track.sizes = if sample_size != 0 {
+        vec![sample_size; sample_count]
+    } else {
+        (0..sample_count)
+            .map(|i| be_u32(stsz, 12 + i * 4))
+            .collect::<Option<_>>()
+            .context(MalformedSnafu { name: "stsz" })?
+    };
+
+    let stsc = find_box(stbl, &[b"stsc"]).context(MalformedSnafu { name: "stsc" })?;
+    track.chunk_runs = (0..be_u32(stsc, 4).unwrap_or(0) as usize)
+        .map(|i| Some((be_u32(stsc, 8 + i * 12)?, be_u32(stsc, 12 + i * 12)?)))
+        .collect::<Option<_>>()
+        .context(MalformedSnafu { name: "stsc" })?;
+
+    track.chunk_offsets = if let Some(stco) = find_box(stbl, &[b"stco"]) {
+        (0..be_u32(stco, 4).unwrap_or(0) as usize)
+            .map(|i| be_u32(stco, 8 + i * 4).map(u64::from))
+            .collect::<Option<_>>()
+            .context(MalformedSnafu { name: "stco" })?
+    } else {
+        let co64 = find_box(stbl, &[b"co64"]).context(MalformedSnafu { name: "stbl" })?;
+        (0..be_u32(co64, 4).unwrap_or(0) as usize)
+            .map(|i| {
+                Some(u64::from_be_bytes(
+                    co64.get(8 + i * 8..16 + i * 8)?.try_into().unwrap(),
+                ))
+            })
+            .collect::<Option<_>>()
+            .context(MalformedSnafu { name: "co64" })?
+    };
+
+    Ok(Some(track))
+}
+
+/// Compute the file offset of every sample from the chunk tables.
+fn sample_offsets(track: &Track) -> Vec<u64> {
+    let mut offsets = Vec::with_capacity(track.sizes.len());
+    for (chunk_ix, &chunk_offset) in track.chunk_offsets.iter().enumerate() {
+        let chunk = chunk_ix as u32 + 1;
+        let samples_per_chunk = track
+            .chunk_runs
+            .iter()
+            .rev()
+            .find(|(first_chunk, _)| *first_chunk <= chunk)
+            .map_or(0, |(_, samples)| *samples);
+        let mut offset = chunk_offset;
+        for _ in 0..samples_per_chunk {
+            match track.sizes.get(offsets.len()) {
+                Some(&size) => {
+                    offsets.push(offset);
+                    offset += size as u64;
+                }
+                None => return offsets,
+            }
+        }
+    }
+    offsets
+}
+
+/// Find the palette in the decoder specific info of an elementary stream
+/// descriptor. It holds 16 `[0, Y, Cr, Cb]` entries.
+fn parse_esds_palette(mut data: &[u8]) -> Option<[[u8; 3]; 16]> {
+    loop {
+        let (&tag, rest) = data.split_first()?;
+        // Descriptor lengths are variable-length integers.
+        let mut len = 0usize;
+        let mut rest = rest;
+        loop {
+            let (&byte, tail) = rest.split_first()?;
+            rest = tail;
+            len = len << 7 | (byte & 0x7f) as usize;
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+        let contents = rest.get(..len)?;
+        data = match tag {
+            // ES_Descriptor: skip the ID and flags, and any optional fields.
+            0x03 => {
+                let flags = *contents.get(2)?;
+                let mut skip = 3;
+                if flags & 0x80 != 0 {
+                    skip += 2;
+                }
+                if flags & 0x40 != 0 {
+                    skip += 1 + *contents.get(skip)? as usize;
+                }
+                if flags & 0x20 != 0 {
+                    skip += 2;
+                }
+                contents.get(skip..)?
+            }
+            // DecoderConfigDescriptor: skip the fixed-size fields.
+            0x04 => contents.get(13..)?,
+            // DecoderSpecificInfo
+            0x05 => {
+                let mut palette = [[0u8; 3]; 16];
+                for (i, color) in palette.iter_mut().enumerate() {
+                    let entry = contents.get(i * 4..i * 4 + 4)?;
+                    *color = ycrcb_to_rgb(entry[1], entry[2], entry[3]);
+                }
+                return Some(palette);
+            }
+            _ => rest.get(len..)?,
+        };
+    }
+}
+
+fn be_u16(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(
+        data.get(offset..offset + 2)?.try_into().unwrap(),
+    ))
+}
+
+fn be_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(
+        data.get(offset..offset + 4)?.try_into().unwrap(),
+    ))
+}
@@ -0,0 +1,131 @@
+//! Just enough MPEG-2 Program Stream parsing to pick subpicture packs out of
+//! DVD video objects, and to write them back out.
+
+use std::io::{self, Read};
+
+/// DVD packs are always exactly one sector long.
+pub const SECTOR_SIZE: usize = 2048;
+
+/// PTS values are 33 bits long.
+const CLOCK_MASK: u64 = (1 << 33) - 1;
+
+/// Return the subpicture stream number (0-31) carried by this pack, or `None`
+/// if the pack does not contain subpicture data.
+pub fn subpicture_stream(pack: &[u8]) -> Option<u8> {
+    // MPEG-2 pack header, which is 14 bytes plus some stuffing.
+    if pack.len() < 14 || pack[0..4] != [0x00, 0x00, 0x01, 0xba] || pack[4] & 0xc0 != 0x40 {
+        return None;
+    }
+    let pes_start = 14 + (pack[13] & 0x07) as usize;
+
+    // Subpictures live in private stream 1, with the substream ID following
+    // the PES header data.
+    let pes_header = pack.get(pes_start..pes_start + 9)?;
+    if pes_header[0..4] != [0x00, 0x00, 0x01, 0xbd] {
+        return None;
+    }
+    let substream_id = *pack.get(pes_start + 9 + pes_header[8] as usize)?;
+    if (0x20..0x40).contains(&substream_id) {
+        Some(substream_id - 0x20)
+    } else {
+        None
+    }
+}
+
+/// Return the subpicture stream number and PTS of a pack that starts a new
+/// subpicture unit. The PTS is in 90 kHz units.
+pub fn subpicture_pts(pack: &[u8]) -> Option<(u8, u64)> {
+    let stream = subpicture_stream(pack)?;
+    let b = pack.get(pts_position(pack)?..)?.get(..5)?;
+    let pts = ((b[0] as u64 >> 1) & 0x07) << 30
+        | (b[1] as u64) << 22
+        | (b[2] as u64 >> 1) << 15
+        | (b[3] as u64) << 7
+        | (b[4] as u64 >> 1);
+    Some((stream, pts))
+}
+
+/// Add an offset to the PTS of a subpicture pack, if it has one.
+pub fn shift_subpicture_pts(pack: &mut [u8], offset: i64) {
+    if let (Some((_, pts)), Some(position)) = (subpicture_pts(pack), pts_position(pack)) {
+        let pts = (pts as i64 + offset).max(0) as u64;
+        pack[position..position + 5].copy_from_slice(&encode_pts(pts));
+    }
+}
+
+/// Find where the PTS is stored in a pack, if it has one.
+fn pts_position(pack: &[u8]) -> Option<usize> {
+    let pes_start = 14 + (*pack.get(13)? & 0x07) as usize;
+    if pack.get(pes_start + 7)? & 0x80 == 0 {
+        return None;
+    }
+    Some(pes_start + 9)
+}
+
+fn encode_pts(pts: u64) -> [u8; 5] {
+    let pts = pts & CLOCK_MASK;
+    [
+        0x21 | ((pts >> 29) & 0x0e) as u8,
+        (pts >> 22) as u8,
+        0x01 | ((pts >> 14) & 0xfe) as u8,
+        (pts >> 7) as u8,
+        0x01 | ((pts << 1) & 0xfe) as u8,
+    ]
+}
+
+/// Read a program stream sector by sector, appending every pack belonging to
+/// the given subpicture stream to `output`. The result can be decoded just like
+/// the contents of a `.sub` file.
+pub fn extract_subpicture_stream(
+    mut reader: impl Read,
+    stream: u8,
+    output: &mut Vec<u8>,
+) -> io::Result<()> {
+    let mut sector = [0u8; SECTOR_SIZE];
+    loop {
+        match reader.read_exact(&mut sector) {
+            Ok(()) => {}
+            // A trailing partial sector can't hold a complete pack anyway.
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        }
+        if subpicture_stream(&sector) == Some(stream) {
+            output.extend_from_slice(&sector);
+        }
+    }
+}
+
+/// Wrap a subpicture unit in program stream packs, the way it would appear in
+/// a `.sub` file, and append them to `output`. `pts` is in 90 kHz units.
+pub fn write_subpicture_packets(output: &mut Vec<u8>, stream: u8, pts: u64, data: &[u8]) {
+    // A pack header with a zero clock reference.
+    const PACK_HEADER: [u8; 14] = [
+        0x00, 0x00, 0x01, 0xba, 0x44, 0x00, 0x04, 0x00, 0x04, 0x01, 0x01, 0x89, 0xc3, 0xf8,
+    ];
+
+    let mut rest = data;
+    let mut first = true;
+    while first || !rest.is_empty() {
+        // Only the first packet carries the timestamp.
+        let pts = encode_pts(pts);
+        let header_data: &[u8] = if first { &pts } else { &[] };
+        let max_payload = SECTOR_SIZE - PACK_HEADER.len() - 10 - header_data.len();
+        let (payload, tail) = rest.split_at(rest.len().min(max_payload));
+        let pes_len = 3 + header_data.len() + 1 + payload.len();
+
+        output.extend_from_slice(&PACK_HEADER);
+        output.extend_from_slice(&[0x00, 0x00, 0x01, 0xbd]);
+        output.extend_from_slice(&(pes_len as u16).to_be_bytes());
+        output.extend_from_slice(&[
+            0x81,
+            if first { 0x80 } else { 0x00 },
+            header_data.len() as u8,
+        ]);
+        output.extend_from_slice(header_data);
+        output.push(0x20 + stream);
+        output.extend_from_slice(payload);
+
+        rest = tail;
+        first = false;
+    }
+}
@@ -0,0 +1,38 @@
+//! Writing MPL2 subtitles, whose lines look like `[start][end]text` with
+//! times in tenths of a second.
+
+use std::fmt::Write;
+
+use crate::{
+    ocr::Subtitle,
+    writer::{self, Result, SubtitleWriter},
+};
+use subparse::timetypes::TimePoint;
+
+/// Writes subtitles as MPL2.
+pub struct Mpl2Writer;
+
+impl SubtitleWriter for Mpl2Writer {
+    fn write(&self, subtitles: &[Subtitle]) -> Result<Vec<u8>> {
+        let mut output = String::new();
+        for subtitle in subtitles {
+            let text = writer::join_lines(&subtitle.text(), "|");
+            if text.is_empty() {
+                continue;
+            }
+            writeln!(
+                output,
+                "[{}][{}]{}",
+                to_deciseconds(subtitle.time_span.start),
+                to_deciseconds(subtitle.time_span.end),
+                text
+            )
+            .unwrap();
+        }
+        Ok(output.into_bytes())
+    }
+}
+
+fn to_deciseconds(time: TimePoint) -> i64 {
+    (time.msecs().max(0) + 50) / 100
+}
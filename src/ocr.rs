@@ -1,23 +1,56 @@
-use std::{io::Cursor, str::Utf8Error};
+//! Recognizing the text of preprocessed subtitles, in parallel, with Tesseract
+//! or another [`OcrEngine`].
 
-use crate::{opt::Opt, preprocessor::PreprocessedVobSubtitle};
+use std::{
+    collections::BTreeMap,
+    ffi::CString,
+    io::Cursor,
+    iter,
+    str::Utf8Error,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc::{self, Receiver, SyncSender},
+        Arc, Once,
+    },
+    thread,
+};
+
+#[cfg(feature = "cloud")]
+use crate::cloud::GoogleVision;
+use crate::{
+    alto, bidi,
+    cache::{Cache, Recognized},
+    hocr,
+    opt::{Engine, OcrOpt, OutputFormat},
+    preprocessor::{LineImage, Placement, PreprocessedVobSubtitle},
+    punctuation::DASHES,
+    writer,
+};
 use image::{
     codecs::pnm::{PnmSubtype, SampleEncoding},
     DynamicImage, GrayImage,
 };
 use leptess::{
-    leptonica::PixError,
-    tesseract::{TessInitError, TessSetVariableError},
-    LepTess, Variable,
+    capi::TessOcrEngineMode,
+    leptonica::{self, Pix, PixError},
+    tesseract::{TessApi, TessInitError, TessSetVariableError},
+    Variable,
 };
+use log::{info, warn};
 use rayon::prelude::*;
 use scoped_tls_hkt::scoped_thread_local;
 use snafu::{ResultExt, Snafu};
 use subparse::timetypes::TimeSpan;
+use tesseract_plumbing::TessBaseApi;
 
-scoped_thread_local!(static mut TESSERACT: Option<TesseractWrapper>);
+scoped_thread_local!(static mut ENGINE: Option<Box<dyn OcrEngine>>);
+
+/// Every thread fails to set the same config file variables, so only the
+/// first says so.
+static WARN_IGNORED_VARIABLES: Once = Once::new();
 
 #[derive(Debug, Snafu)]
+#[snafu(visibility(pub(crate)))]
 pub enum Error {
     #[snafu(display("Could not build tesseract thread pool: {}", source))]
     BuildThreadPool { source: rayon::ThreadPoolBuildError },
@@ -37,89 +70,601 @@ pub enum Error {
     #[snafu(display("Could not get tesseract text: {}", source))]
     GetText { source: Utf8Error },
 
+    #[cfg(feature = "cloud")]
+    #[snafu(display("No API key given; use --api-key or set GOOGLE_API_KEY"))]
+    MissingApiKey,
+
+    #[cfg(feature = "cloud")]
+    #[snafu(display("OCR engine failed: {}", source))]
+    Engine { source: BoxError },
+
     #[snafu(display("Tesseract not initialized"))]
     TesseractNotInitialized,
+
+    #[snafu(display("Skipped after OCR failed on another subtitle with --strict"))]
+    Skipped,
 }
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
+/// Errors from OCR engines other than Tesseract.
+#[cfg(feature = "cloud")]
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// A recognized subtitle, along with what is known about how it looked.
+pub struct Subtitle {
+    pub time_span: TimeSpan,
+    pub force: bool,
+    pub placement: Placement,
+    pub text_color: [u8; 3],
+    pub lines: Vec<Line>,
+}
+
+impl Subtitle {
+    /// The text of all lines.
+    pub fn text(&self) -> String {
+        self.lines
+            .iter()
+            .map(|line| line.text.as_str())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Whether the lines are in more than one color, as when each speaker has
+    /// their own.
+    pub fn has_speaker_colors(&self) -> bool {
+        let mut colors = self
+            .lines
+            .iter()
+            .filter(|line| !line.text.is_empty())
+            .map(|line| line.color);
+        match colors.next() {
+            Some(first) => colors.any(|color| !writer::similar_colors(first, color)),
+            None => false,
+        }
+    }
+
+    /// Start the lines of each speaker, told apart by their colors, with a
+    /// dialogue dash, unless they already have one.
+    pub fn add_speaker_dashes(&mut self) {
+        if !self.has_speaker_colors() {
+            return;
+        }
+        let mut previous = None;
+        for line in self.lines.iter_mut().filter(|line| !line.text.is_empty()) {
+            let same_speaker =
+                matches!(previous, Some(color) if writer::similar_colors(color, line.color));
+            previous = Some(line.color);
+            if !same_speaker && !line.text.starts_with(DASHES) {
+                line.text.insert_str(0, "- ");
+            }
+        }
+    }
+
+    /// Rewrap the text into lines of at most `max_length` characters, or
+    /// longer for longer words. Runs of lines in the same color and style
+    /// which don't start with a dialogue dash are joined and wrapped together,
+    /// taking the lowest confidence among them.
+    pub fn reflow(&mut self, max_length: usize) {
+        let mut lines: Vec<Line> = Vec::with_capacity(self.lines.len());
+        let mut paragraph: Option<Line> = None;
+        for line in self.lines.drain(..) {
+            if let Some(current) = &mut paragraph {
+                let continues = !line.text.is_empty()
+                    && !line.text.starts_with(DASHES)
+                    && line.italic == current.italic
+                    && writer::similar_colors(line.color, current.color);
+                if continues {
+                    current.text.push(' ');
+                    current.text.push_str(&line.text);
+                    current.confidence = current.confidence.min(line.confidence);
+                    if let Some(markup) = line.markup {
+                        current
+                            .markup
+                            .get_or_insert_with(String::new)
+                            .push_str(&markup);
+                    }
+                    continue;
+                }
+            }
+            lines.extend(
+                paragraph
+                    .take()
+                    .map(|line| line.wrap(max_length))
+                    .into_iter()
+                    .flatten(),
+            );
+            if line.text.is_empty() {
+                lines.push(line);
+            } else {
+                paragraph = Some(line);
+            }
+        }
+        lines.extend(
+            paragraph
+                .map(|line| line.wrap(max_length))
+                .into_iter()
+                .flatten(),
+        );
+        self.lines = lines;
+    }
+
+    /// Tesseract's mean confidence in the text, from 0 to 100, averaged over
+    /// the lines.
+    pub fn confidence(&self) -> i32 {
+        let total: i32 = self.lines.iter().map(|line| line.confidence).sum();
+        total / self.lines.len().max(1) as i32
+    }
+}
+
+/// A recognized line of a subtitle.
+pub struct Line {
+    pub text: String,
+    /// Tesseract's mean confidence in the text, from 0 to 100.
+    pub confidence: i32,
+    /// The most common color of the line's text pixels.
+    pub color: [u8; 3],
+    /// Whether the line was detected to be in italics.
+    pub italic: bool,
+    /// hOCR or ALTO markup for the line, positioned on the screen, if the
+    /// output format needs it.
+    pub markup: Option<String>,
+}
+
+/// Markup with the layout of each line, which some output formats are made
+/// from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Markup {
+    Hocr,
+    Alto,
+}
+
+impl Markup {
+    /// The markup needed for the output format, if any.
+    fn for_output(opt: &OcrOpt) -> Option<Self> {
+        match writer::output_format(opt) {
+            OutputFormat::Hocr => Some(Markup::Hocr),
+            OutputFormat::Alto => Some(Markup::Alto),
+            _ => None,
+        }
+    }
+
+    /// Move the markup of a line image whose top left corner was at `origin`
+    /// to where it was on the screen.
+    fn place_line(self, page: &str, origin: (i32, i32)) -> String {
+        match self {
+            Markup::Hocr => hocr::place_line(page, origin),
+            Markup::Alto => alto::place_line(page, origin),
+        }
+    }
+}
+
+impl Line {
+    /// Split the line into as few lines of at most `max_length` characters as
+    /// possible, breaking between words to make them as even as possible. The
+    /// first line keeps any markup.
+    fn wrap(mut self, max_length: usize) -> Vec<Line> {
+        let mut markup = self.markup.take();
+        let words: Vec<&str> = self.text.split_whitespace().collect();
+        let lengths: Vec<usize> = words.iter().map(|word| word.chars().count()).collect();
+        // Greedily fill lines of a width, giving the number of words on each.
+        let fill = |width: usize| {
+            let mut counts = Vec::new();
+            let mut length = 0;
+            for &word in &lengths {
+                if length > 0 && length + 1 + word <= width {
+                    length += 1 + word;
+                    *counts.last_mut().unwrap() += 1;
+                } else {
+                    length = word;
+                    counts.push(1);
+                }
+            }
+            counts
+        };
+        // Narrow the lines as far as possible without needing more of them.
+        let lines = fill(max_length).len();
+        let mut narrowest = lengths.iter().copied().max().unwrap_or(0);
+        let mut widest = max_length.max(narrowest);
+        while narrowest < widest {
+            let width = (narrowest + widest) / 2;
+            if fill(width).len() > lines {
+                narrowest = width + 1;
+            } else {
+                widest = width;
+            }
+        }
+        let mut words = words.into_iter();
+        fill(widest)
+            .into_iter()
+            .map(|count| Line {
+                text: words.by_ref().take(count).collect::<Vec<_>>().join(" "),
+                confidence: self.confidence,
+                color: self.color,
+                italic: self.italic,
+                markup: markup.take(),
+            })
+            .collect()
+    }
+}
+
+/// Recognize the lines of each subtitle, on `--jobs` threads, reusing the
+/// lines cached by earlier runs. Each subtitle fails on its own, so that one
+/// bad image doesn't lose the rest, except with `--strict`, where the
+/// subtitles not yet started once one fails are [`Error::Skipped`].
 pub fn process(
     vobsubs: Vec<PreprocessedVobSubtitle>,
-    opt: &Opt,
-) -> Result<Vec<Result<(TimeSpan, String)>>> {
+    opt: &OcrOpt,
+) -> Result<Vec<Result<Subtitle>>> {
+    let markup = Markup::for_output(opt);
+    let cache = Cache::new(opt, markup);
+    let lines = vobsubs
+        .iter()
+        .map(|vobsub| vobsub.lines.len())
+        .sum::<usize>();
+    let failed = AtomicBool::new(false);
     std::env::set_var("OMP_THREAD_LIMIT", "1");
-    rayon::ThreadPoolBuilder::new()
+    let subtitles = rayon::ThreadPoolBuilder::new()
+        .num_threads(opt.jobs.unwrap_or(0) as usize)
         .build_scoped(
             |thread| {
-                let mut tesseract = None;
-                TESSERACT.set(&mut tesseract, || thread.run())
+                let mut engine = None;
+                ENGINE.set(&mut engine, || thread.run())
             },
             |pool| {
                 pool.install(|| {
                     vobsubs
                         .into_par_iter()
                         .map(|vobsub| {
-                            let text = vobsub
-                                .images
-                                .into_iter()
-                                .map(|image| {
-                                    TESSERACT.with(|maybe_tesseract| {
-                                        let tesseract = match maybe_tesseract {
-                                            Some(tesseract) => tesseract,
-                                            None => {
-                                                let tesseract = TesseractWrapper::new(
-                                                    opt.tessdata_dir.as_deref(),
-                                                    &opt.lang,
-                                                    &opt.config,
-                                                )?;
-                                                maybe_tesseract.insert(tesseract)
-                                            }
-                                        };
-                                        tesseract.set_image(image, opt.dpi)?;
-                                        tesseract.get_text()
-                                    })
-                                })
-                                .collect::<Result<String>>()?;
-                            Ok((vobsub.time_span, text))
+                            if failed.load(Ordering::Relaxed) {
+                                return SkippedSnafu {}.fail();
+                            }
+                            let subtitle = process_subtitle(vobsub, cache.as_ref(), opt, markup);
+                            if opt.strict && subtitle.is_err() {
+                                failed.store(true, Ordering::Relaxed);
+                            }
+                            subtitle
                         })
-                        .collect::<Vec<Result<(TimeSpan, String)>>>()
+                        .collect::<Vec<Result<Subtitle>>>()
                 })
             },
         )
-        .context(BuildThreadPoolSnafu {})
+        .context(BuildThreadPoolSnafu {})?;
+    log_cache_hits(cache.as_ref(), lines);
+    Ok(subtitles)
+}
+
+/// Subtitles recognized in the background by [`stream`], in order.
+pub struct Stream {
+    receiver: Receiver<(usize, Result<Subtitle>)>,
+    /// Lets another subtitle be started for each one yielded.
+    permits: SyncSender<()>,
+    /// Subtitles which finished before one ahead of them.
+    finished: BTreeMap<usize, Result<Subtitle>>,
+    next: usize,
 }
 
+impl Iterator for Stream {
+    type Item = Result<Subtitle>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(subtitle) = self.finished.remove(&self.next) {
+                self.next += 1;
+                // Fails once every subtitle has been started.
+                let _ = self.permits.send(());
+                return Some(subtitle);
+            }
+            let (index, subtitle) = self.receiver.recv().ok()?;
+            self.finished.insert(index, subtitle);
+        }
+    }
+}
+
+/// Recognize subtitles like [`process`], but on background threads, yielding
+/// each as soon as it and those before it are done. At most twice as many
+/// subtitles as there are threads are taken from `vobsubs` ahead of the one to
+/// be yielded next, so memory doesn't grow with the length of the input.
+/// Dropping the stream stops recognizing the rest.
+pub fn stream<I>(vobsubs: I, opt: Arc<OcrOpt>) -> Result<Stream>
+where
+    I: IntoIterator<Item = PreprocessedVobSubtitle>,
+    I::IntoIter: Send + 'static,
+{
+    std::env::set_var("OMP_THREAD_LIMIT", "1");
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(opt.jobs.unwrap_or(0) as usize)
+        .spawn_handler(|thread| {
+            thread::Builder::new().spawn(|| {
+                let mut engine = None;
+                ENGINE.set(&mut engine, || thread.run())
+            })?;
+            Ok(())
+        })
+        .build()
+        .context(BuildThreadPoolSnafu {})?;
+    let window = pool.current_num_threads() * 2;
+    let (sender, receiver) = mpsc::sync_channel(window);
+    let (permits, permit) = mpsc::sync_channel(window);
+    for _ in 0..window {
+        permits.send(()).unwrap();
+    }
+    // Waiting for a permit fails once the stream is dropped, which stops the
+    // rest.
+    let mut vobsubs = vobsubs.into_iter();
+    let vobsubs = iter::from_fn(move || {
+        permit.recv().ok()?;
+        vobsubs.next()
+    });
+    // The pool's threads exit once this is done, since the pool is dropped.
+    pool.spawn(move || {
+        let markup = Markup::for_output(&opt);
+        let cache = Cache::new(&opt, markup);
+        let lines = AtomicUsize::new(0);
+        // As does sending.
+        let _ = vobsubs.enumerate().par_bridge().try_for_each_with(
+            sender,
+            |sender, (index, vobsub)| {
+                lines.fetch_add(vobsub.lines.len(), Ordering::Relaxed);
+                let subtitle = process_subtitle(vobsub, cache.as_ref(), &opt, markup);
+                sender.send((index, subtitle))
+            },
+        );
+        log_cache_hits(cache.as_ref(), lines.into_inner());
+    });
+    Ok(Stream {
+        receiver,
+        permits,
+        finished: BTreeMap::new(),
+        next: 0,
+    })
+}
+
+/// Recognize each line of a subtitle.
+fn process_subtitle(
+    vobsub: PreprocessedVobSubtitle,
+    cache: Option<&Cache>,
+    opt: &OcrOpt,
+    markup: Option<Markup>,
+) -> Result<Subtitle> {
+    let lines = vobsub
+        .lines
+        .into_iter()
+        .map(|line| process_line(line, cache, opt, markup))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(Subtitle {
+        time_span: vobsub.time_span,
+        force: vobsub.force,
+        placement: vobsub.placement,
+        text_color: vobsub.text_color,
+        lines,
+    })
+}
+
+fn log_cache_hits(cache: Option<&Cache>, lines: usize) {
+    if let Some(cache) = cache {
+        if cache.hits() > 0 {
+            info!(
+                "Reused the OCR results of {} of {} lines from the cache",
+                cache.hits(),
+                lines
+            );
+        }
+    }
+}
+
+/// Recognize a line of a subtitle, unless its result is already cached.
+fn process_line(
+    LineImage {
+        image,
+        origin,
+        color,
+        italic,
+    }: LineImage,
+    cache: Option<&Cache>,
+    opt: &OcrOpt,
+    markup: Option<Markup>,
+) -> Result<Line> {
+    let cached = cache.map(|cache| (cache, cache.key(&image)));
+    let recognized = match cached.and_then(|(cache, key)| cache.get(key)) {
+        Some(recognized) => recognized,
+        None => {
+            let recognized = ENGINE.with(|maybe_engine| {
+                let engine = match maybe_engine {
+                    Some(engine) => engine,
+                    None => maybe_engine.insert(new_engine(opt)?),
+                };
+                recognize(engine.as_mut(), image, opt, markup)
+            })?;
+            if let Some((cache, key)) = cached {
+                cache.put(key, &recognized);
+            }
+            recognized
+        }
+    };
+    Ok(Line {
+        text: recognized
+            .text
+            .lines()
+            .map(bidi::fix_line)
+            .collect::<Vec<_>>()
+            .join("\n"),
+        confidence: recognized.confidence,
+        color,
+        italic,
+        markup: markup
+            .zip(recognized.markup)
+            .map(|(markup, page)| markup.place_line(&page, origin)),
+    })
+}
+
+/// Recognize a line image, marking its doubtful words if asked to.
+fn recognize(
+    engine: &mut dyn OcrEngine,
+    image: GrayImage,
+    opt: &OcrOpt,
+    markup: Option<Markup>,
+) -> Result<Recognized> {
+    engine.set_image(image, opt.dpi)?;
+    let mut text = engine.get_text()?.trim_end().to_owned();
+    if let Some(min_confidence) = opt.mark_words_below {
+        text = mark_words(&engine.words()?, min_confidence, &opt.word_marker);
+    }
+    let markup = match markup {
+        Some(markup) => engine.get_markup(markup)?,
+        None => None,
+    };
+    Ok(Recognized {
+        text,
+        confidence: engine.confidence(),
+        markup,
+    })
+}
+
+/// An OCR backend. Each thread creates one and gives it one line image at a
+/// time.
+pub trait OcrEngine {
+    /// Set the line image to recognize.
+    fn set_image(&mut self, image: GrayImage, dpi: i32) -> Result<()>;
+
+    /// Recognize the text of the image.
+    fn get_text(&mut self) -> Result<String>;
+
+    /// Get the mean confidence of the last recognized text, from 0 to 100.
+    fn confidence(&self) -> i32;
+
+    /// Get each word of the last recognized text along with its confidence.
+    ///
+    /// Engines which can't tell words apart give every word the confidence of
+    /// the whole text.
+    fn words(&mut self) -> Result<Vec<(String, f32)>> {
+        let confidence = self.confidence() as f32;
+        Ok(self
+            .get_text()?
+            .split_whitespace()
+            .map(|word| (word.to_owned(), confidence))
+            .collect())
+    }
+
+    /// Get the last recognized text as a page of hOCR or ALTO, if the engine
+    /// supports it.
+    fn get_markup(&mut self, _markup: Markup) -> Result<Option<String>> {
+        Ok(None)
+    }
+}
+
+/// Create the OCR engine selected with `--engine`.
+fn new_engine(opt: &OcrOpt) -> Result<Box<dyn OcrEngine>> {
+    match opt.engine {
+        Engine::Tesseract => Ok(Box::new(TesseractWrapper::new(
+            opt.tessdata_dir.as_deref(),
+            opt.tesseract_language(),
+            opt.oem,
+            opt.psm,
+            &opt.config_file_variables(),
+            &opt.tesseract_config(),
+        )?)),
+        #[cfg(feature = "cloud")]
+        Engine::GoogleVision => Ok(Box::new(GoogleVision::new(opt)?)),
+    }
+}
+
+/// Page segmentation modes to try when the chosen one finds no text: 6 is a
+/// uniform block of text and 13 a raw line, bypassing Tesseract's hacks.
+const FALLBACK_PSMS: [u32; 2] = [6, 13];
+
 struct TesseractWrapper {
-    leptess: LepTess,
+    api: TessApi,
+    /// The page segmentation mode to try first.
+    psm: u32,
+    /// The current image and its DPI, kept to recognize it again in another
+    /// mode.
+    image: Option<(Pix, i32)>,
 }
 
 impl TesseractWrapper {
     fn new(
         datapath: Option<&str>,
         language: impl AsRef<str>,
+        oem: Option<u32>,
+        psm: u32,
+        file_config: &[(Variable, String)],
         config: &[(Variable, String)],
     ) -> Result<Self> {
-        let mut leptess = LepTess::new(datapath, language.as_ref()).context(InitializeSnafu {})?;
+        // The engine mode can only be chosen when initializing, which
+        // `TessApi::new` does without one, so initialize it here instead,
+        // loading the traineddata only once.
+        let mut api = TessApi {
+            raw: TessBaseApi::create(),
+        };
+        let datapath = datapath.map(|path| CString::new(path).unwrap());
+        let language = CString::new(language.as_ref()).unwrap();
+        match oem {
+            Some(oem) => api.raw.init_4(
+                datapath.as_deref(),
+                Some(&language),
+                oem as TessOcrEngineMode,
+            ),
+            None => api.raw.init_2(datapath.as_deref(), Some(&language)),
+        }
+        .map_err(|_| TessInitError { code: -1 })
+        .context(InitializeSnafu {})?;
+        let mut tesseract = Self {
+            api,
+            psm,
+            image: None,
+        };
         // Disable learning by default, though a user could re-enable this
         // option with `-c`. We turn this off since we are are multithreading,
         // so this option would result in non-deterministic output.
-        leptess
-            .set_variable(leptess::Variable::ClassifyEnableLearning, "0")
-            .context(SetVariableSnafu {})?;
-        // 7 is PSM_SINGLE_LINE. We have preprocessed the input into individual
-        // lines, and telling Tesseract this fact greatly improves accuracy.
-        leptess
-            .set_variable(leptess::Variable::TesseditPagesegMode, "7")
-            .context(SetVariableSnafu {})?;
+        tesseract.set_variable(Variable::ClassifyEnableLearning, "0")?;
+        tesseract.set_variable(Variable::TesseditPagesegMode, &psm.to_string())?;
+        // Config files are meant for initialization, so they can have
+        // variables which can't be set anymore.
+        let ignored: Vec<_> = file_config
+            .iter()
+            .filter(|(key, value)| tesseract.set_variable(*key, value).is_err())
+            .map(|(key, _)| key.as_cstr().to_string_lossy())
+            .collect();
+        if !ignored.is_empty() {
+            WARN_IGNORED_VARIABLES.call_once(|| {
+                warn!(
+                    "Ignoring config file variables which Tesseract only reads when \
+                     initializing: {}",
+                    ignored.join(", ")
+                )
+            });
+        }
         // Add user options.
         for (key, value) in config {
-            leptess
-                .set_variable(*key, value)
-                .context(SetVariableSnafu {})?;
+            tesseract.set_variable(*key, value)?;
         }
-        Ok(Self { leptess })
+        Ok(tesseract)
     }
 
+    fn set_variable(&mut self, name: Variable, value: &str) -> Result<()> {
+        self.api
+            .raw
+            .set_variable(name.as_cstr(), &CString::new(value).unwrap())
+            .map_err(|_| TessSetVariableError())
+            .context(SetVariableSnafu {})
+    }
+
+    /// Recognize the current image again with another page segmentation mode.
+    fn get_text_with_psm(&mut self, psm: u32) -> Result<String> {
+        self.set_variable(Variable::TesseditPagesegMode, &psm.to_string())?;
+        // Setting the image again clears the previous results.
+        if let Some((pix, dpi)) = &self.image {
+            self.api.set_image(pix);
+            self.api.set_source_resolution(*dpi);
+        }
+        let text = self.api.get_utf8_text().context(GetTextSnafu {});
+        self.set_variable(Variable::TesseditPagesegMode, &self.psm.to_string())?;
+        text
+    }
+}
+
+impl OcrEngine for TesseractWrapper {
     /// Set the tesseract image to the given image's contents.
     fn set_image(&mut self, image: GrayImage, dpi: i32) -> Result<()> {
         let mut bytes: Cursor<Vec<u8>> = Cursor::new(Vec::new());
@@ -129,15 +674,84 @@ impl TesseractWrapper {
                 image::ImageOutputFormat::Pnm(PnmSubtype::Graymap(SampleEncoding::Binary)),
             )
             .context(WriteImageSnafu {})?;
-        self.leptess
-            .set_image_from_mem(bytes.get_ref())
-            .context(SetImageSnafu {})?;
-        self.leptess.set_source_resolution(dpi);
+        let pix = leptonica::pix_read_mem(bytes.get_ref()).context(SetImageSnafu {})?;
+        self.api.set_image(&pix);
+        self.api.set_source_resolution(dpi);
+        self.image = Some((pix, dpi));
         Ok(())
     }
 
-    /// Get text.
+    /// Get text, trying the fallback page segmentation modes if the chosen
+    /// one finds none.
     fn get_text(&mut self) -> Result<String> {
-        self.leptess.get_utf8_text().context(GetTextSnafu {})
+        let text = self.api.get_utf8_text().context(GetTextSnafu {})?;
+        if !text.trim().is_empty() {
+            return Ok(text);
+        }
+        for psm in FALLBACK_PSMS {
+            if psm == self.psm {
+                continue;
+            }
+            let text = self.get_text_with_psm(psm)?;
+            if !text.trim().is_empty() {
+                info!(
+                    "Page segmentation mode {} found no text in a line, but mode {} did",
+                    self.psm, psm
+                );
+                return Ok(text);
+            }
+        }
+        Ok(text)
+    }
+
+    /// Get the mean confidence of the last recognized text.
+    fn confidence(&self) -> i32 {
+        self.api.mean_text_conf()
+    }
+
+    /// Read the words from Tesseract's TSV output, which has a row for each
+    /// word and its confidence.
+    fn words(&mut self) -> Result<Vec<(String, f32)>> {
+        let tsv = self.api.get_tsv_text(0).context(GetTextSnafu {})?;
+        Ok(tsv
+            .lines()
+            .filter_map(|row| {
+                // level, page, block, paragraph, line, word, left, top, width,
+                // height, confidence, text
+                let fields: Vec<&str> = row.splitn(12, '\t').collect();
+                match fields[..] {
+                    ["5", .., confidence, word] if !word.trim().is_empty() => {
+                        Some((word.to_owned(), confidence.parse().unwrap_or(0.0)))
+                    }
+                    _ => None,
+                }
+            })
+            .collect())
     }
+
+    /// Get the last recognized text as hOCR or ALTO.
+    fn get_markup(&mut self, markup: Markup) -> Result<Option<String>> {
+        match markup {
+            Markup::Hocr => self.api.get_hocr_text(0),
+            Markup::Alto => self.api.get_alto_text(0),
+        }
+        .map(Some)
+        .context(GetTextSnafu {})
+    }
+}
+
+/// Join words back into a line of text, wrapping each word with a confidence
+/// below `min_confidence` in `marker`, where `{}` stands for the word.
+fn mark_words(words: &[(String, f32)], min_confidence: i32, marker: &str) -> String {
+    words
+        .iter()
+        .map(|(word, confidence)| {
+            if *confidence < min_confidence as f32 {
+                marker.replace("{}", word)
+            } else {
+                word.clone()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
 }
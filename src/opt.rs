@@ -1,8 +1,14 @@
+//! The command line options, which also configure the library.
+
+use crate::{fixes::FixList, recase, replace};
 use clap::{crate_description, crate_name, crate_version};
-use clap::{Parser, ValueHint};
+use clap::{ArgAction, Args, CommandFactory, Parser, Subcommand, ValueEnum, ValueHint};
+use clap_complete::Shell;
 use leptess::Variable;
-use snafu::Snafu;
-use std::path::PathBuf;
+use log::{warn, LevelFilter};
+use snafu::{ensure, OptionExt, ResultExt, Snafu};
+use std::{convert::Infallible, env, ffi::OsString, fmt, fs, io, ops::Range, path::PathBuf};
+use subparse::timetypes::{TimeDelta, TimePoint};
 
 #[derive(Debug, Snafu)]
 enum Error {
@@ -11,19 +17,200 @@ enum Error {
 
     #[snafu(display("Invalid tesseract variable name: {}", value))]
     TesseractVariableName { value: String },
+
+    #[snafu(display("Could not read Tesseract config file {}: {}", filename.display(), source))]
+    ReadConfigFile {
+        filename: PathBuf,
+        source: io::Error,
+    },
+
+    #[snafu(display("Expected 16 comma-separated hex RGB colors: {}", value))]
+    Palette { value: String },
+
+    #[snafu(display(
+        "Expected custom colors like `tridx=1000,colors=000000,ffffff,000000,000000`: {}",
+        value
+    ))]
+    CustomColors { value: String },
+
+    #[snafu(display("Invalid time offset: {}", value))]
+    Offset { value: String },
+
+    #[snafu(display("Expected a factor greater than 0: {}", value))]
+    Stretch { value: String },
+
+    #[snafu(display("Expected two frame rates like `25:23.976`: {}", value))]
+    FramerateConversion { value: String },
+
+    #[snafu(display(
+        "Expected two different times and where they should be, in the same order, like `1:02=1:04.5,1:30:00=1:32:10`: {}",
+        value
+    ))]
+    Sync { value: String },
+
+    #[snafu(display("Expected a duration like `[[HH:]MM:]SS[.mmm]`: {}", value))]
+    Duration { value: String },
+
+    #[snafu(display("Expected a threshold from 0 to 1, or `auto`: {}", value))]
+    Threshold { value: String },
+
+    #[snafu(display("Expected a threshold from 0 to 1: {}", value))]
+    RetryThreshold { value: String },
+
+    #[snafu(display("Expected four margins like `top,bottom,left,right`: {}", value))]
+    Crop { value: String },
+
+    #[snafu(display(
+        "Expected a name with `{{part}}` and `{{index}}` or `{{start}}`, and no other `{{...}}`: {}",
+        value
+    ))]
+    DumpName { value: String },
+
+    #[snafu(display(
+        "Expected a name with `{{stem}}`, and no `{{...}}` other than `{{lang}}`: {}",
+        value
+    ))]
+    OutputName { value: String },
+
+    #[snafu(display("Expected a rectangle like `x,y,width,height`: {}", value))]
+    Rect { value: String },
+
+    #[snafu(display("Expected a size like `4x4`: {}", value))]
+    RegionSize { value: String },
+
+    #[snafu(display("Expected an index or a range of indices like `120..180`: {}", value))]
+    IndexRange { value: String },
+
+    #[snafu(display(
+        "Expected erode, dilate, open or close, and an optional radius like `open:1`: {}",
+        value
+    ))]
+    Morph { value: String },
+
+    #[snafu(display("Invalid frame rate: {}", value))]
+    Fps { value: String },
+
+    #[cfg(feature = "cloud")]
+    #[snafu(display("Invalid request rate: {}", value))]
+    Rate { value: String },
+
+    #[snafu(display("Word marker has no `{{}}` for the word: {}", value))]
+    WordMarker { value: String },
+
+    #[snafu(display("Unknown character encoding: {}", value))]
+    Encoding { value: String },
 }
 
 type Result<T, E = Error> = std::result::Result<T, E>;
 
 #[derive(Parser, Debug)]
-#[clap(name = crate_name!(), about = crate_description!(), version = crate_version!())]
+#[clap(
+    name = crate_name!(),
+    about = crate_description!(),
+    version = crate_version!(),
+    arg_required_else_help = true,
+    after_help = "Without a subcommand, `ocr` is run, so `vobsubocr -l eng movie.idx` is \
+                  `vobsubocr ocr -l eng movie.idx`.\n\n\
+                  Exit codes: 1 for other errors, 2 for invalid arguments, 3 when inputs \
+                  can't be read, 4 when OCR fails, 5 when outputs can't be written."
+)]
 pub struct Opt {
+    #[clap(subcommand)]
+    pub command: Command,
+
+    /// Log more: `-v` for the progress of each subtitle, `-vv` and `-vvv`
+    /// for debugging. Takes the place of `RUST_LOG`.
+    #[clap(short = 'v', long, action = ArgAction::Count, global = true)]
+    pub verbose: u8,
+
+    /// Log less: `-q` for only errors, `-qq` for nothing at all.
+    #[clap(short = 'q', long, action = ArgAction::Count, global = true, conflicts_with = "verbose")]
+    pub quiet: u8,
+}
+
+impl Opt {
+    /// Parse the command line, running `ocr` when no subcommand is given, the
+    /// way vobsubocr was run before it had subcommands.
+    pub fn parse_with_default_command() -> Self {
+        let mut args: Vec<OsString> = env::args_os().collect();
+        let command = Self::command();
+        // `-v` and `-q` may come before the subcommand.
+        let first = (1..args.len()).find(|&i| !is_log_option(&args[i]));
+        if let Some(first) = first {
+            let named = matches!(args[first].to_str(), Some(arg)
+                if matches!(arg, "help" | "-h" | "--help" | "-V" | "--version")
+                    || command.get_subcommands().any(|subcommand| subcommand.get_name() == arg));
+            if !named {
+                args.insert(first, "ocr".into());
+            }
+        }
+        Self::parse_from(args)
+    }
+
+    /// The level to log at, if chosen with `--verbose` or `--quiet`.
+    pub fn log_level(&self) -> Option<LevelFilter> {
+        match (self.verbose, self.quiet) {
+            (0, 0) => None,
+            (0, 1) => Some(LevelFilter::Error),
+            (0, _) => Some(LevelFilter::Off),
+            (1, _) => Some(LevelFilter::Info),
+            (2, _) => Some(LevelFilter::Debug),
+            _ => Some(LevelFilter::Trace),
+        }
+    }
+}
+
+/// Whether an argument is `--verbose`, `--quiet` or their short forms, like
+/// `-vv`.
+fn is_log_option(arg: &OsString) -> bool {
+    match arg.to_str() {
+        Some("--verbose" | "--quiet") => true,
+        Some(arg) => {
+            arg.len() > 1 && arg.starts_with('-') && arg[1..].chars().all(|c| c == 'v' || c == 'q')
+        }
+        None => false,
+    }
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Recognize the text of subtitle images and write it as text subtitles.
+    Ocr(Box<OcrOpt>),
+    /// Convert subtitle images to another image-based format without OCR.
+    Convert(ConvertOpt),
+    /// List the tracks of each input, with their languages, delays, numbers
+    /// of subtitles and times, and the palette.
+    Info(InfoOpt),
+    /// Print a script completing vobsubocr's arguments in a shell, including
+    /// the languages given to `-l` from the installed traineddata files.
+    Completions(CompletionsOpt),
+}
+
+#[derive(Args, Debug)]
+pub struct CompletionsOpt {
+    /// Shell to complete arguments in.
+    #[clap(value_enum)]
+    pub shell: Shell,
+
+    /// Path to Tesseract's tessdata directory, to list the languages
+    /// installed there instead of in the usual places.
+    #[clap(short = 'D', long, value_hint = ValueHint::DirPath)]
+    pub tessdata_dir: Option<PathBuf>,
+}
+
+/// Options of the `ocr` subcommand. Programs using vobsubocr as a library can
+/// parse them from arguments like the command line's with
+/// [`Parser::try_parse_from`].
+#[derive(Parser, Debug)]
+pub struct OcrOpt {
     /// Threshold for subtitle image binarization.
     ///
     /// Must be between 0.0 and 1.0. Only pixels with luminance above the
-    /// threshold will be considered text pixels for OCR.
-    #[clap(short = 't', long, default_value = "0.6")]
-    pub threshold: f32,
+    /// threshold will be considered text pixels for OCR. With `auto`, each
+    /// subtitle gets the threshold best separating its text from its outline,
+    /// found with Otsu's method.
+    #[clap(short = 't', long, value_parser = parse_threshold, default_value = "0.6")]
+    pub threshold: Threshold,
 
     /// DPI of subtitle images.
     ///
@@ -38,17 +225,291 @@ pub struct Opt {
     #[clap(short = 'b', long, default_value = "10")]
     pub border: u32,
 
+    /// Ignore anything within these margins of the screen, in pixels, where
+    /// some captures have garbage rows or columns.
+    #[clap(long, value_name = "TOP,BOTTOM,LEFT,RIGHT", value_parser = parse_crop)]
+    pub crop: Option<Crop>,
+
+    /// Ignore anything within this rectangle of the screen, in pixels, such
+    /// as a broadcaster's logo. Repeat to ignore several.
+    #[clap(
+        long,
+        value_name = "X,Y,WIDTH,HEIGHT",
+        value_parser = parse_rect,
+        number_of_values = 1
+    )]
+    pub exclude_rect: Vec<Rect>,
+
+    /// Remove specks of up to this many touching text pixels before finding
+    /// the lines of text.
+    ///
+    /// Dithering and compression artifacts can otherwise be read as
+    /// punctuation or merge with the text.
+    #[clap(long, value_name = "PIXELS")]
+    pub despeckle: Option<usize>,
+
+    /// How to find the lines of text in subtitle images.
+    #[clap(long, value_enum, default_value = "scanlines")]
+    pub segmentation: Segmentation,
+
+    /// Join lines of text separated by gaps of up to this many empty
+    /// scanlines, so that accents, umlauts and descenders which don't touch
+    /// their letters stay on their line.
+    #[clap(long, value_name = "PIXELS", default_value = "0")]
+    pub join_gap: usize,
+
+    /// Drop lines of text narrower or shorter than this, given as `WxH` in
+    /// pixels, so that specks don't get read as stray punctuation.
+    #[clap(long, value_name = "WxH", value_parser = parse_region_size)]
+    pub min_region: Option<(usize, usize)>,
+
+    /// Straighten lines of text rotated by up to 5 degrees before OCR, as
+    /// happens with some cropped or telecined sources.
+    #[clap(long)]
+    pub deskew: bool,
+
+    /// Thin or thicken the text of each line image before OCR.
+    ///
+    /// Given as an operation, `erode` or `dilate` to thin or thicken the text,
+    /// `open` to erode and then dilate it, removing noise, or `close` to
+    /// dilate and then erode it, closing gaps in broken strokes, optionally
+    /// followed by a radius in pixels, like `open:1`. Repeat to apply several
+    /// operations in order.
+    #[clap(long, value_parser = parse_morph, number_of_values = 1)]
+    pub morph: Vec<Morph>,
+
+    /// Detect lines in italics from the slant of their letters, and mark them
+    /// as such in SRT, WebVTT and ASS output.
+    #[clap(long)]
+    pub italics: bool,
+
+    /// Start the lines of each speaker with a dialogue dash in subtitles whose
+    /// lines are in different colors, as when each speaker has their own.
+    #[clap(long)]
+    pub speaker_dashes: bool,
+
+    /// Rewrap the text of each subtitle into lines of at most this many
+    /// characters, as evenly as possible, instead of keeping the lines of its
+    /// image. Dialogue lines starting with a dash, and lines in a different
+    /// color or style, are kept apart.
+    #[clap(long, value_name = "CHARS", value_parser = clap::value_parser!(u32).range(1..))]
+    pub max_line_length: Option<u32>,
+
     /// Output subtitle file; stdout if not present.
     #[clap(short = 'o', long, value_parser, value_hint = ValueHint::FilePath)]
     pub output: Option<PathBuf>,
 
+    /// Also write the forced subtitles alone to this file, numbered on their
+    /// own, in the format its extension is for or else that of the output.
+    #[clap(
+        long,
+        value_parser,
+        value_hint = ValueHint::FilePath,
+        conflicts_with_all = ["output_archive", "forced_only", "no_forced"]
+    )]
+    pub forced_output: Option<PathBuf>,
+
+    /// Warn about subtitles whose OCR confidence, from 0 to 100, is below
+    /// this.
+    ///
+    /// Each subtitle's confidence is the mean of Tesseract's confidence in its
+    /// lines. The confidence of every subtitle is logged with `-v`.
+    #[clap(long, value_parser = clap::value_parser!(i32).range(0..=100))]
+    pub min_confidence: Option<i32>,
+
+    /// Leave subtitles below `--min-confidence` out of the output.
+    #[clap(long, requires = "min_confidence")]
+    pub drop_low_confidence: bool,
+
+    /// Show each subtitle below `--min-confidence` in the terminal along with
+    /// its image after OCR, to accept, edit or drop it before it is written.
+    #[clap(long, requires = "min_confidence")]
+    pub interactive: bool,
+
+    /// Fail without writing any subtitles if OCR fails on any subtitle image,
+    /// instead of leaving it out. OCR stops at the first failure, which is
+    /// reported with its index, as counted by `--only`.
+    #[clap(long)]
+    pub strict: bool,
+
+    /// Exit with 0 even if OCR fails on some subtitle images, which are left
+    /// out.
+    #[clap(long, conflicts_with = "strict")]
+    pub lax: bool,
+
+    /// When to take the darkest colors of subtitles as the text instead of the
+    /// brightest, for discs with dark text in a light outline.
+    #[clap(long, value_enum, default_value = "never")]
+    pub invert: Invert,
+
+    /// Keep the anti-aliasing of subtitle images as shades of gray instead of
+    /// binarizing them to black and white.
+    ///
+    /// Text is still told apart from its outline with `--threshold`, but its
+    /// smoother edges can suit Tesseract's LSTM engine better.
+    #[clap(long)]
+    pub grayscale: bool,
+
+    /// Also run OCR on a grayscale rendering of each subtitle, or a binarized
+    /// one with `--grayscale`, and keep whichever result is more confident.
+    ///
+    /// Some fonts are recognized far better without hard thresholding, at the
+    /// cost of running OCR twice.
+    #[clap(long)]
+    pub grayscale_pass: bool,
+
+    /// Run OCR again on subtitles whose confidence is below this, binarizing
+    /// them at each of `--retry-thresholds` and keeping the most confident
+    /// result.
+    #[clap(long, value_name = "CONFIDENCE", value_parser = clap::value_parser!(i32).range(0..=100))]
+    pub retry_below: Option<i32>,
+
+    /// Binarization thresholds from 0 to 1 to try for subtitles below
+    /// `--retry-below`.
+    #[clap(
+        long,
+        value_name = "THRESHOLD",
+        value_delimiter = ',',
+        value_parser = parse_retry_threshold,
+        default_values_t = vec![0.45, 0.75],
+        requires = "retry_below"
+    )]
+    pub retry_thresholds: Vec<f32>,
+
+    /// Write a subtitle file for each input into a zip archive, instead of
+    /// joining them into one output.
+    ///
+    /// Files are named after the input, the `--track` or the OCR language,
+    /// and the output format, such as `movie.en.srt`; see `--output-name`.
+    #[clap(long, value_parser, value_hint = ValueHint::FilePath, conflicts_with = "output")]
+    pub output_archive: Option<PathBuf>,
+
+    /// Write a subtitle file for each input next to it, instead of joining
+    /// them into one output, as when giving many inputs like `*.idx`.
+    ///
+    /// Files are named by `--output-name`. The subtitles of every input go
+    /// through OCR together, keeping every thread busy.
+    #[clap(long, conflicts_with_all = ["output", "output_archive", "forced_output"])]
+    pub separate_outputs: bool,
+
+    /// Write a subtitle file next to each input, named the way media servers
+    /// like Plex and Jellyfin expect: `movie.en.srt`, with the language's
+    /// two-letter code where it has one.
+    ///
+    /// The forced subtitles of each input are also written on their own, to
+    /// `movie.en.forced.srt`. With `--forced-only`, that is the only file.
+    #[clap(
+        long,
+        conflicts_with_all = ["output", "output_archive", "forced_output", "output_name"]
+    )]
+    pub auto_output: bool,
+
+    /// Name of each file written by `--separate-outputs` or into
+    /// `--output-archive`, without its extension.
+    ///
+    /// `{stem}` is replaced with the input's name without its extension, and
+    /// `{lang}` with the `--track` or else the OCR language. Names may hold
+    /// directories, which for `--separate-outputs` are relative to the input's.
+    #[clap(long, value_name = "TEMPLATE", value_parser = parse_output_name, default_value = "{stem}.{lang}")]
+    pub output_name: String,
+
+    /// Search directories given as inputs, and the directories inside them,
+    /// for idx files to read, such as to convert a whole library at once.
+    ///
+    /// Each gets its own subtitle file, as with `--separate-outputs`, unless
+    /// `--output-archive` is given.
+    #[clap(long, conflicts_with_all = ["output", "forced_output"])]
+    pub recursive: bool,
+
+    /// Watch the directories given as inputs, such as the drop folder of a
+    /// ripper, converting each idx file with its sub file once both are there
+    /// and have stopped changing, until interrupted.
+    ///
+    /// Each gets its own subtitle file, as with `--separate-outputs`. With
+    /// `--recursive`, the directories inside them are watched too. Use `-v`
+    /// to log each file converted.
+    #[clap(
+        long,
+        conflicts_with_all = ["output", "output_archive", "forced_output", "interactive", "analyze"]
+    )]
+    pub watch: bool,
+
+    /// Output subtitle format.
+    ///
+    /// Defaults to the format matching the extension of the `--output` file,
+    /// or SRT. ASS output keeps each subtitle's position on the screen and
+    /// text color, and WebVTT output keeps its position.
+    #[clap(short = 'f', long, value_enum)]
+    pub format: Option<OutputFormat>,
+
+    /// Character encoding of the output: `utf8`, `utf8-bom`, `utf16le`,
+    /// `utf16be`, or a legacy encoding such as `cp1252`, `iso-8859-2` or
+    /// `shift_jis`, for players and tools which need one.
+    ///
+    /// Characters the encoding lacks are written as `?`. UTF-16 starts with a
+    /// byte order mark. JSON, hOCR and ALTO output are always UTF-8.
+    #[clap(long, value_parser = parse_encoding, default_value = "utf8")]
+    pub encoding: TextEncoding,
+
+    /// End lines with CR LF, as on Windows, for players and editors which
+    /// need it.
+    #[clap(long)]
+    pub crlf: bool,
+
+    /// Frame rate used to convert times to frame numbers for MicroDVD and
+    /// Spruce STL output.
+    #[clap(long, value_parser = parse_fps, default_value = "23.976")]
+    pub fps: f64,
+
+    /// Number of subtitles to process at once; one per CPU by default.
+    ///
+    /// Each OCR thread has its own Tesseract instance, so lowering this saves
+    /// memory.
+    #[clap(short = 'j', long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub jobs: Option<u32>,
+
+    /// OCR engine to recognize text with.
+    #[clap(long, value_enum, default_value = "tesseract")]
+    pub engine: Engine,
+
+    /// API key for a cloud OCR engine. Defaults to the `GOOGLE_API_KEY`
+    /// environment variable.
+    #[cfg(feature = "cloud")]
+    #[clap(long)]
+    pub api_key: Option<String>,
+
+    /// How many requests to send a cloud OCR engine per second at most.
+    #[cfg(feature = "cloud")]
+    #[clap(long, value_parser = parse_rate, default_value = "10")]
+    pub requests_per_second: f64,
+
     /// Path to Tesseract's tessdata directory.
     #[clap(short = 'D', long, value_hint = ValueHint::DirPath)]
     pub tessdata_dir: Option<String>,
 
     /// The Tesseract language(s) to use for OCR.
-    #[clap(short = 'l', long)]
-    pub lang: String,
+    ///
+    /// Several languages can be given as `eng+fra` or by repeating `-l`, for
+    /// subtitles mixing languages. Defaults to the language of the subtitle
+    /// track, if the input declares one.
+    #[clap(short = 'l', long, number_of_values = 1)]
+    pub lang: Vec<String>,
+
+    /// Tesseract OCR engine mode.
+    ///
+    /// 0 is the legacy engine, 1 the LSTM neural network, 2 both combined and
+    /// 3 whatever is available, which is the default. The legacy engine needs
+    /// traineddata files which include it, and does better on some DVD fonts.
+    #[clap(long, value_parser = clap::value_parser!(u32).range(0..=3))]
+    pub oem: Option<u32>,
+
+    /// Tesseract page segmentation mode.
+    ///
+    /// The default, 7, treats each image as a single line of text, which is
+    /// what the subtitles are split into before OCR. If a line comes out
+    /// empty, modes 6 and 13 are tried before giving up on it.
+    #[clap(long, value_parser = clap::value_parser!(u32).range(0..=13), default_value = "7")]
+    pub psm: u32,
 
     /// Set values for config variables.
     ///
@@ -59,12 +520,1034 @@ pub struct Opt {
     #[clap(short = 'c', long, value_parser = parse_key_val, number_of_values = 1)]
     pub config: Vec<(Variable, String)>,
 
-    #[clap(name = "FILE", value_parser, value_hint = ValueHint::FilePath)]
-    pub input: PathBuf,
+    /// Load a Tesseract config file, like those given to the `tesseract`
+    /// command.
+    ///
+    /// Each line of a config file names a variable and its value. Variables
+    /// which Tesseract only reads when initializing are ignored with a
+    /// warning, and those given with `-c` take precedence.
+    #[clap(
+        long,
+        value_name = "PATH",
+        value_parser = parse_config_file,
+        number_of_values = 1,
+        value_hint = ValueHint::FilePath
+    )]
+    pub tess_config: Vec<ConfigFile>,
+
+    /// Only recognize these characters.
+    ///
+    /// Shorthand for `-c tessedit_char_whitelist=...`. Constraining the OCR to
+    /// the characters a track can contain, such as digits only, cuts down on
+    /// misrecognized characters.
+    #[clap(long)]
+    pub whitelist: Option<String>,
+
+    /// Never recognize these characters.
+    ///
+    /// Shorthand for `-c tessedit_char_blacklist=...`.
+    #[clap(long)]
+    pub blacklist: Option<String>,
+
+    /// Mark words whose OCR confidence, from 0 to 100, is below this, so that
+    /// proofreading can focus on them; see `--word-marker`.
+    #[clap(long, value_parser = clap::value_parser!(i32).range(0..=100))]
+    pub mark_words_below: Option<i32>,
+
+    /// How to mark words for `--mark-words-below`, with `{}` standing for the
+    /// word.
+    #[clap(long, value_parser = parse_word_marker, default_value = "⟦{}⟧")]
+    pub word_marker: String,
+
+    #[clap(flatten)]
+    pub input: InputOpt,
+
+    /// Directory to cache OCR results in, so that running again on the same
+    /// subtitles only recognizes the lines which changed.
+    ///
+    /// Defaults to `vobsubocr` in the user's cache directory.
+    #[clap(long, value_parser, value_hint = ValueHint::DirPath)]
+    pub cache_dir: Option<PathBuf>,
+
+    /// Recognize every line again instead of using or filling the OCR cache.
+    #[clap(long, conflicts_with = "cache_dir")]
+    pub no_cache: bool,
+
+    /// Describe the subtitles of each input, including how long OCR would
+    /// roughly take, and exit without running it.
+    #[clap(long)]
+    pub analyze: bool,
+
+    /// Run OCR on this many subtitles spread evenly over the inputs and print
+    /// them with their confidence instead of writing subtitles, to try out
+    /// settings in seconds.
+    #[clap(
+        long,
+        value_name = "N",
+        value_parser = clap::value_parser!(u32).range(1..),
+        conflicts_with_all = ["analyze", "interactive", "watch"]
+    )]
+    pub preview: Option<u32>,
+
+    /// Run OCR on only the subtitles at these indices, given as `N`,
+    /// `START..END` (without `END`), `START..=END`, `START..` or `..END`.
+    ///
+    /// Indices count from 0, like `{index}` in `--dump-name`, so that a
+    /// subtitle whose dumped images look wrong can be tried again quickly with
+    /// `--only N --dump`.
+    #[clap(long, value_name = "RANGE", value_parser = parse_index_range)]
+    pub only: Option<Range<usize>>,
 
     /// Dump processed subtitle images into the working directory as PNGs.
     #[clap(long)]
     pub dump: bool,
+
+    /// Which images `--dump` writes, separated by commas.
+    #[clap(
+        long,
+        value_enum,
+        value_name = "STAGE",
+        value_delimiter = ',',
+        default_value = "lines",
+        requires = "dump"
+    )]
+    pub dump_stages: Vec<DumpStage>,
+
+    /// Directory to dump images into, instead of the working directory.
+    #[clap(long, value_parser, value_hint = ValueHint::DirPath, requires = "dump")]
+    pub dump_dir: Option<PathBuf>,
+
+    /// Name of each dumped image, without its `.png` extension.
+    ///
+    /// `{index}` is replaced with the subtitle's number, `{part}` with the
+    /// line's number for line images or the stage, like `binarized`, for the
+    /// others, `{start}` with the subtitle's start time as `HH-MM-SS.mmm`, and
+    /// `{lang}` with the OCR language. Names need `{part}`, and `{index}` or
+    /// `{start}`, to tell the images apart.
+    #[clap(
+        long,
+        value_name = "TEMPLATE",
+        value_parser = parse_dump_name,
+        default_value = "{index}-{part}",
+        requires = "dump"
+    )]
+    pub dump_name: String,
+
+    /// Fix common OCR mistakes, like zeros read for the letter O in words and,
+    /// in English, French, German and Spanish text, words with `I` and `l`
+    /// mixed up.
+    #[clap(long)]
+    pub fix_ocr_errors: bool,
+
+    /// Fix characters OCR confuses with each other, judging from the
+    /// characters around them, after `--fix-ocr-errors`. Takes the kinds of
+    /// confusions to fix, separated by commas, like
+    /// `--fix-confusions=pipes,zeros`, or fixes all of them without any.
+    #[clap(
+        long,
+        value_enum,
+        value_name = "KIND",
+        value_delimiter = ',',
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "pipes,numbers,initial-l,medial-i,zeros"
+    )]
+    pub fix_confusions: Vec<Confusion>,
+
+    /// Fix OCR mistakes with a SubtitleEdit OCR fix list, like
+    /// `eng_OCRFixReplaceList.xml`, after `--fix-confusions`. Repeat to apply
+    /// several lists in order.
+    #[clap(
+        long,
+        value_name = "PATH",
+        value_parser = FixList::read,
+        number_of_values = 1,
+        value_hint = ValueHint::FilePath
+    )]
+    pub fix_list: Vec<FixList>,
+
+    /// Shift every subtitle by this, given as `[[HH:]MM:]SS[.mmm]` and possibly
+    /// negative, after the inputs are joined; see `--offsets` to shift each
+    /// input on its own.
+    #[clap(
+        long,
+        value_name = "TIME",
+        value_parser = parse_offset,
+        allow_hyphen_values = true,
+        conflicts_with = "sync"
+    )]
+    pub offset: Option<TimeDelta>,
+
+    /// Multiply every time by this, before `--offset`, for subtitles which
+    /// steadily drift out of sync; see also `--convert-framerate`.
+    #[clap(long, value_name = "FACTOR", value_parser = parse_stretch, conflicts_with = "sync")]
+    pub stretch: Option<f64>,
+
+    /// Rescale every time for a video at another frame rate, given as
+    /// `FROM:TO`, like `25:23.976` for subtitles from a PAL DVD, which plays
+    /// films sped up, paired with a video at film speed.
+    #[clap(
+        long,
+        value_name = "FROM:TO",
+        value_parser = parse_framerate_conversion,
+        conflicts_with = "sync"
+    )]
+    pub convert_framerate: Option<(f64, f64)>,
+
+    /// Shift and stretch the subtitles so that two times, each given as
+    /// `[[HH:]MM:]SS[.mmm]`, move to where they should be, like
+    /// `1:02=1:04.5,1:30:00=1:32:10`.
+    #[clap(long, value_name = "OLD=NEW,OLD=NEW", value_parser = parse_sync)]
+    pub sync: Option<SyncPoints>,
+
+    /// Stop subtitles from running past the start of the next one on the same
+    /// half of the screen, which makes some players drop lines.
+    #[clap(long, value_enum, value_name = "STRATEGY")]
+    pub fix_overlaps: Option<Overlaps>,
+
+    /// Lengthen subtitles shorter than this, given as `[[HH:]MM:]SS[.mmm]`,
+    /// as far as the next subtitle allows.
+    #[clap(long, value_name = "DURATION", value_parser = parse_duration)]
+    pub min_duration: Option<TimeDelta>,
+
+    /// Shorten subtitles longer than this, given as `[[HH:]MM:]SS[.mmm]`, for
+    /// subtitles whose stop command is missing or far too late.
+    #[clap(long, value_name = "DURATION", value_parser = parse_duration)]
+    pub max_duration: Option<TimeDelta>,
+
+    /// End subtitles at least this long before the next one starts, given as
+    /// `[[HH:]MM:]SS[.mmm]`.
+    #[clap(long, value_name = "DURATION", value_parser = parse_duration)]
+    pub min_gap: Option<TimeDelta>,
+
+    /// Remove hearing-impaired annotations, like `[door slams]`, `(SIGHS)` and
+    /// speaker labels like `JOHN:`, along with subtitles left empty.
+    #[clap(long)]
+    pub remove_hi: bool,
+
+    /// Normalize punctuation and the spaces around it for the OCR language:
+    /// French gets no-break spaces before `?`, `!`, `;` and `:`, Spanish
+    /// questions and exclamations get their opening `¿` and `¡`, German
+    /// quotes become „ and “, and other languages lose spaces before
+    /// punctuation.
+    #[clap(long)]
+    pub normalize_punctuation: bool,
+
+    /// Sentence case subtitles which are entirely in capitals, capitalizing
+    /// the words of `--proper-nouns` and, with `--spellcheck`, those its
+    /// dictionaries only know capitalized.
+    #[clap(long)]
+    pub recase: bool,
+
+    /// File of names and other words for `--recase` to keep capitalized as
+    /// written, separated by whitespace like a Tesseract user words file.
+    /// Repeat to use several.
+    #[clap(
+        long,
+        value_name = "PATH",
+        value_parser = recase::ProperNouns::read,
+        number_of_values = 1,
+        value_hint = ValueHint::FilePath,
+        requires = "recase"
+    )]
+    pub proper_nouns: Vec<recase::ProperNouns>,
+
+    /// Check the spelling of the text against Hunspell dictionaries for the
+    /// OCR language, after fixing OCR mistakes.
+    #[clap(long, value_enum, value_name = "MODE")]
+    pub spellcheck: Option<Spellcheck>,
+
+    /// Hunspell dictionary to check spelling with, like
+    /// `/usr/share/hunspell/en_US.dic`, with its `.aff` file next to it.
+    ///
+    /// Defaults to those for the OCR languages in `DICPATH`,
+    /// `/usr/share/hunspell` or `/usr/share/myspell`. Repeat to use several.
+    #[clap(
+        long,
+        value_name = "PATH",
+        value_parser,
+        number_of_values = 1,
+        value_hint = ValueHint::FilePath,
+        requires = "spellcheck"
+    )]
+    pub dictionary: Vec<PathBuf>,
+
+    /// Write each word spellchecking didn't know to this file, one per line,
+    /// as the number and start time of its subtitle, OCR's confidence in its
+    /// line, the word and the line, separated by tabs.
+    #[clap(long, value_name = "PATH", value_parser, value_hint = ValueHint::FilePath, requires = "spellcheck")]
+    pub spelling_report: Option<PathBuf>,
+
+    /// Fix recurring OCR mistakes with the regular expression rules in this
+    /// file, each given as `pattern => replacement` on its own line.
+    ///
+    /// The rules are applied in order to each line of text, and lines they
+    /// empty are dropped. Repeat to apply several files in order.
+    #[clap(
+        long,
+        value_name = "PATH",
+        value_parser = replace::Rules::read,
+        number_of_values = 1,
+        value_hint = ValueHint::FilePath
+    )]
+    pub replace: Vec<replace::Rules>,
+
+    /// Write an HTML page showing each subtitle's image next to its OCR text
+    /// and confidence, for proofreading.
+    #[clap(long, value_parser, value_hint = ValueHint::FilePath)]
+    pub review: Option<PathBuf>,
+
+    /// Write a JSON summary of the run for scripts: the numbers of subtitles
+    /// written, failed and below `--min-confidence`, their mean confidence,
+    /// the number of unknown words, the seconds spent in each stage and the
+    /// command line.
+    #[clap(long, value_name = "PATH", value_parser, value_hint = ValueHint::FilePath)]
+    pub summary_json: Option<PathBuf>,
+}
+
+impl OcrOpt {
+    /// The languages given with `-l`, joined the way Tesseract expects.
+    pub fn tesseract_language(&self) -> String {
+        self.lang.join("+")
+    }
+
+    /// The variables from every `--tess-config` file, in order.
+    pub fn config_file_variables(&self) -> Vec<(Variable, String)> {
+        self.tess_config
+            .iter()
+            .flat_map(|file| file.variables.iter().cloned())
+            .collect()
+    }
+
+    /// Warn about the variables in `--tess-config` files which are skipped.
+    pub fn warn_unknown_variables(&self) {
+        for file in &self.tess_config {
+            for name in &file.unknown {
+                warn!(
+                    "Ignoring unknown Tesseract variable `{}` in {}",
+                    name, file.filename
+                );
+            }
+        }
+    }
+
+    /// The Tesseract variables to set, with those given with `-c` last so that
+    /// they take precedence.
+    pub fn tesseract_config(&self) -> Vec<(Variable, String)> {
+        let mut config = Vec::new();
+        if let Some(whitelist) = &self.whitelist {
+            config.push((Variable::TesseditCharWhitelist, whitelist.clone()));
+        }
+        if let Some(blacklist) = &self.blacklist {
+            config.push((Variable::TesseditCharBlacklist, blacklist.clone()));
+        }
+        config.extend(self.config.iter().cloned());
+        config
+    }
+
+    /// The factor to multiply every time by and the offset to add after, if
+    /// the subtitles are to be retimed.
+    pub fn retiming(&self) -> Option<(f64, TimeDelta)> {
+        if let Some(SyncPoints { old, new }) = self.sync {
+            let scale = (new[1] - new[0]).msecs() as f64 / (old[1] - old[0]).msecs() as f64;
+            let offset = new[0].msecs() - (old[0].msecs() as f64 * scale).round() as i64;
+            return Some((scale, TimeDelta::from_msecs(offset)));
+        }
+        if self.offset.is_none() && self.stretch.is_none() && self.convert_framerate.is_none() {
+            return None;
+        }
+        // A subtitle shown at a frame is shown at the same frame at the new
+        // frame rate.
+        let conversion = self.convert_framerate.map_or(1.0, |(from, to)| from / to);
+        Some((
+            self.stretch.unwrap_or(1.0) * conversion,
+            self.offset.unwrap_or_else(|| TimeDelta::from_msecs(0)),
+        ))
+    }
+
+    /// Whether to dump the images of a stage of preprocessing.
+    pub fn dumps(&self, stage: DumpStage) -> bool {
+        self.dump && self.dump_stages.contains(&stage)
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct InfoOpt {
+    /// Print JSON instead of text.
+    #[clap(long)]
+    pub json: bool,
+
+    #[clap(flatten)]
+    pub input: InputOpt,
+}
+
+#[derive(Args, Debug)]
+pub struct ConvertOpt {
+    /// Output file; a `.sup` file or a BDN XML file, which is written along
+    /// with a PNG image for each subtitle.
+    #[clap(short = 'o', long, value_parser, value_hint = ValueHint::FilePath)]
+    pub output: PathBuf,
+
+    /// Output format; defaults to the one matching the output file's
+    /// extension.
+    #[clap(short = 'f', long, value_enum)]
+    pub format: Option<BitmapFormat>,
+
+    /// Frame rate of the video, used for the timecodes of BDN XML output.
+    #[clap(long, value_parser = parse_fps, default_value = "23.976")]
+    pub fps: f64,
+
+    #[clap(flatten)]
+    pub input: InputOpt,
+}
+
+/// Options selecting the subtitles to read.
+#[derive(Args, Debug)]
+pub struct InputOpt {
+    /// Input VobSub idx or sub file, DVD ISO image or VOB file, MP4 file with
+    /// VobSub tracks, MPEG transport stream (`.ts`, `.m2ts`) with DVB
+    /// subtitles, or BDN XML file with PNG images.
+    ///
+    /// A `.sub` file can be read without its idx file, in which case the
+    /// palette should be given with `--palette`. If `-`, a `.sub` file is read
+    /// from stdin. Inputs other than ISO images and MP4 files may be compressed
+    /// with gzip, zstd or xz, e.g. `movie.sub.zst`.
+    ///
+    /// Several inputs, such as the discs of a multi-disc title, are joined
+    /// into a single output; see `--offsets`. To keep them apart, see
+    /// `--output-archive`.
+    #[clap(name = "FILE", value_parser, value_hint = ValueHint::FilePath, required = true)]
+    pub inputs: Vec<PathBuf>,
+
+    /// Comma-separated time offsets to apply to each input, in order.
+    ///
+    /// Each offset is given as `[[HH:]MM:]SS[.mmm]`, and may be negative.
+    /// Inputs without an offset are not shifted.
+    #[clap(long, value_parser = parse_offset, value_delimiter = ',', allow_hyphen_values = true)]
+    pub offsets: Vec<TimeDelta>,
+
+    /// VobSub sub file to use for an idx input.
+    ///
+    /// Defaults to the sub file next to the idx file with the same name, or
+    /// its volumes named like `movie.cd1.sub`, `movie.cd2.sub`. Give this
+    /// several times to join a sub file split into volumes.
+    #[clap(long, value_parser, value_hint = ValueHint::FilePath, number_of_values = 1)]
+    pub sub: Vec<PathBuf>,
+
+    /// VobSub idx file to use for a `.sub` input.
+    ///
+    /// Defaults to the idx file next to the `.sub` file, if any. Needed to read
+    /// a `.sub` file with its palette and tracks from stdin.
+    #[clap(long, value_parser, value_hint = ValueHint::FilePath)]
+    pub idx: Option<PathBuf>,
+
+    /// DVD title set to read subtitles from when the input is an ISO image.
+    #[clap(long, default_value = "1")]
+    pub title: u32,
+
+    /// Subtitle track to read from inputs containing several.
+    ///
+    /// Either a zero-based stream index or a language code as declared by the
+    /// input, such as `en` for idx files and DVDs or `eng` for DVB and MP4.
+    /// Defaults to the idx file's `langidx`, or the first track otherwise.
+    #[clap(long, visible_alias = "stream", value_parser = parse_stream_selector)]
+    pub track: Option<StreamSelector>,
+
+    /// Read only forced subtitles, which are shown even with subtitles turned
+    /// off, like those translating foreign dialogue.
+    #[clap(long, conflicts_with = "no_forced")]
+    pub forced_only: bool,
+
+    /// Leave out forced subtitles.
+    #[clap(long)]
+    pub no_forced: bool,
+
+    /// Read only the subtitles starting at or after this time, as
+    /// `[[HH:]MM:]SS[.mmm]`, such as to try out settings on part of a disc.
+    ///
+    /// Times are after `--offsets` are applied.
+    #[clap(long, value_name = "TIME", value_parser = parse_time)]
+    pub start: Option<TimePoint>,
+
+    /// Read only the subtitles starting before this time.
+    #[clap(long, value_name = "TIME", value_parser = parse_time)]
+    pub end: Option<TimePoint>,
+
+    /// Override the 16-color palette, as 16 comma-separated hex RGB colors.
+    ///
+    /// Takes the same form as the idx file's `palette:` line, and is used in
+    /// its place, which helps with idx files that have a broken or missing
+    /// palette. Also applies to ISO images and `.sub` files without an idx.
+    #[clap(long, value_parser = parse_palette)]
+    pub palette: Option<[[u8; 3]; 16]>,
+
+    /// Force the colors of VobSub subtitles, like the idx file's custom colors,
+    /// as in `tridx=1000,colors=000000,ffffff,000000,000000`.
+    ///
+    /// `colors` gives the hex RGB colors of the background, text, outline and
+    /// anti-aliasing pixels, and `tridx` a digit for each which is 1 to make
+    /// it transparent. This takes the place of any palette.
+    #[clap(long, value_parser = parse_custom_colors)]
+    pub custom_colors: Option<CustomColors>,
+
+    /// Take the palette from a DVD title set's IFO file, such as
+    /// `VTS_01_0.IFO`.
+    ///
+    /// Useful when the idx file lacks a palette, or when reading a VOB file
+    /// directly, in which case the IFO's stream list is also used to select a
+    /// `--track`. `--palette` takes precedence.
+    #[clap(long, value_parser, value_hint = ValueHint::FilePath)]
+    pub ifo: Option<PathBuf>,
+
+    /// Work around malformed idx files instead of giving up.
+    ///
+    /// Lines that can't be parsed are skipped, tracks without an index are
+    /// numbered in order, and a missing palette is guessed. Everything ignored
+    /// is logged.
+    #[clap(long)]
+    pub lenient: bool,
+}
+
+/// Image-based subtitle formats that can be written by `convert`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum BitmapFormat {
+    /// Blu-ray PGS subtitles.
+    Sup,
+    /// BDN XML with PNG images.
+    Bdn,
+}
+
+/// The colors and transparency of VobSub's four pixel values, in order.
+#[derive(Debug, Clone, Copy)]
+pub struct CustomColors {
+    pub transparent: [bool; 4],
+    pub colors: [[u8; 3]; 4],
+}
+
+/// Selects one of several subtitle streams, either by position or by language.
+#[derive(Debug, Clone)]
+pub enum StreamSelector {
+    Index(usize),
+    Language(String),
+}
+
+impl fmt::Display for StreamSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StreamSelector::Index(i) => write!(f, "index {}", i),
+            StreamSelector::Language(lang) => write!(f, "language {}", lang),
+        }
+    }
+}
+
+/// How to choose the luminance threshold for binarizing subtitles.
+#[derive(Debug, Clone, Copy)]
+pub enum Threshold {
+    Fixed(f32),
+    /// Find a threshold for each subtitle from its colors.
+    Auto,
+}
+
+impl fmt::Display for Threshold {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Threshold::Fixed(threshold) => write!(f, "{}", threshold),
+            Threshold::Auto => write!(f, "auto"),
+        }
+    }
+}
+
+/// When to take the darkest colors of a subtitle as its text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Invert {
+    /// Never; the text is brighter than its outline.
+    Never,
+    /// Always; the text is darker than its outline.
+    Always,
+    /// Whenever the bright pixels surround the dark ones, like an outline.
+    Auto,
+}
+
+/// Ways of splitting subtitle images into lines of text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Segmentation {
+    /// Runs of scanlines containing text, spanning the whole image.
+    Scanlines,
+    /// Groups of touching pixels joined into lines, which copes better with
+    /// text placed side by side or diagonally.
+    Components,
+}
+
+/// Two times in the subtitles and where they should be.
+#[derive(Debug, Clone, Copy)]
+pub struct SyncPoints {
+    pub old: [TimeDelta; 2],
+    pub new: [TimeDelta; 2],
+}
+
+/// How to fix subtitles overlapping the next one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Overlaps {
+    /// End the subtitle when the next one starts.
+    Clamp,
+    /// Split the overlap between them, ending the subtitle and starting the
+    /// next one halfway through it.
+    Rebalance,
+}
+
+/// Kinds of characters OCR confuses with each other, in the order they're
+/// fixed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Confusion {
+    /// Pipes, read as `l` after lowercase letters and `I` elsewhere.
+    Pipes,
+    /// The letters O, I and l in numbers, read as digits, like `2O1O`.
+    Numbers,
+    /// `l` read as `I` alone or starting a word where it couldn't, like `lt`.
+    InitialL,
+    /// `I` read as `l` after lowercase letters, like `caII`.
+    MedialI,
+    /// Zeros in words, read as the letter O.
+    Zeros,
+}
+
+/// What to do with words spellchecking doesn't know.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Spellcheck {
+    /// Report them.
+    Flag,
+    /// Replace those a single letter away from exactly one known word with
+    /// it, and report the rest.
+    Correct,
+}
+
+/// Stages of preprocessing whose images can be dumped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum DumpStage {
+    /// Each subtitle image's palette indices, as shades of gray.
+    Raw,
+    /// Each subtitle image in its palette's colors.
+    Palette,
+    /// Each subtitle image binarized, cropped and despeckled, as searched for
+    /// lines of text.
+    Binarized,
+    /// The line images given to OCR.
+    Lines,
+}
+
+/// Margins of the screen to ignore, in pixels.
+#[derive(Debug, Clone, Copy)]
+pub struct Crop {
+    pub top: u32,
+    pub bottom: u32,
+    pub left: u32,
+    pub right: u32,
+}
+
+/// A rectangle on the screen, in pixels.
+#[derive(Debug, Clone, Copy)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// A morphological operation on the text of line images.
+#[derive(Debug, Clone, Copy)]
+pub struct Morph {
+    pub op: MorphOp,
+    pub radius: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum MorphOp {
+    Erode,
+    Dilate,
+    Open,
+    Close,
+}
+
+/// OCR engines that can be used.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Engine {
+    /// Tesseract, through leptess.
+    Tesseract,
+    /// Google Cloud Vision; see `--api-key`. Needs the `cloud` feature.
+    #[cfg(feature = "cloud")]
+    GoogleVision,
+}
+
+/// Subtitle formats that can be written.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// SubRip.
+    Srt,
+    /// Advanced SubStation Alpha.
+    Ass,
+    /// WebVTT.
+    Vtt,
+    /// Frame-based MicroDVD; see `--fps`.
+    Microdvd,
+    /// MPL2, with times in tenths of a second.
+    Mpl2,
+    /// TMPlayer, with start times in whole seconds.
+    Tmplayer,
+    /// Spruce STL, as used by DVD Studio Pro; see `--fps`.
+    Stl,
+    /// JSON array of cues with times in milliseconds, OCR confidence and
+    /// on-screen placement.
+    Json,
+    /// hOCR with the bounding box of every line and word, and a page for each
+    /// subtitle.
+    Hocr,
+    /// ALTO XML with the position of every line and word, and a page for each
+    /// subtitle.
+    Alto,
+}
+
+/// A character encoding to write text subtitles in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextEncoding {
+    Utf8,
+    /// UTF-8 starting with a byte order mark.
+    Utf8Bom,
+    Utf16Le,
+    Utf16Be,
+    /// An encoding other than UTF, which is written without a byte order mark.
+    Legacy(&'static encoding_rs::Encoding),
+}
+
+fn parse_encoding(s: &str) -> Result<TextEncoding> {
+    let label = s.trim().to_ascii_lowercase();
+    Ok(match label.as_str() {
+        "utf8" | "utf-8" => TextEncoding::Utf8,
+        "utf8-bom" | "utf-8-bom" => TextEncoding::Utf8Bom,
+        "utf16le" | "utf-16le" => TextEncoding::Utf16Le,
+        "utf16be" | "utf-16be" => TextEncoding::Utf16Be,
+        _ => {
+            let encoding = encoding_rs::Encoding::for_label(label.as_bytes())
+                // Labels of encodings which are unsafe to decode map to the
+                // replacement encoding, which can't be written.
+                .filter(|&encoding| encoding != encoding_rs::REPLACEMENT)
+                .context(EncodingSnafu { value: s })?;
+            match encoding {
+                _ if encoding == encoding_rs::UTF_8 => TextEncoding::Utf8,
+                _ if encoding == encoding_rs::UTF_16LE => TextEncoding::Utf16Le,
+                _ if encoding == encoding_rs::UTF_16BE => TextEncoding::Utf16Be,
+                _ => TextEncoding::Legacy(encoding),
+            }
+        }
+    })
+}
+
+fn parse_stream_selector(s: &str) -> Result<StreamSelector, Infallible> {
+    Ok(match s.parse() {
+        Ok(i) => StreamSelector::Index(i),
+        Err(_) => StreamSelector::Language(s.to_owned()),
+    })
+}
+
+fn parse_palette(s: &str) -> Result<[[u8; 3]; 16]> {
+    crate::idx::parse_palette(s).context(PaletteSnafu { value: s })
+}
+
+fn parse_custom_colors(s: &str) -> Result<CustomColors> {
+    custom_colors(s).context(CustomColorsSnafu { value: s })
+}
+
+fn custom_colors(s: &str) -> Option<CustomColors> {
+    let (tridx, colors) = s.split_once(',')?;
+    let tridx = tridx.trim().strip_prefix("tridx=")?.as_bytes();
+    let mut colors = colors.trim().strip_prefix("colors=")?.split(',');
+    if tridx.len() != 4 {
+        return None;
+    }
+    let mut custom_colors = CustomColors {
+        transparent: [false; 4],
+        colors: [[0; 3]; 4],
+    };
+    for ((transparent, entry), digit) in custom_colors
+        .transparent
+        .iter_mut()
+        .zip(&mut custom_colors.colors)
+        .zip(tridx)
+    {
+        *transparent = match digit {
+            b'0' => false,
+            b'1' => true,
+            _ => return None,
+        };
+        let color = u32::from_str_radix(colors.next()?.trim(), 16).ok()?;
+        *entry = [(color >> 16) as u8, (color >> 8) as u8, color as u8];
+    }
+    if colors.next().is_some() {
+        return None;
+    }
+    Some(custom_colors)
+}
+
+fn parse_threshold(s: &str) -> Result<Threshold> {
+    if s == "auto" {
+        return Ok(Threshold::Auto);
+    }
+    s.parse::<f32>()
+        .ok()
+        .filter(|threshold| (0.0..=1.0).contains(threshold))
+        .map(Threshold::Fixed)
+        .context(ThresholdSnafu { value: s })
+}
+
+fn parse_retry_threshold(s: &str) -> Result<f32> {
+    s.parse::<f32>()
+        .ok()
+        .filter(|threshold| (0.0..=1.0).contains(threshold))
+        .context(RetryThresholdSnafu { value: s })
+}
+
+fn parse_crop(s: &str) -> Result<Crop> {
+    let margins = s
+        .split(',')
+        .map(|margin| margin.trim().parse().ok())
+        .collect::<Option<Vec<u32>>>();
+    match margins.as_deref() {
+        Some(&[top, bottom, left, right]) => Ok(Crop {
+            top,
+            bottom,
+            left,
+            right,
+        }),
+        _ => CropSnafu { value: s }.fail(),
+    }
+}
+
+fn parse_rect(s: &str) -> Result<Rect> {
+    let values = s
+        .split(',')
+        .map(|value| value.trim().parse().ok())
+        .collect::<Option<Vec<u32>>>();
+    match values.as_deref() {
+        Some(&[x, y, width, height]) => Ok(Rect {
+            x,
+            y,
+            width,
+            height,
+        }),
+        _ => RectSnafu { value: s }.fail(),
+    }
+}
+
+fn parse_region_size(s: &str) -> Result<(usize, usize)> {
+    s.split_once('x')
+        .and_then(|(width, height)| Some((width.trim().parse().ok()?, height.trim().parse().ok()?)))
+        .context(RegionSizeSnafu { value: s })
+}
+
+/// Find the names in `{...}` in a template, or `None` if a brace isn't
+/// closed.
+fn template_tokens(s: &str) -> Option<Vec<&str>> {
+    let mut tokens = Vec::new();
+    let mut rest = s;
+    while let Some(start) = rest.find('{') {
+        let (token, after) = rest[start + 1..].split_once('}')?;
+        tokens.push(token);
+        rest = after;
+    }
+    Some(tokens)
+}
+
+fn parse_index_range(s: &str) -> Result<Range<usize>> {
+    let index = |n: &str| n.trim().parse::<usize>().ok();
+    let (start, end) = if let Some((start, end)) = s.split_once("..=") {
+        (start, index(end).and_then(|end| end.checked_add(1)))
+    } else if let Some((start, end)) = s.split_once("..") {
+        let end = if end.is_empty() {
+            Some(usize::MAX)
+        } else {
+            index(end)
+        };
+        (start, end)
+    } else {
+        (s, index(s).and_then(|n| n.checked_add(1)))
+    };
+    let start = if start.is_empty() {
+        Some(0)
+    } else {
+        index(start)
+    };
+    match (start, end) {
+        (Some(start), Some(end)) if start < end => Ok(start..end),
+        _ => IndexRangeSnafu { value: s }.fail(),
+    }
+}
+
+fn parse_dump_name(s: &str) -> Result<String> {
+    let tokens = template_tokens(s).context(DumpNameSnafu { value: s })?;
+    ensure!(
+        tokens
+            .iter()
+            .all(|token| matches!(*token, "index" | "part" | "start" | "lang"))
+            && tokens.contains(&"part")
+            && (tokens.contains(&"index") || tokens.contains(&"start")),
+        DumpNameSnafu { value: s }
+    );
+    Ok(s.to_owned())
+}
+
+fn parse_output_name(s: &str) -> Result<String> {
+    let tokens = template_tokens(s).context(OutputNameSnafu { value: s })?;
+    ensure!(
+        tokens.iter().all(|token| matches!(*token, "stem" | "lang")) && tokens.contains(&"stem"),
+        OutputNameSnafu { value: s }
+    );
+    Ok(s.to_owned())
+}
+
+fn parse_morph(s: &str) -> Result<Morph> {
+    let (op, radius) = s.split_once(':').unwrap_or((s, "1"));
+    let op = match op {
+        "erode" => MorphOp::Erode,
+        "dilate" => MorphOp::Dilate,
+        "open" => MorphOp::Open,
+        "close" => MorphOp::Close,
+        _ => return MorphSnafu { value: s }.fail(),
+    };
+    let radius = radius
+        .parse()
+        .ok()
+        .filter(|&radius| radius > 0)
+        .context(MorphSnafu { value: s })?;
+    Ok(Morph { op, radius })
+}
+
+fn parse_fps(s: &str) -> Result<f64> {
+    s.parse::<f64>()
+        .ok()
+        .filter(|fps| fps.is_finite() && *fps > 0.0)
+        .context(FpsSnafu { value: s })
+}
+
+#[cfg(feature = "cloud")]
+fn parse_rate(s: &str) -> Result<f64> {
+    s.parse::<f64>()
+        .ok()
+        .filter(|rate| rate.is_finite() && *rate > 0.0)
+        .context(RateSnafu { value: s })
+}
+
+fn parse_word_marker(s: &str) -> Result<String> {
+    ensure!(s.contains("{}"), WordMarkerSnafu { value: s });
+    Ok(s.to_owned())
+}
+
+fn parse_offset(s: &str) -> Result<TimeDelta> {
+    let (negative, time) = match s.strip_prefix('-') {
+        Some(time) => (true, time),
+        None => (false, s),
+    };
+    let mut fields = time.rsplit(':');
+    let seconds = fields
+        .next()
+        .and_then(|f| f.parse::<f64>().ok())
+        .filter(|s| s.is_finite() && *s >= 0.0);
+    let minutes_hours = fields
+        .map(|f| f.parse::<u32>().ok())
+        .collect::<Option<Vec<_>>>()
+        .filter(|f| f.len() <= 2);
+    let (seconds, minutes_hours) = seconds
+        .zip(minutes_hours)
+        .context(OffsetSnafu { value: s })?;
+    let minutes = minutes_hours.first().copied().unwrap_or(0);
+    let hours = minutes_hours.get(1).copied().unwrap_or(0);
+    // In floating point, so that large values can't overflow.
+    let msecs = ((f64::from(hours) * 60.0 + f64::from(minutes)) * 60.0 + seconds) * 1000.0;
+    let msecs = Some(msecs.round())
+        .filter(|&msecs| msecs < i64::MAX as f64)
+        .context(OffsetSnafu { value: s })? as i64;
+    Ok(TimeDelta::from_msecs(if negative { -msecs } else { msecs }))
+}
+
+fn parse_stretch(s: &str) -> Result<f64> {
+    s.parse::<f64>()
+        .ok()
+        .filter(|factor| factor.is_finite() && *factor > 0.0)
+        .context(StretchSnafu { value: s })
+}
+
+fn parse_framerate_conversion(s: &str) -> Result<(f64, f64)> {
+    s.split_once(':')
+        .and_then(|(from, to)| Some((parse_fps(from.trim()).ok()?, parse_fps(to.trim()).ok()?)))
+        .context(FramerateConversionSnafu { value: s })
+}
+
+fn parse_sync(s: &str) -> Result<SyncPoints> {
+    let points = s
+        .split(',')
+        .map(|point| {
+            let (old, new) = point.split_once('=')?;
+            Some((
+                parse_offset(old.trim()).ok()?,
+                parse_offset(new.trim()).ok()?,
+            ))
+        })
+        .collect::<Option<Vec<_>>>();
+    match points.as_deref() {
+        // The times must keep their order, or the scale would be negative.
+        Some(&[(old0, new0), (old1, new1)])
+            if old0 != old1 && new0 != new1 && (old0 < old1) == (new0 < new1) =>
+        {
+            Ok(SyncPoints {
+                old: [old0, old1],
+                new: [new0, new1],
+            })
+        }
+        _ => SyncSnafu { value: s }.fail(),
+    }
+}
+
+fn parse_duration(s: &str) -> Result<TimeDelta> {
+    parse_offset(s)
+        .ok()
+        .filter(|duration| duration.msecs() >= 0)
+        .context(DurationSnafu { value: s })
+}
+
+fn parse_time(s: &str) -> Result<TimePoint> {
+    parse_duration(s).map(|duration| TimePoint::from_msecs(duration.msecs()))
+}
+
+/// A Tesseract config file.
+#[derive(Clone, Debug)]
+pub struct ConfigFile {
+    pub filename: String,
+    /// The variables read from it.
+    pub variables: Vec<(Variable, String)>,
+    /// Names of the variables Tesseract doesn't know, which are skipped. They
+    /// are warned about once logging is set up, after parsing.
+    pub unknown: Vec<String>,
+}
+
+/// Read a Tesseract config file, which has a variable name and its value on
+/// each line, skipping variables Tesseract doesn't know like it does.
+fn parse_config_file(s: &str) -> Result<ConfigFile> {
+    let contents = fs::read_to_string(s).context(ReadConfigFileSnafu { filename: s })?;
+    let mut variables = Vec::new();
+    let mut unknown = Vec::new();
+    for line in contents.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (name, value) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        match parse_tesseract_variable(name) {
+            Ok(variable) => variables.push((variable, value.trim_start().to_owned())),
+            Err(_) => unknown.push(name.to_owned()),
+        }
+    }
+    Ok(ConfigFile {
+        filename: s.to_owned(),
+        variables,
+        unknown,
+    })
 }
 
 // https://github.com/clap-rs/clap_derive/blob/master/examples/keyvalue.rs
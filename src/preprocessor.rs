@@ -1,56 +1,408 @@
+//! Reading subtitle images from the supported inputs and preparing them for
+//! OCR, by binarizing them and cutting out each line of text.
+
 use std::{
+    borrow::Cow,
     cmp::{max, min},
+    collections::HashMap,
     ops::Range,
+    path::Path,
 };
 
-use crate::opt::Opt;
-use image::{GrayImage, ImageBuffer, Luma};
-use iter_fixed::IntoIteratorFixed;
-use log::warn;
+use crate::{
+    bdn, compression, dvb, dvd, idx, morph, mp4,
+    opt::{Crop, CustomColors, DumpStage, InputOpt, Invert, OcrOpt, Rect, Segmentation, Threshold},
+};
+use image::{GrayImage, ImageBuffer, Luma, Rgba, RgbaImage};
+use log::{info, warn};
 use rayon::prelude::*;
+use serde::Serialize;
+use snafu::{ResultExt, Snafu};
 use subparse::timetypes::{TimePoint, TimeSpan};
 
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("{}", source))]
+    Idx { source: idx::Error },
+
+    #[snafu(display("{}", source))]
+    Dvd { source: dvd::Error },
+
+    #[snafu(display("{}", source))]
+    Dvb { source: dvb::Error },
+
+    #[snafu(display("{}", source))]
+    Bdn { source: bdn::Error },
+
+    #[snafu(display("{}", source))]
+    Mp4 { source: mp4::Error },
+}
+
+/// A subtitle ready for OCR, cut into lines of text.
 pub struct PreprocessedVobSubtitle {
     pub time_span: TimeSpan,
     pub force: bool,
-    pub images: Vec<GrayImage>,
+    pub placement: Placement,
+    /// The most common color of the text pixels.
+    pub text_color: [u8; 3],
+    pub lines: Vec<LineImage>,
+    /// The binarized image the lines were found in, if it is to be dumped.
+    pub binarized: Option<GrayImage>,
+}
+
+/// One line of a subtitle's text, ready for OCR.
+pub struct LineImage {
+    pub image: GrayImage,
+    /// Screen position of the top left corner of the image, border included.
+    pub origin: (i32, i32),
+    /// The most common color of the line's text pixels.
+    pub color: [u8; 3],
+    /// Whether the text looks slanted, if italics are detected at all.
+    pub italic: bool,
+}
+
+/// Where a subtitle's text was displayed on the screen, in pixels.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Placement {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    pub screen_width: u32,
+    pub screen_height: u32,
+}
+
+/// Where a subtitle sits horizontally on the screen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Column {
+    Left,
+    Center,
+    Right,
+}
+
+impl Placement {
+    /// Whether the text sits in the upper half of the screen.
+    pub fn is_top(&self) -> bool {
+        self.y + self.height / 2 < self.screen_height / 2
+    }
+
+    /// Whether the text is centered, or off to one side.
+    pub fn column(&self) -> Column {
+        let center = self.x + self.width / 2;
+        let middle = self.screen_width / 2;
+        if center.abs_diff(middle) < self.screen_width / 10 {
+            Column::Center
+        } else if center < middle {
+            Column::Left
+        } else {
+            Column::Right
+        }
+    }
+}
+
+/// A single subpicture stream, along with the palette needed to render it.
+pub struct VobSubStream {
+    /// The 16-color palette, if known.
+    pub palette: Option<[[u8; 3]; 16]>,
+    /// Width and height of the video, if known.
+    pub screen_size: Option<(u32, u32)>,
+    /// Language code of the track, if the input declares it.
+    pub language: Option<String>,
+    /// Subpicture packs in the same format as a `.sub` file.
+    pub data: Vec<u8>,
+}
+
+/// The subtitles read from an input.
+pub struct SubtitleTrack {
+    pub bitmaps: Vec<SubtitleBitmap>,
+    /// Language code of the track, if the input declares it.
+    pub language: Option<String>,
+    /// The 16-color palette the subtitles were decoded with, for VobSub
+    /// inputs.
+    pub palette: Option<[[u8; 3]; 16]>,
+}
+
+/// A decoded, paletted subtitle image, independent of the format it came from.
+#[derive(Clone)]
+pub struct SubtitleBitmap {
+    pub time_span: TimeSpan,
+    pub force: bool,
+    /// Position of the image's top left corner on the screen.
+    pub x: u32,
+    pub y: u32,
+    /// Width and height of the screen the image is displayed on.
+    pub screen_size: (u32, u32),
+    pub width: usize,
+    pub height: usize,
+    /// Palette index of each pixel, row by row.
+    pub pixels: Vec<u8>,
+    /// sRGB color and alpha of each palette entry.
+    pub palette: Vec<Rgba<u8>>,
+}
+
+impl SubtitleBitmap {
+    /// Convert a true color image placed at `x` and `y` into a paletted bitmap.
+    /// Images with more than 256 colors have their color depth reduced until
+    /// they fit.
+    pub fn from_rgba(
+        time_span: TimeSpan,
+        force: bool,
+        (x, y): (u32, u32),
+        screen_size: (u32, u32),
+        image: &RgbaImage,
+    ) -> Self {
+        let mut palette = Vec::new();
+        let mut pixels = Vec::with_capacity(image.len() / 4);
+        for shift in 0..8 {
+            let mask = 0xffu8 << shift;
+            let mut indices = HashMap::new();
+            palette.clear();
+            pixels.clear();
+            for pixel in image.pixels() {
+                // All fully transparent pixels are the same.
+                let color = if pixel[3] == 0 {
+                    Rgba([0, 0, 0, 0])
+                } else {
+                    Rgba(pixel.0.map(|c| c & mask))
+                };
+                let index = *indices.entry(color).or_insert_with(|| {
+                    palette.push(color);
+                    palette.len() - 1
+                });
+                if index > u8::MAX as usize {
+                    break;
+                }
+                pixels.push(index as u8);
+            }
+            if palette.len() <= 256 {
+                break;
+            }
+        }
+        SubtitleBitmap {
+            time_span,
+            force,
+            x,
+            y,
+            screen_size,
+            width: image.width() as usize,
+            height: image.height() as usize,
+            pixels,
+            palette,
+        }
+    }
+
+    /// Show the palette index of each pixel as a shade of gray, spread from
+    /// black for the first entry to white for the last.
+    pub fn to_indexed(&self) -> GrayImage {
+        let last = max(self.palette.len(), 2) - 1;
+        GrayImage::from_fn(self.width as u32, self.height as u32, |x, y| {
+            let index = self.pixels[y as usize * self.width + x as usize] as usize;
+            Luma([(min(index, last) * 255 / last) as u8])
+        })
+    }
+
+    /// Convert the bitmap back into a true color image.
+    pub fn to_rgba(&self) -> RgbaImage {
+        RgbaImage::from_fn(self.width as u32, self.height as u32, |x, y| {
+            let index = self.pixels[y as usize * self.width + x as usize];
+            self.palette
+                .get(index as usize)
+                .copied()
+                .unwrap_or(Rgba([0, 0, 0, 0]))
+        })
+    }
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Return a vector of preprocessed subtitles, each with the bitmap it came
+/// from.
+pub fn preprocess_subtitles<'a>(
+    bitmaps: &'a [SubtitleBitmap],
+    opt: &OcrOpt,
+) -> Vec<(&'a SubtitleBitmap, PreprocessedVobSubtitle)> {
+    bitmaps
+        .par_iter()
+        .filter_map(|bitmap| {
+            preprocess_subtitle(bitmap, opt.threshold, opt.grayscale, opt)
+                .map(|vobsub| (bitmap, vobsub))
+        })
+        .collect()
+}
+
+/// Decode the input file into subtitle bitmaps, picking the decoder by the
+/// file's extension.
+pub fn read_bitmaps(opt: &InputOpt, input: &Path) -> Result<SubtitleTrack> {
+    let vts = match &opt.ifo {
+        Some(ifo) => Some(dvd::read_ifo(ifo).context(DvdSnafu {})?),
+        None => None,
+    };
+    // An explicit palette wins over the IFO's, which wins over the input's.
+    let palette = opt.palette.or(vts.as_ref().map(|vts| vts.palette));
+    let palette = palette.as_ref();
+
+    let extension = compression::strip_extension(input)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_ascii_lowercase());
+    let vobsub = match extension.as_deref() {
+        Some("iso") => dvd::read_iso(input, opt.title, opt.track.as_ref()).context(DvdSnafu {})?,
+        Some("vob") => {
+            dvd::read_vob(input, vts.as_ref(), opt.track.as_ref()).context(DvdSnafu {})?
+        }
+        Some("mp4" | "m4v") => mp4::read_mp4(input, opt.track.as_ref()).context(Mp4Snafu {})?,
+        Some("ts" | "m2ts" | "mts") => {
+            return Ok(SubtitleTrack {
+                bitmaps: dvb::read_ts(input, opt.track.as_ref()).context(DvbSnafu {})?,
+                language: None,
+                palette: None,
+            })
+        }
+        Some("xml") => {
+            return Ok(SubtitleTrack {
+                bitmaps: bdn::read_bdn(input).context(BdnSnafu {})?,
+                language: None,
+                palette: None,
+            })
+        }
+        Some("sub") => read_sub(opt, input, palette)?,
+        // Without a file name, the input can only be a `.sub` file.
+        _ if opt.idx.is_some() || input.as_os_str() == "-" => read_sub(opt, input, palette)?,
+        _ => idx::read_vobsub(input, &opt.sub, opt.track.as_ref(), palette, opt.lenient)
+            .context(IdxSnafu {})?,
+    };
+    let palette = palette.copied().or(vobsub.palette);
+    let mut bitmaps = decode_vobsubs(
+        vobsub::subtitles(&vobsub.data),
+        palette.as_ref(),
+        vobsub.screen_size,
+    );
+    if let Some(custom_colors) = &opt.custom_colors {
+        let palette = custom_palette(custom_colors);
+        for bitmap in &mut bitmaps {
+            bitmap.palette = palette.clone();
+        }
+    }
+    Ok(SubtitleTrack {
+        bitmaps,
+        language: vobsub.language,
+        palette: palette.filter(|_| opt.custom_colors.is_none()),
+    })
 }
 
-pub type Result<T, E = vobsub::Error> = std::result::Result<T, E>;
+/// Make the palette of VobSub's four pixel values from `--custom-colors`.
+fn custom_palette(custom_colors: &CustomColors) -> Vec<Rgba<u8>> {
+    custom_colors
+        .colors
+        .iter()
+        .zip(custom_colors.transparent)
+        .map(|(&[r, g, b], transparent)| Rgba([r, g, b, if transparent { 0 } else { 255 }]))
+        .collect()
+}
 
-/// Return a vector of binarized subtitles.
-pub fn preprocess_subtitles(opt: &Opt) -> Result<Vec<PreprocessedVobSubtitle>> {
-    let idx = vobsub::Index::open(&opt.input)?;
-    let subtitles: Vec<vobsub::Subtitle> = idx
-        .subtitles()
+fn read_sub(opt: &InputOpt, input: &Path, palette: Option<&[[u8; 3]; 16]>) -> Result<VobSubStream> {
+    idx::read_sub(
+        input,
+        opt.idx.as_deref(),
+        opt.track.as_ref(),
+        palette,
+        opt.lenient,
+    )
+    .context(IdxSnafu {})
+}
+
+fn decode_vobsubs(
+    subtitles: vobsub::Subtitles,
+    palette: Option<&[[u8; 3]; 16]>,
+    screen_size: Option<(u32, u32)>,
+) -> Vec<SubtitleBitmap> {
+    let mut skipped = 0;
+    let bitmaps = subtitles
         .filter_map(|sub| match sub {
-            Ok(sub) => Some(sub),
+            Ok(sub) => Some(vobsub_to_bitmap(&sub, palette, screen_size)),
             Err(e) => {
-                warn!(
-                    "warning: unable to read subtitle: {}. (This can usually be safely ignored.)",
-                    e
-                );
+                info!("Skipping subtitle packet which couldn't be read: {}", e);
+                skipped += 1;
                 None
             }
         })
         .collect();
-    let palette = rgb_palette_to_luminance(idx.palette());
-    let result = subtitles
-        .par_iter()
-        .filter_map(|sub| {
-            subtitle_to_images(sub, &palette, opt.threshold, opt.border).map(|images| {
-                PreprocessedVobSubtitle {
-                    time_span: TimeSpan::new(
-                        seconds_to_time_point(sub.start_time()),
-                        seconds_to_time_point(sub.end_time()),
-                    ),
-                    force: sub.force(),
-                    images,
-                }
-            })
+    // These are usually harmless, like padding at the end of a stream.
+    if skipped > 0 {
+        warn!(
+            "Skipped {} subtitle packets which couldn't be read; list them with -v",
+            skipped
+        );
+    }
+    bitmaps
+}
+
+/// Gaps between side by side text wider than this many times the height of
+/// the line split it in two.
+const COLUMN_GAP: usize = 2;
+
+/// Slants, in degrees, to look for in text.
+const SLANTS: [f32; 10] = [2.0, 4.0, 6.0, 8.0, 10.0, 12.0, 14.0, 16.0, 18.0, 20.0];
+
+/// Text slanted at least this many degrees is in italics.
+const ITALIC_SLANT: f32 = 8.0;
+
+/// Steepest rotation, in degrees, to straighten lines of text by.
+const MAX_SKEW: f32 = 5.0;
+
+/// Step between the rotations tried when straightening lines of text.
+const SKEW_STEP: f32 = 0.25;
+
+/// Colors assumed for each pixel value when there is no palette: by convention,
+/// a background, the text itself, an outline, and an anti-aliasing color.
+const GUESSED_COLORS: [[u8; 3]; 4] = [[0, 0, 0], [255, 255, 255], [0, 0, 0], [128, 128, 128]];
+
+fn vobsub_to_bitmap(
+    subtitle: &vobsub::Subtitle,
+    palette: Option<&[[u8; 3]; 16]>,
+    screen_size: Option<(u32, u32)>,
+) -> SubtitleBitmap {
+    let coordinates = subtitle.coordinates();
+    // Without a known size, tell NTSC and PAL apart by whether the subtitle
+    // fits on an NTSC screen.
+    let screen_size = screen_size.unwrap_or_else(|| {
+        if coordinates.top() as u32 + coordinates.height() as u32 > 480 {
+            (720, 576)
+        } else {
+            (720, 480)
+        }
+    });
+    // The subtitle's palette and alpha are both reversed relative to the pixel
+    // values.
+    let sub_palette = subtitle
+        .palette()
+        .iter()
+        .rev()
+        .zip(subtitle.alpha().iter().rev())
+        .zip(GUESSED_COLORS.iter())
+        .map(|((&palette_ix, &alpha), guessed)| {
+            let [r, g, b] = match palette {
+                Some(palette) => palette[palette_ix as usize],
+                None => *guessed,
+            };
+            // Alpha is 4 bits.
+            Rgba([r, g, b, alpha * 17])
         })
         .collect();
-    Ok(result)
+    SubtitleBitmap {
+        time_span: TimeSpan::new(
+            seconds_to_time_point(subtitle.start_time()),
+            seconds_to_time_point(subtitle.end_time()),
+        ),
+        force: subtitle.force(),
+        x: coordinates.left() as u32,
+        y: coordinates.top() as u32,
+        screen_size,
+        width: coordinates.width() as usize,
+        height: coordinates.height() as usize,
+        pixels: subtitle.raw_image().to_vec(),
+        palette: sub_palette,
+    }
 }
 
 /// Represents the left and right boundaries on a scanline.
@@ -71,117 +423,487 @@ fn seconds_to_time_point(seconds: f64) -> TimePoint {
     TimePoint::from_msecs((seconds * 1000.0) as i64)
 }
 
-/// Convert an sRGB palette to a luminance palette.
-fn rgb_palette_to_luminance(palette: &vobsub::Palette) -> [f32; 16] {
-    palette.map(|x| {
-        let r = srgb_to_linear(x[0]);
-        let g = srgb_to_linear(x[1]);
-        let b = srgb_to_linear(x[2]);
-        0.2126 * r + 0.7152 * g + 0.0722 * b
-    })
+/// Convert an sRGB color to its luminance.
+fn rgb_to_luminance(color: &Rgba<u8>) -> f32 {
+    let r = srgb_to_linear(color[0]);
+    let g = srgb_to_linear(color[1]);
+    let b = srgb_to_linear(color[2]);
+    0.2126 * r + 0.7152 * g + 0.0722 * b
 }
 
 /// Given a subtitle, binarize, invert, and split the image into multiple lines
 /// with borders for direct feeding into Tesseract.
-fn subtitle_to_images(
-    subtitle: &vobsub::Subtitle,
-    palette: &[f32; 16],
-    threshold: f32,
-    border: u32,
-) -> Option<Vec<GrayImage>> {
-    let sub_palette_visibility = generate_visibility_palette(subtitle);
+///
+/// With `grayscale`, the lines are still found by binarizing, but their images
+/// keep the subtitle's anti-aliasing as shades of gray. The other settings are
+/// taken from `opt`.
+pub fn preprocess_subtitle(
+    subtitle: &SubtitleBitmap,
+    threshold: Threshold,
+    grayscale: bool,
+    opt: &OcrOpt,
+) -> Option<PreprocessedVobSubtitle> {
+    let border = opt.border;
+    // Crop first, so that what is cut off doesn't skew the threshold.
+    let mut subtitle = match opt.crop {
+        Some(margins) => crop(subtitle, margins)?,
+        None => Cow::Borrowed(subtitle),
+    };
+    let palette_visibility = generate_visibility_palette(&subtitle);
+    let threshold = match threshold {
+        Threshold::Fixed(threshold) => threshold,
+        Threshold::Auto => otsu_threshold(&subtitle, &palette_visibility),
+    };
 
-    let binarized_palette = binarize_palette(
-        palette,
-        subtitle.palette(),
-        &sub_palette_visibility,
-        threshold,
-    );
+    let mut binarized_palette = binarize_palette(&subtitle.palette, &palette_visibility, threshold);
+    let invert = match opt.invert {
+        Invert::Never => false,
+        Invert::Always => true,
+        Invert::Auto => bright_pixels_outline(&subtitle, &binarized_palette, &palette_visibility),
+    };
+    if invert {
+        for (text, &visible) in binarized_palette.iter_mut().zip(&palette_visibility) {
+            *text = visible && !*text;
+        }
+    }
+    let gray_palette: Vec<u8> = if grayscale {
+        gray_palette(&subtitle.palette, &palette_visibility, invert)
+    } else {
+        binarized_palette
+            .iter()
+            .map(|&text| if text { 0 } else { 255 })
+            .collect()
+    };
 
-    let scanlines = inventory_scanlines(subtitle, &binarized_palette);
-    let scanline_groups = find_contiguous_scanline_groups(&scanlines);
-    if scanline_groups.is_empty() {
+    // Clean the image up, copying it only if that changes anything.
+    if let Some(masked) = mask(&subtitle, &binarized_palette, &opt.exclude_rect) {
+        subtitle = Cow::Owned(masked);
+    }
+    if let Some(cleaned) = opt
+        .despeckle
+        .and_then(|max_size| despeckle(&subtitle, &binarized_palette, max_size))
+    {
+        subtitle = Cow::Owned(cleaned);
+    }
+    let subtitle = &*subtitle;
+
+    let image_regions = match opt.segmentation {
+        Segmentation::Scanlines => {
+            let scanlines = inventory_scanlines(subtitle, &binarized_palette);
+            let scanline_groups = find_contiguous_scanline_groups(&scanlines, opt.join_gap);
+            scanline_groups_to_image_regions(&scanlines, &scanline_groups)
+        }
+        Segmentation::Components => component_regions(subtitle, &binarized_palette),
+    };
+    if image_regions.is_empty() {
         // No images found.
         return None;
     }
 
-    let image_regions = scanline_groups_to_image_regions(&scanlines, &scanline_groups);
-
-    let raw_image_width = subtitle.coordinates().width() as u32;
-
-    Some(
-        image_regions
-            .into_par_iter()
-            .map(|region| {
-                let x0 = region.x.start as u32;
-                let y0 = region.y.start as u32;
-                let width = region.x.len() as u32;
-                let height = region.y.len() as u32;
-                ImageBuffer::from_fn(width + border * 2, height + border * 2, |x, y| {
-                    if x < border || x >= width + border || y < border || y >= height + border {
-                        Luma([255])
-                    } else {
-                        let offset = (y0 + (y - border)) * raw_image_width + x0 + (x - border);
-                        let sub_palette_ix = subtitle.raw_image()[offset as usize] as usize;
-                        if binarized_palette[sub_palette_ix] {
-                            Luma([0])
-                        } else {
-                            Luma([255])
-                        }
-                    }
-                })
-            })
-            .collect(),
-    )
+    let mut image_regions = split_columns(subtitle, &binarized_palette, image_regions);
+    if let Some((min_width, min_height)) = opt.min_region {
+        image_regions.retain(|region| region.x.len() >= min_width && region.y.len() >= min_height);
+        if image_regions.is_empty() {
+            return None;
+        }
+    }
+    let binarized = opt.dumps(DumpStage::Binarized).then(|| {
+        GrayImage::from_fn(subtitle.width as u32, subtitle.height as u32, |x, y| {
+            let palette_ix = subtitle.pixels[y as usize * subtitle.width + x as usize];
+            match binarized_palette.get(palette_ix as usize) {
+                Some(true) => Luma([0]),
+                _ => Luma([255]),
+            }
+        })
+    });
+    let placement = text_placement(subtitle, &image_regions);
+    let text_color = dominant_text_color(
+        subtitle,
+        &binarized_palette,
+        &ImageRegion {
+            x: 0..subtitle.width,
+            y: 0..subtitle.height,
+        },
+    );
+
+    let raw_image_width = subtitle.width as u32;
+
+    let lines = image_regions
+        .into_par_iter()
+        .map(|region| {
+            let x0 = region.x.start as u32;
+            let y0 = region.y.start as u32;
+            let width = region.x.len() as u32;
+            let height = region.y.len() as u32;
+            let image = ImageBuffer::from_fn(width + border * 2, height + border * 2, |x, y| {
+                if x < border || x >= width + border || y < border || y >= height + border {
+                    Luma([255])
+                } else {
+                    let offset = (y0 + (y - border)) * raw_image_width + x0 + (x - border);
+                    let palette_ix = subtitle.pixels[offset as usize] as usize;
+                    Luma([gray_palette[palette_ix]])
+                }
+            });
+            let mut origin = (
+                (subtitle.x + x0) as i32 - border as i32,
+                (subtitle.y + y0) as i32 - border as i32,
+            );
+            let image = if opt.deskew {
+                let (image, shift) = deskew(&image, border);
+                origin.1 += shift;
+                image
+            } else {
+                image
+            };
+            let image = morph::apply(image, &opt.morph);
+            LineImage {
+                italic: opt.italics && is_italic(&image),
+                image,
+                origin,
+                color: dominant_text_color(subtitle, &binarized_palette, &region),
+            }
+        })
+        .collect();
+    Some(PreprocessedVobSubtitle {
+        time_span: subtitle.time_span,
+        force: subtitle.force,
+        placement,
+        text_color,
+        lines,
+        binarized,
+    })
+}
+
+/// Find the area of the screen covered by the text.
+fn text_placement(subtitle: &SubtitleBitmap, image_regions: &[ImageRegion]) -> Placement {
+    let left = image_regions.iter().map(|r| r.x.start).min().unwrap_or(0);
+    let right = image_regions.iter().map(|r| r.x.end).max().unwrap_or(0);
+    let top = image_regions.iter().map(|r| r.y.start).min().unwrap_or(0);
+    let bottom = image_regions.iter().map(|r| r.y.end).max().unwrap_or(0);
+    Placement {
+        x: subtitle.x + left as u32,
+        y: subtitle.y + top as u32,
+        width: (right - left) as u32,
+        height: (bottom - top) as u32,
+        screen_width: subtitle.screen_size.0,
+        screen_height: subtitle.screen_size.1,
+    }
+}
+
+/// Find the most common color among the text pixels in a region.
+fn dominant_text_color(
+    subtitle: &SubtitleBitmap,
+    binarized_palette: &[bool],
+    region: &ImageRegion,
+) -> [u8; 3] {
+    let mut counts = vec![0usize; subtitle.palette.len()];
+    for y in region.y.clone() {
+        let row = &subtitle.pixels[y * subtitle.width..(y + 1) * subtitle.width];
+        for &palette_ix in &row[region.x.clone()] {
+            if binarized_palette.get(palette_ix as usize) == Some(&true) {
+                counts[palette_ix as usize] += 1;
+            }
+        }
+    }
+    counts
+        .iter()
+        .enumerate()
+        .max_by_key(|(_, &count)| count)
+        .map_or([255, 255, 255], |(ix, _)| {
+            let [r, g, b, _] = subtitle.palette[ix].0;
+            [r, g, b]
+        })
 }
 
 /// Find all the palette indices used in this image, and filter out the
 /// transparent ones. Checking each and every single pixel in the image like
 /// this is probably not strictly necessary, but it could theoretically catch an
 /// edge case.
-fn generate_visibility_palette(subtitle: &vobsub::Subtitle) -> [bool; 4] {
-    let mut sub_palette_visibility = subtitle
-        .raw_image()
+fn generate_visibility_palette(subtitle: &SubtitleBitmap) -> Vec<bool> {
+    let palette_len = subtitle.palette.len();
+    let mut palette_visibility = subtitle
+        .pixels
         .par_iter()
         .fold(
-            || [false; 4],
-            |mut visible: [bool; 4], &sub_palette_ix| {
-                visible[sub_palette_ix as usize] = true;
+            || vec![false; palette_len],
+            |mut visible: Vec<bool>, &palette_ix| {
+                if let Some(v) = visible.get_mut(palette_ix as usize) {
+                    *v = true;
+                }
                 visible
             },
         )
         .reduce(
-            || [false; 4],
-            |mut a: [bool; 4], b: [bool; 4]| {
-                a[0] = a[0] || b[0];
-                a[1] = a[1] || b[1];
-                a[2] = a[2] || b[2];
-                a[3] = a[3] || b[3];
+            || vec![false; palette_len],
+            |mut a: Vec<bool>, b: Vec<bool>| {
+                for (a, b) in a.iter_mut().zip(b) {
+                    *a = *a || b;
+                }
                 a
             },
         );
-    // The alpha palette is reversed.
-    for (i, &alpha) in subtitle.alpha().iter().rev().enumerate() {
-        if alpha == 0 {
-            sub_palette_visibility[i] = false;
+    for (visible, color) in palette_visibility.iter_mut().zip(&subtitle.palette) {
+        if color[3] == 0 {
+            *visible = false;
+        }
+    }
+    palette_visibility
+}
+
+/// Cut off the parts of a subtitle within the `--crop` margins of the screen,
+/// copying it only if there are any, or return `None` if nothing is left.
+fn crop(subtitle: &SubtitleBitmap, margins: Crop) -> Option<Cow<'_, SubtitleBitmap>> {
+    let (screen_width, screen_height) = subtitle.screen_size;
+    let left = max(subtitle.x, margins.left);
+    let top = max(subtitle.y, margins.top);
+    let right = min(
+        subtitle.x + subtitle.width as u32,
+        screen_width.saturating_sub(margins.right),
+    )
+    .max(left);
+    let bottom = min(
+        subtitle.y + subtitle.height as u32,
+        screen_height.saturating_sub(margins.bottom),
+    )
+    .max(top);
+    let width = (right - left) as usize;
+    let height = (bottom - top) as usize;
+    if width == 0 || height == 0 {
+        return None;
+    }
+    if width == subtitle.width && height == subtitle.height {
+        return Some(Cow::Borrowed(subtitle));
+    }
+    let x0 = (left - subtitle.x) as usize;
+    let y0 = (top - subtitle.y) as usize;
+    let pixels = (y0..y0 + height)
+        .flat_map(|y| {
+            let start = y * subtitle.width + x0;
+            subtitle.pixels[start..start + width].iter().copied()
+        })
+        .collect();
+    Some(Cow::Owned(SubtitleBitmap {
+        x: left,
+        y: top,
+        width,
+        height,
+        pixels,
+        palette: subtitle.palette.clone(),
+        ..*subtitle
+    }))
+}
+
+/// Paint over the parts of a subtitle within the `--exclude-rect` rectangles
+/// of the screen with a color which isn't text, or return `None` if there are
+/// none or no such color.
+fn mask(
+    subtitle: &SubtitleBitmap,
+    binarized_palette: &[bool],
+    rects: &[Rect],
+) -> Option<SubtitleBitmap> {
+    let mut pixels = None;
+    for rect in rects {
+        let left = rect.x.saturating_sub(subtitle.x) as usize;
+        let top = rect.y.saturating_sub(subtitle.y) as usize;
+        let right = min(
+            (rect.x + rect.width).saturating_sub(subtitle.x) as usize,
+            subtitle.width,
+        );
+        let bottom = min(
+            (rect.y + rect.height).saturating_sub(subtitle.y) as usize,
+            subtitle.height,
+        );
+        if left >= right || top >= bottom {
+            continue;
+        }
+        let background = background_color(subtitle, binarized_palette)?;
+        let pixels = pixels.get_or_insert_with(|| subtitle.pixels.clone());
+        for y in top..bottom {
+            pixels[y * subtitle.width + left..y * subtitle.width + right].fill(background);
+        }
+    }
+    Some(SubtitleBitmap {
+        pixels: pixels?,
+        palette: subtitle.palette.clone(),
+        ..*subtitle
+    })
+}
+
+/// Find a color which isn't text, preferring the most transparent one, which
+/// is usually the background.
+fn background_color(subtitle: &SubtitleBitmap, binarized_palette: &[bool]) -> Option<u8> {
+    (0..subtitle.palette.len())
+        .filter(|&ix| !binarized_palette[ix])
+        .min_by_key(|&ix| subtitle.palette[ix][3])
+        .map(|ix| ix as u8)
+}
+
+/// Remove specks, groups of at most `max_size` touching text pixels, by
+/// painting them with a color which isn't text. Returns `None` if there is
+/// nothing to remove or no such color.
+fn despeckle(
+    subtitle: &SubtitleBitmap,
+    binarized_palette: &[bool],
+    max_size: usize,
+) -> Option<SubtitleBitmap> {
+    let background = background_color(subtitle, binarized_palette)?;
+    let specks: Vec<_> = connected_components(subtitle, binarized_palette)
+        .into_iter()
+        .filter(|component| component.len() <= max_size)
+        .collect();
+    if specks.is_empty() {
+        return None;
+    }
+    let mut pixels = subtitle.pixels.clone();
+    for offset in specks.into_iter().flatten() {
+        pixels[offset] = background;
+    }
+    Some(SubtitleBitmap {
+        pixels,
+        palette: subtitle.palette.clone(),
+        ..*subtitle
+    })
+}
+
+/// Find the groups of touching text pixels, diagonals included, as the
+/// offsets of their pixels.
+fn connected_components(subtitle: &SubtitleBitmap, binarized_palette: &[bool]) -> Vec<Vec<usize>> {
+    let (width, height) = (subtitle.width, subtitle.height);
+    let is_text =
+        |offset: usize| binarized_palette.get(subtitle.pixels[offset] as usize) == Some(&true);
+
+    let mut seen = vec![false; subtitle.pixels.len()];
+    let mut components = Vec::new();
+    for start in 0..subtitle.pixels.len() {
+        if seen[start] || !is_text(start) {
+            continue;
+        }
+        seen[start] = true;
+        let mut component = vec![start];
+        let mut next = 0;
+        while let Some(&offset) = component.get(next) {
+            next += 1;
+            let (x, y) = (offset % width, offset / width);
+            for ny in y.saturating_sub(1)..min(y + 2, height) {
+                for nx in x.saturating_sub(1)..min(x + 2, width) {
+                    let neighbor = ny * width + nx;
+                    if !seen[neighbor] && is_text(neighbor) {
+                        seen[neighbor] = true;
+                        component.push(neighbor);
+                    }
+                }
+            }
+        }
+        components.push(component);
+    }
+    components
+}
+
+/// Find the lines of text by joining the boxes around groups of touching
+/// text pixels: those side by side, unless far enough apart to be separate
+/// columns, and small ones like accents and dots just above or below others.
+fn component_regions(subtitle: &SubtitleBitmap, binarized_palette: &[bool]) -> Vec<ImageRegion> {
+    let width = subtitle.width;
+    let mut regions: Vec<ImageRegion> = connected_components(subtitle, binarized_palette)
+        .into_iter()
+        .map(|component| {
+            let xs = component.iter().map(|offset| offset % width);
+            let ys = component.iter().map(|offset| offset / width);
+            ImageRegion {
+                x: xs.clone().min().unwrap()..xs.max().unwrap() + 1,
+                y: ys.clone().min().unwrap()..ys.max().unwrap() + 1,
+            }
+        })
+        .collect();
+
+    let gap = |a: &Range<usize>, b: &Range<usize>| {
+        max(a.start, b.start).saturating_sub(min(a.end, b.end))
+    };
+    let overlap = |a: &Range<usize>, b: &Range<usize>| a.start < b.end && b.start < a.end;
+    let joined = |a: &ImageRegion, b: &ImageRegion| {
+        let short = min(a.y.len(), b.y.len());
+        let tall = max(a.y.len(), b.y.len());
+        (overlap(&a.y, &b.y) && gap(&a.x, &b.x) < tall * COLUMN_GAP)
+            || (overlap(&a.x, &b.x) && short * 2 <= tall && gap(&a.y, &b.y) <= tall / 4)
+    };
+    // Join regions until none are left to join.
+    let mut i = 0;
+    while i < regions.len() {
+        match (i + 1..regions.len()).find(|&j| joined(&regions[i], &regions[j])) {
+            Some(j) => {
+                let other = regions.swap_remove(j);
+                let region = &mut regions[i];
+                region.x = min(region.x.start, other.x.start)..max(region.x.end, other.x.end);
+                region.y = min(region.y.start, other.y.start)..max(region.y.end, other.y.end);
+                // The bigger region may now reach earlier ones.
+                i = 0;
+            }
+            None => i += 1,
         }
     }
-    sub_palette_visibility
+    regions.sort_by_key(|region| (region.y.start, region.x.start));
+    regions
+}
+
+/// Find the threshold which best separates the luminances of a subtitle's
+/// visible pixels into two groups, such as the text and its outline, with
+/// Otsu's method.
+fn otsu_threshold(subtitle: &SubtitleBitmap, palette_visibility: &[bool]) -> f32 {
+    let mut counts = vec![0usize; subtitle.palette.len()];
+    for &palette_ix in &subtitle.pixels {
+        if let Some(count) = counts.get_mut(palette_ix as usize) {
+            *count += 1;
+        }
+    }
+    // The luminance of each visible color and its number of pixels, darkest
+    // first.
+    let mut colors: Vec<(f32, f32)> = subtitle
+        .palette
+        .iter()
+        .zip(palette_visibility)
+        .zip(&counts)
+        .filter(|((_, &visible), _)| visible)
+        .map(|((color, _), &count)| (rgb_to_luminance(color), count as f32))
+        .collect();
+    colors.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+    let max_luminance = colors.last().map_or(0.0, |&(luminance, _)| luminance);
+    let total: f32 = colors.iter().map(|&(_, count)| count).sum();
+    let total_sum: f32 = colors
+        .iter()
+        .map(|&(luminance, count)| luminance * count)
+        .sum();
+
+    // With a single color, any threshold below 1 makes it text.
+    let mut best = (0.0, 0.5);
+    let (mut weight, mut sum) = (0.0, 0.0);
+    for pair in colors.windows(2) {
+        let (luminance, count) = pair[0];
+        weight += count;
+        sum += luminance * count;
+        if pair[1].0 == luminance {
+            continue;
+        }
+        let mean_below = sum / weight;
+        let mean_above = (total_sum - sum) / (total - weight);
+        let variance = weight * (total - weight) * (mean_below - mean_above).powi(2);
+        if variance > best.0 {
+            best = (variance, (luminance + pair[1].0) / 2.0 / max_luminance);
+        }
+    }
+    best.1
 }
 
 /// Generate a binarized palette where `true` represents a filled text pixel.
 fn binarize_palette(
-    palette: &[f32; 16],
-    sub_palette: &[u8; 4],
-    sub_palette_visibility: &[bool; 4],
+    palette: &[Rgba<u8>],
+    palette_visibility: &[bool],
     threshold: f32,
-) -> [bool; 4] {
+) -> Vec<bool> {
     // Find the max luminance, so we can scale each luminance value by it.
-    // Reminder that the sub palette is reversed.
     let mut max_luminance = 0.0;
-    for (&palette_ix, &visible) in sub_palette.iter().rev().zip(sub_palette_visibility) {
+    for (color, &visible) in palette.iter().zip(palette_visibility) {
         if visible {
-            let luminance = palette[palette_ix as usize];
+            let luminance = rgb_to_luminance(color);
             if luminance > max_luminance {
                 max_luminance = luminance;
             }
@@ -190,16 +912,15 @@ fn binarize_palette(
 
     // Empty image?
     if max_luminance == 0.0 {
-        return [false; 4];
+        return vec![false; palette.len()];
     }
 
-    sub_palette
-        .into_iter_fixed()
-        .rev()
-        .zip(sub_palette_visibility)
-        .map(|(&palette_ix, &visible)| {
+    palette
+        .iter()
+        .zip(palette_visibility)
+        .map(|(color, &visible)| {
             if visible {
-                let luminance = palette[palette_ix as usize] / max_luminance;
+                let luminance = rgb_to_luminance(color) / max_luminance;
                 luminance > threshold
             } else {
                 false
@@ -208,15 +929,81 @@ fn binarize_palette(
         .collect()
 }
 
+/// Find the shade of gray to draw each palette color with: black for the
+/// brightest visible color, fading to white with luminance and transparency,
+/// or the other way around for the darkest color with `invert`.
+fn gray_palette(palette: &[Rgba<u8>], palette_visibility: &[bool], invert: bool) -> Vec<u8> {
+    let max_luminance = palette
+        .iter()
+        .zip(palette_visibility)
+        .filter(|(_, &visible)| visible)
+        .map(|(color, _)| rgb_to_luminance(color))
+        .fold(0.0, f32::max);
+    palette
+        .iter()
+        .zip(palette_visibility)
+        .map(|(color, &visible)| {
+            if visible && max_luminance > 0.0 {
+                let mut brightness = rgb_to_luminance(color) / max_luminance;
+                if invert {
+                    brightness = 1.0 - brightness;
+                }
+                let ink = brightness * color[3] as f32 / 255.0;
+                255 - (ink * 255.0).round() as u8
+            } else {
+                255
+            }
+        })
+        .collect()
+}
+
+/// Whether the text pixels found by binarizing surround the rest, like an
+/// outline would: they border transparent pixels more often than the other
+/// visible pixels do. That happens with dark text in a light outline.
+fn bright_pixels_outline(
+    subtitle: &SubtitleBitmap,
+    binarized_palette: &[bool],
+    palette_visibility: &[bool],
+) -> bool {
+    let (width, height) = (subtitle.width, subtitle.height);
+    let visible = |x: usize, y: usize| {
+        palette_visibility.get(subtitle.pixels[y * width + x] as usize) == Some(&true)
+    };
+    // Counts of bright and dark visible pixels, and of those on the edge.
+    let mut bright = (0usize, 0usize);
+    let mut dark = (0usize, 0usize);
+    for y in 0..height {
+        for x in 0..width {
+            if !visible(x, y) {
+                continue;
+            }
+            let edge = x == 0
+                || y == 0
+                || x + 1 == width
+                || y + 1 == height
+                || !visible(x - 1, y)
+                || !visible(x + 1, y)
+                || !visible(x, y - 1)
+                || !visible(x, y + 1);
+            let counts = if binarized_palette[subtitle.pixels[y * width + x] as usize] {
+                &mut bright
+            } else {
+                &mut dark
+            };
+            counts.0 += 1;
+            counts.1 += edge as usize;
+        }
+    }
+    // Compare the fractions on the edge without dividing.
+    bright.0 > 0 && dark.0 > 0 && bright.1 * dark.0 > dark.1 * bright.0
+}
+
 /// Inventory each scanline of the image, recording if a given scanline has
 /// text pixels, and if it does, the left and right extents of the pixels on
 /// the scanline.
-fn inventory_scanlines(
-    subtitle: &vobsub::Subtitle,
-    palette: &[bool; 4],
-) -> Vec<Option<ScanlineExtent>> {
-    let width = subtitle.coordinates().width() as usize;
-    let height = subtitle.coordinates().height() as usize;
+fn inventory_scanlines(subtitle: &SubtitleBitmap, palette: &[bool]) -> Vec<Option<ScanlineExtent>> {
+    let width = subtitle.width;
+    let height = subtitle.height;
     (0..height)
         .into_par_iter()
         .map(|y| {
@@ -226,7 +1013,7 @@ fn inventory_scanlines(
                     || None,
                     |scanline: Option<ScanlineExtent>, x| {
                         let offset = y * width + x;
-                        let palette_ix = subtitle.raw_image()[offset] as usize;
+                        let palette_ix = subtitle.pixels[offset] as usize;
                         if palette[palette_ix] {
                             match scanline {
                                 Some(extent) => Some(ScanlineExtent {
@@ -257,8 +1044,12 @@ fn inventory_scanlines(
         .collect()
 }
 
-/// Find ranges of contiguous, filled scanlines.
-fn find_contiguous_scanline_groups(scanlines: &[Option<ScanlineExtent>]) -> Vec<Range<usize>> {
+/// Find ranges of contiguous, filled scanlines, joining those separated by at
+/// most `join_gap` empty scanlines.
+fn find_contiguous_scanline_groups(
+    scanlines: &[Option<ScanlineExtent>],
+    join_gap: usize,
+) -> Vec<Range<usize>> {
     let mut scanline_groups: Vec<Range<usize>> = Vec::new();
     let mut scanline_ix = 0;
     while scanline_ix < scanlines.len() {
@@ -274,7 +1065,11 @@ fn find_contiguous_scanline_groups(scanlines: &[Option<ScanlineExtent>]) -> Vec<
                     Some(end_ix_offset) => scanline_ix + start_ix_offset + end_ix_offset,
                     None => scanlines.len(),
                 };
-                scanline_groups.push((scanline_ix + start_ix_offset)..end_ix);
+                let start_ix = scanline_ix + start_ix_offset;
+                match scanline_groups.last_mut() {
+                    Some(previous) if start_ix - previous.end <= join_gap => previous.end = end_ix,
+                    _ => scanline_groups.push(start_ix..end_ix),
+                }
                 scanline_ix = end_ix;
             }
             None => break,
@@ -294,10 +1089,8 @@ fn scanline_groups_to_image_regions(
         .map(|y_range| {
             let mut left = usize::MAX;
             let mut right = usize::MIN;
-            for y in y_range.clone() {
-                // Unwrap here, since we should have filtered out all None
-                // scanlines before calling this.
-                let x = scanlines[y].as_ref().unwrap();
+            // Skip the empty scanlines of joined gaps.
+            for x in scanlines[y_range.clone()].iter().flatten() {
                 if x.left < left {
                     left = x.left;
                 }
@@ -313,6 +1106,141 @@ fn scanline_groups_to_image_regions(
         .collect()
 }
 
+/// Split regions where text sits side by side, such as two lines of dialogue
+/// on the same scanlines, at each gap between columns of text which is wider
+/// than [`COLUMN_GAP`] times the region's height.
+fn split_columns(
+    subtitle: &SubtitleBitmap,
+    binarized_palette: &[bool],
+    regions: Vec<ImageRegion>,
+) -> Vec<ImageRegion> {
+    let mut split = Vec::with_capacity(regions.len());
+    for region in regions {
+        let min_gap = region.y.len() * COLUMN_GAP;
+        let filled: Vec<bool> = region
+            .x
+            .clone()
+            .map(|x| {
+                region.y.clone().any(|y| {
+                    let palette_ix = subtitle.pixels[y * subtitle.width + x] as usize;
+                    binarized_palette.get(palette_ix) == Some(&true)
+                })
+            })
+            .collect();
+        // Regions are trimmed to their text, so they start and end filled.
+        let mut start = 0;
+        let mut gap_start = None;
+        for (i, &filled) in filled.iter().enumerate() {
+            match (filled, gap_start) {
+                (false, None) => gap_start = Some(i),
+                (true, Some(gap)) => {
+                    if i - gap >= min_gap {
+                        split.push(ImageRegion {
+                            x: region.x.start + start..region.x.start + gap,
+                            y: region.y.clone(),
+                        });
+                        start = i;
+                    }
+                    gap_start = None;
+                }
+                _ => {}
+            }
+        }
+        split.push(ImageRegion {
+            x: region.x.start + start..region.x.end,
+            y: region.y,
+        });
+    }
+    split
+}
+
+/// Guess whether a line image is in italics, by finding the slant which lines
+/// the strokes of its letters up best, in which case its columns are filled
+/// the most unevenly.
+fn is_italic(image: &GrayImage) -> bool {
+    let (width, height) = image.dimensions();
+    let text: Vec<(u32, u32)> = image
+        .enumerate_pixels()
+        .filter(|(_, _, pixel)| pixel[0] < 128)
+        .map(|(x, y, _)| (x, y))
+        .collect();
+    // Shearing the image back by the slant shifts each row to the right by
+    // how far below the top it is.
+    let unevenness = |degrees: f32| {
+        let shear = degrees.to_radians().tan();
+        let mut columns = vec![0u64; (width as f32 + height as f32 * shear) as usize + 1];
+        for &(x, y) in &text {
+            columns[(x as f32 + y as f32 * shear) as usize] += 1;
+        }
+        columns.iter().map(|count| count * count).sum::<u64>()
+    };
+    let mut best = (0.0, unevenness(0.0));
+    for degrees in SLANTS.iter().copied() {
+        let score = unevenness(degrees);
+        if score > best.1 {
+            best = (degrees, score);
+        }
+    }
+    best.0 >= ITALIC_SLANT
+}
+
+/// Straighten a slightly rotated line image, by finding the skew which lines
+/// its text up best, in which case its rows are filled the most unevenly, and
+/// shifting each column back by it. The result is trimmed to the text and
+/// `border`, and returned with how far down the original image its top is.
+fn deskew(image: &GrayImage, border: u32) -> (GrayImage, i32) {
+    let (width, height) = image.dimensions();
+    let text: Vec<(u32, u32)> = image
+        .enumerate_pixels()
+        .filter(|(_, _, pixel)| pixel[0] < 128)
+        .map(|(x, y, _)| (x, y))
+        .collect();
+    // How far each column is shifted up, keeping the middle column in place.
+    let shifts = |degrees: f32| -> Vec<i32> {
+        let skew = degrees.to_radians().tan();
+        (0..width)
+            .map(|x| ((x as f32 - width as f32 / 2.0) * skew).round() as i32)
+            .collect()
+    };
+    let unevenness = |shifts: &[i32]| {
+        let mut rows = HashMap::new();
+        for &(x, y) in &text {
+            *rows.entry(y as i32 - shifts[x as usize]).or_insert(0u64) += 1;
+        }
+        rows.values().map(|count| count * count).sum::<u64>()
+    };
+    let steps = (MAX_SKEW / SKEW_STEP) as i32;
+    let mut best = (0.0, unevenness(&shifts(0.0)));
+    for degrees in (-steps..=steps).map(|step| step as f32 * SKEW_STEP) {
+        let score = unevenness(&shifts(degrees));
+        if score > best.1 {
+            best = (degrees, score);
+        }
+    }
+    if best.0 == 0.0 {
+        return (image.clone(), 0);
+    }
+
+    // Shift every column down by the highest shift so that none go above the
+    // top, and find the rows holding text.
+    let shifts = shifts(best.0);
+    let highest = shifts.iter().copied().max().unwrap_or(0);
+    let rows = text
+        .iter()
+        .map(|&(x, y)| y as i32 - shifts[x as usize] + highest);
+    let top = rows.clone().min().unwrap_or(0) - border as i32;
+    let bottom = rows.max().unwrap_or(0) + border as i32 + 1;
+    let straightened = GrayImage::from_fn(width, (bottom - top) as u32, |x, y| {
+        let source = y as i32 + top + shifts[x as usize] - highest;
+        if (0..height as i32).contains(&source) {
+            *image.get_pixel(x, source as u32)
+        } else {
+            Luma([255])
+        }
+    });
+    (straightened, top - highest)
+}
+
 /// Convert an sRGB color space channel to linear.
 fn srgb_to_linear(channel: u8) -> f32 {
     let value = channel as f32 / 255.0;
@@ -0,0 +1,159 @@
+//! Normalizing punctuation and the spaces around it following the rules of
+//! the OCR language, for `--normalize-punctuation`.
+//!
+//! OCR tends to get spaces next to punctuation wrong, adding them where they
+//! don't belong or, in French, dropping the ones which do.
+
+/// No-break space, which French puts before a colon.
+const NBSP: char = '\u{a0}';
+
+/// Narrow no-break space, which French puts before `?`, `!` and `;` and
+/// inside guillemets.
+const NNBSP: char = '\u{202f}';
+
+/// Punctuation which follows words without a space in most languages.
+const TRAILING: &[char] = &[',', '.', '?', '!', ':', ';', '…'];
+
+/// Punctuation ending a sentence.
+pub const ENDING: &[char] = &['.', '?', '!', '…'];
+
+/// Dashes which start a line of dialogue.
+pub const DASHES: &[char] = &['-', '–', '—'];
+
+/// Normalize the punctuation of a subtitle's text, keeping its line breaks, for
+/// a Tesseract language.
+pub fn normalize(text: &str, language: &str) -> String {
+    match language {
+        "fra" => french_spaces(&tighten(text, &[',', '.', '…'])),
+        "spa" => inverted_marks(&tighten(text, TRAILING))
+            .replace("¿ ", "¿")
+            .replace("¡ ", "¡"),
+        "deu" => german_quotes(&tighten(text, TRAILING)),
+        _ => tighten(text, TRAILING),
+    }
+}
+
+/// Remove spaces between words, or closing quotes and parentheses, and the
+/// punctuation which follows them.
+fn tighten(text: &str, punctuation: &[char]) -> String {
+    let mut normalized = String::with_capacity(text.len());
+    let mut spaces = String::new();
+    for c in text.chars() {
+        if c == ' ' {
+            spaces.push(c);
+            continue;
+        }
+        let after_word = matches!(
+            normalized.chars().next_back(),
+            Some(p) if p.is_alphanumeric() || matches!(p, '"' | '”' | '»' | ')')
+        );
+        if !(after_word && punctuation.contains(&c)) {
+            normalized.push_str(&spaces);
+        }
+        spaces.clear();
+        normalized.push(c);
+    }
+    normalized.push_str(&spaces);
+    normalized
+}
+
+/// Put the no-break spaces French uses before `?`, `!`, `;` and `:` and
+/// inside guillemets, replacing any ordinary spaces there.
+fn french_spaces(text: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut normalized = String::with_capacity(text.len());
+    for (i, &c) in chars.iter().enumerate() {
+        let previous = normalized.chars().next_back();
+        let next = chars.get(i + 1).copied();
+        let space = match c {
+            '?' | '!' | ';' | '»' => Some(NNBSP),
+            // Not in times like 10:30.
+            ':' if !(matches!(previous, Some(p) if p.is_ascii_digit())
+                && matches!(next, Some(n) if n.is_ascii_digit())) =>
+            {
+                Some(NBSP)
+            }
+            _ => None,
+        };
+        match space {
+            // Runs of punctuation like `?!` only get one space.
+            Some(space) if !matches!(previous, None | Some('\n' | '?' | '!' | ';' | ':')) => {
+                while normalized.ends_with(&[' ', NBSP, NNBSP][..]) {
+                    normalized.pop();
+                }
+                normalized.push(space);
+            }
+            _ => {}
+        }
+        if c == ' ' && normalized.ends_with(&format!("«{}", NNBSP)) {
+            continue;
+        }
+        normalized.push(c);
+        if c == '«' {
+            normalized.push(NNBSP);
+        }
+    }
+    normalized
+}
+
+/// Open questions and exclamations without the inverted marks Spanish starts
+/// them with.
+fn inverted_marks(text: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut normalized = String::with_capacity(text.len() + 4);
+    let mut sentence = 0;
+    let mut i = 0;
+    while i < chars.len() {
+        // A line starting with a dash starts another speaker's sentence.
+        if chars[i] == '\n' && matches!(chars.get(i + 1), Some(c) if DASHES.contains(c)) {
+            normalized.extend(&chars[sentence..=i]);
+            sentence = i + 1;
+        }
+        if !ENDING.contains(&chars[i]) {
+            i += 1;
+            continue;
+        }
+        // Find the start of the sentence, past dialogue dashes and spaces.
+        let start = sentence
+            + chars[sentence..i]
+                .iter()
+                .position(|c| !(c.is_whitespace() || DASHES.contains(c)))
+                .unwrap_or(i - sentence);
+        normalized.extend(&chars[sentence..start]);
+        let opening = match chars[i] {
+            '?' => Some('¿'),
+            '!' => Some('¡'),
+            _ => None,
+        };
+        if let Some(opening) = opening {
+            let body = &chars[start..i];
+            if !body.contains(&opening) && body.iter().any(|c| c.is_alphabetic()) {
+                normalized.push(opening);
+            }
+        }
+        // Keep runs of punctuation like `?!` and `...` together.
+        let end = i + chars[i..].iter().take_while(|c| ENDING.contains(c)).count();
+        normalized.extend(&chars[start..end]);
+        sentence = end;
+        i = end;
+    }
+    normalized.extend(&chars[sentence..]);
+    normalized
+}
+
+/// Turn straight double quotes into the German „ and “.
+fn german_quotes(text: &str) -> String {
+    let mut normalized = String::with_capacity(text.len());
+    for c in text.chars() {
+        if c != '"' {
+            normalized.push(c);
+            continue;
+        }
+        let opening = matches!(
+            normalized.chars().next_back(),
+            None | Some('(' | ' ' | '\n')
+        ) || normalized.ends_with(DASHES);
+        normalized.push(if opening { '„' } else { '“' });
+    }
+    normalized
+}
@@ -0,0 +1,107 @@
+//! Sentence casing subtitles which are entirely in capitals, for `--recase`.
+//!
+//! Words are lowercased and then capitalized at the start of each sentence,
+//! and wherever they are in a list of proper nouns given with
+//! `--proper-nouns` or, when spellchecking, only known capitalized.
+
+use std::{collections::HashMap, fs, io, path::PathBuf};
+
+use crate::{
+    ocr::Subtitle,
+    punctuation::{DASHES, ENDING},
+    spelling,
+};
+use snafu::{ResultExt, Snafu};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Could not read proper nouns {}: {}", filename.display(), source))]
+    Read {
+        filename: PathBuf,
+        source: io::Error,
+    },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Words spelled the same way wherever they are, by their lowercase forms.
+#[derive(Debug, Clone)]
+pub struct ProperNouns(HashMap<String, String>);
+
+impl ProperNouns {
+    /// Read the words of a file, separated by whitespace, like a Tesseract
+    /// user words file. Lines starting with `#` are skipped.
+    pub fn read(filename: &str) -> Result<Self> {
+        let contents = fs::read_to_string(filename).context(ReadSnafu { filename })?;
+        Ok(Self(
+            contents
+                .lines()
+                .filter(|line| !line.trim_start().starts_with('#'))
+                .flat_map(str::split_whitespace)
+                .map(|word| (word.to_lowercase(), word.to_owned()))
+                .collect(),
+        ))
+    }
+}
+
+/// Sentence case the subtitles without any lowercase letters, following
+/// sentences from one subtitle into the next.
+pub fn recase(
+    subtitles: &mut [Subtitle],
+    proper_nouns: &[ProperNouns],
+    dictionary: Option<&spelling::Dictionary>,
+    languages: &[String],
+) {
+    let english = languages.iter().any(|language| language == "eng");
+    let case = |word: &str, sentence_start: bool| {
+        // Look possessives and other contractions up by the word before the
+        // apostrophe too.
+        let stem = word.find(&['\'', '’'][..]).unwrap_or(word.len());
+        for (key, rest) in [(word, ""), (&word[..stem], &word[stem..])] {
+            if let Some(proper) = proper_nouns.iter().find_map(|nouns| nouns.0.get(key)) {
+                return format!("{}{}", proper, rest);
+            }
+        }
+        let pronoun = word == "i" || word.starts_with("i'") || word.starts_with("i’");
+        if english && pronoun {
+            return spelling::capitalize(word);
+        }
+        match dictionary.and_then(|dictionary| dictionary.proper_case(word)) {
+            Some(proper) => proper,
+            None if sentence_start => spelling::capitalize(word),
+            None => word.to_owned(),
+        }
+    };
+
+    let mut sentence_start = true;
+    for subtitle in subtitles {
+        let capitals = !subtitle.text().chars().any(char::is_lowercase);
+        for line in &mut subtitle.lines {
+            if line.text.trim_start().starts_with(DASHES) {
+                sentence_start = true;
+            }
+            let text = if capitals {
+                line.text.to_lowercase()
+            } else {
+                line.text.clone()
+            };
+            let mut recased = String::with_capacity(text.len());
+            let mut copied = 0;
+            for (start, end) in spelling::words(&text) {
+                let between = &text[copied..start];
+                sentence_start |= between.contains(ENDING);
+                recased.push_str(between);
+                if capitals {
+                    recased.push_str(&case(&text[start..end], sentence_start));
+                } else {
+                    recased.push_str(&text[start..end]);
+                }
+                sentence_start = false;
+                copied = end;
+            }
+            sentence_start |= text[copied..].contains(ENDING);
+            recased.push_str(&text[copied..]);
+            line.text = recased;
+        }
+    }
+}
@@ -0,0 +1,83 @@
+//! Rules fixing recurring OCR mistakes in the recognized text with regular
+//! expressions, read from files given with `--replace`.
+//!
+//! Each line of a rules file holds a pattern and its replacement separated by
+//! ` => `, like `\bI'rn\b => I'm`. The replacement may refer to the pattern's
+//! groups as `$1` or `${name}`. Blank lines and lines starting with `#` are
+//! skipped.
+
+use std::{fs, io, path::PathBuf};
+
+use regex::Regex;
+use snafu::{OptionExt, ResultExt, Snafu};
+
+/// What separates a pattern from its replacement.
+const SEPARATOR: &str = " => ";
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Could not read replacement rules {}: {}", filename.display(), source))]
+    Read {
+        filename: PathBuf,
+        source: io::Error,
+    },
+
+    #[snafu(display(
+        "Expected `pattern => replacement` on line {} of {}",
+        line,
+        filename.display()
+    ))]
+    Separator { filename: PathBuf, line: usize },
+
+    #[snafu(display(
+        "Invalid pattern on line {} of {}: {}",
+        line,
+        filename.display(),
+        source
+    ))]
+    Pattern {
+        filename: PathBuf,
+        line: usize,
+        source: regex::Error,
+    },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The rules of one file, applied in order.
+#[derive(Debug, Clone)]
+pub struct Rules(Vec<(Regex, String)>);
+
+impl Rules {
+    /// Read the rules from a file.
+    pub fn read(filename: &str) -> Result<Self> {
+        let contents = fs::read_to_string(filename).context(ReadSnafu { filename })?;
+        let mut rules = Vec::new();
+        for (i, line) in contents.lines().enumerate() {
+            if line.trim().is_empty() || line.trim_start().starts_with('#') {
+                continue;
+            }
+            let (pattern, replacement) = line.split_once(SEPARATOR).context(SeparatorSnafu {
+                filename,
+                line: i + 1,
+            })?;
+            let pattern = Regex::new(pattern.trim_start()).context(PatternSnafu {
+                filename,
+                line: i + 1,
+            })?;
+            rules.push((pattern, replacement.to_owned()));
+        }
+        Ok(Self(rules))
+    }
+
+    /// Apply every rule to a line of text.
+    pub fn apply(&self, text: &str) -> String {
+        self.0
+            .iter()
+            .fold(text.to_owned(), |text, (pattern, replacement)| {
+                pattern
+                    .replace_all(&text, replacement.as_str())
+                    .into_owned()
+            })
+    }
+}
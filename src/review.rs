@@ -0,0 +1,136 @@
+//! Writing an HTML page for proofreading, showing each subtitle's image next
+//! to the text recognized from it.
+
+use std::{
+    fmt::Write as _,
+    fs,
+    io::{self, Cursor},
+    path::Path,
+};
+
+use crate::{
+    format::format_time,
+    ocr::{self, Subtitle},
+    preprocessor::PreprocessedVobSubtitle,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use image::{DynamicImage, GrayImage, ImageOutputFormat};
+use snafu::{ResultExt, Snafu};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Could not encode image: {}", source))]
+    EncodeImage { source: image::ImageError },
+
+    #[snafu(display("{}", source))]
+    Io { source: io::Error },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Subtitles with a line of lower confidence than this are highlighted.
+const LOW_CONFIDENCE: i32 = 70;
+
+const HEADER: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>vobsubocr review</title>
+<style>
+body { font-family: sans-serif; background: #eee; }
+table { border-collapse: collapse; }
+td { border-bottom: 1px solid #ccc; padding: 0.5em; vertical-align: top; }
+td.time { white-space: nowrap; color: #666; }
+td.text { font-size: 1.2em; white-space: pre; }
+img { background: #fff; }
+.low { background: #fdd; }
+.error { color: #c00; }
+</style>
+</head>
+<body>
+<table>
+"#;
+
+const FOOTER: &str = "</table>\n</body>\n</html>\n";
+
+/// Encode the images Tesseract is given for a subtitle, stacked on top of each
+/// other, as a PNG data URI.
+pub fn thumbnail(vobsub: &PreprocessedVobSubtitle) -> Result<String> {
+    let images = vobsub.lines.iter().map(|line| &line.image);
+    let width = images.clone().map(|i| i.width()).max().unwrap_or(0);
+    let height = images.clone().map(|i| i.height()).sum();
+    let mut stacked = GrayImage::from_pixel(width, height, image::Luma([255]));
+    let mut y = 0;
+    for image in images {
+        image::imageops::replace(&mut stacked, image, 0, y);
+        y += image.height() as i64;
+    }
+    let mut png = Cursor::new(Vec::new());
+    DynamicImage::ImageLuma8(stacked)
+        .write_to(&mut png, ImageOutputFormat::Png)
+        .context(EncodeImageSnafu {})?;
+    Ok(format!(
+        "data:image/png;base64,{}",
+        STANDARD.encode(png.get_ref())
+    ))
+}
+
+/// Write the review page, with one row for each OCR result and its thumbnail.
+pub fn write_review(
+    path: impl AsRef<Path>,
+    subtitles: &[ocr::Result<Subtitle>],
+    thumbnails: &[String],
+) -> Result<()> {
+    let mut html = String::from(HEADER);
+    for (i, (subtitle, thumbnail)) in subtitles.iter().zip(thumbnails).enumerate() {
+        html.push_str("<tr>");
+        write!(html, "<td>{}</td>", i + 1).unwrap();
+        match subtitle {
+            Ok(subtitle) => {
+                write!(
+                    html,
+                    "<td class=\"time\">{}<br>{}</td>",
+                    format_time(subtitle.time_span.start),
+                    format_time(subtitle.time_span.end)
+                )
+                .unwrap();
+                write!(html, "<td><img src=\"{}\"></td>", thumbnail).unwrap();
+                let low = subtitle
+                    .lines
+                    .iter()
+                    .any(|line| line.confidence < LOW_CONFIDENCE);
+                write!(
+                    html,
+                    "<td class=\"text{}\">{}",
+                    if low { " low" } else { "" },
+                    escape(&subtitle.text())
+                )
+                .unwrap();
+                html.push_str("</td><td>");
+                for line in &subtitle.lines {
+                    write!(html, "{}<br>", line.confidence).unwrap();
+                }
+                html.push_str("</td>");
+            }
+            Err(e) => {
+                write!(
+                    html,
+                    "<td></td><td><img src=\"{}\"></td><td class=\"error\" colspan=\"2\">{}</td>",
+                    thumbnail,
+                    escape(&e.to_string())
+                )
+                .unwrap();
+            }
+        }
+        html.push_str("</tr>\n");
+    }
+    html.push_str(FOOTER);
+    fs::write(path, html).context(IoSnafu {})
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
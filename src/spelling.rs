@@ -0,0 +1,549 @@
+//! Checking the spelling of the recognized text against Hunspell dictionaries,
+//! for `--spellcheck`.
+//!
+//! Hunspell dictionaries come as a `.dic` file of stems, each with flags naming
+//! the prefixes and suffixes it takes, and a `.aff` file defining those. The
+//! stems are expanded into every word they make up front, which is enough for
+//! looking words up; compounding and the other rules which only Hunspell
+//! itself implements are ignored, so some words Hunspell knows are flagged.
+
+use std::{
+    collections::{HashMap, HashSet},
+    env, fs, io, iter,
+    path::{Path, PathBuf},
+};
+
+use crate::{format::format_time, language, ocr::Subtitle};
+use log::info;
+use snafu::{ResultExt, Snafu};
+use subparse::timetypes::TimePoint;
+
+/// Where Hunspell dictionaries are usually installed, after those in
+/// `DICPATH`.
+const DICTIONARY_DIRS: &[&str] = &[
+    "/usr/share/hunspell",
+    "/usr/share/myspell",
+    "/usr/share/myspell/dicts",
+    "/usr/local/share/hunspell",
+    "/Library/Spelling",
+];
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Could not read dictionary {}: {}", filename.display(), source))]
+    Read {
+        filename: PathBuf,
+        source: io::Error,
+    },
+
+    #[snafu(display(
+        "No Hunspell dictionary found for `{}`; install one or use --dictionary",
+        language
+    ))]
+    MissingDictionary { language: String },
+
+    #[snafu(display("Could not write spelling report {}: {}", filename.display(), source))]
+    WriteReport {
+        filename: PathBuf,
+        source: io::Error,
+    },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Every word of some dictionaries.
+pub struct Dictionary {
+    words: HashSet<String>,
+    /// Lowercase letters to try when looking for corrections.
+    alphabet: Vec<char>,
+}
+
+/// A word the dictionaries didn't know.
+pub struct UnknownWord {
+    /// The number of the subtitle in its output file, from 1.
+    pub number: usize,
+    pub time: TimePoint,
+    /// OCR's confidence in the word's line.
+    pub confidence: i32,
+    pub word: String,
+    pub line: String,
+}
+
+impl Dictionary {
+    /// Read the given dictionaries, or else find those for the OCR languages.
+    pub fn open(filenames: &[PathBuf], languages: &[String]) -> Result<Self> {
+        let found;
+        let filenames = if filenames.is_empty() {
+            found = languages
+                .iter()
+                .map(|language| {
+                    find_dictionary(language).ok_or_else(|| Error::MissingDictionary {
+                        language: language.clone(),
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?;
+            &found
+        } else {
+            filenames
+        };
+        let mut dictionary = Self {
+            words: HashSet::new(),
+            alphabet: Vec::new(),
+        };
+        for filename in filenames {
+            dictionary.read(filename)?;
+            info!("Checking spelling with {}", filename.display());
+        }
+        Ok(dictionary)
+    }
+
+    /// Add the words of a `.dic` file, expanded with the affixes of the `.aff`
+    /// file next to it.
+    fn read(&mut self, filename: &Path) -> Result<()> {
+        let aff_filename = filename.with_extension("aff");
+        let aff = read_text(&aff_filename, None)?;
+        let affixes = Affixes::parse(&aff);
+        let dic = read_text(filename, affixes.encoding.as_deref())?;
+
+        // The first line holds the number of stems.
+        for line in dic.lines().skip(1) {
+            let entry = line.split_whitespace().next().unwrap_or("");
+            let (stem, flags) = match entry.find('/') {
+                Some(slash) => (&entry[..slash], affixes.parse_flags(&entry[slash + 1..])),
+                None => (entry, Vec::new()),
+            };
+            if stem.is_empty() {
+                continue;
+            }
+            affixes.expand(stem, &flags, &mut self.words);
+        }
+
+        let try_letters = affixes.try_letters.iter().flat_map(|c| c.to_lowercase());
+        for c in try_letters {
+            if !self.alphabet.contains(&c) {
+                self.alphabet.push(c);
+            }
+        }
+        if self.alphabet.is_empty() {
+            let mut letters: Vec<char> = self
+                .words
+                .iter()
+                .flat_map(|word| word.chars())
+                .filter(|c| c.is_lowercase())
+                .collect::<HashSet<_>>()
+                .into_iter()
+                .collect();
+            letters.sort_unstable();
+            self.alphabet = letters;
+        }
+        Ok(())
+    }
+
+    /// Whether a word is known, in its own case, in lowercase, or capitalized
+    /// if it is in capitals.
+    pub fn check(&self, word: &str) -> bool {
+        if self.words.contains(word) {
+            return true;
+        }
+        let lower = word.to_lowercase();
+        self.words.contains(&lower)
+            || (word.chars().all(|c| !c.is_lowercase()) && self.words.contains(&capitalize(&lower)))
+    }
+
+    /// How a lowercase word is spelled if it is only known capitalized, like a
+    /// name, or in capitals, like an acronym.
+    pub fn proper_case(&self, word: &str) -> Option<String> {
+        let key = word.replace('’', "'");
+        if self.words.contains(&key) {
+            None
+        } else if self.words.contains(&capitalize(&key)) {
+            Some(capitalize(word))
+        } else if self.words.contains(&key.to_uppercase()) {
+            Some(word.to_uppercase())
+        } else {
+            None
+        }
+    }
+
+    /// The only known word a single letter away from a word, if there is
+    /// exactly one, in the word's case.
+    pub fn correction(&self, word: &str) -> Option<String> {
+        let lower: Vec<char> = word.to_lowercase().chars().collect();
+        let mut candidates = HashSet::new();
+        let mut consider = |candidate: Vec<char>| {
+            let candidate: String = candidate.into_iter().collect();
+            if self.check(&candidate) {
+                candidates.insert(candidate);
+            }
+        };
+        for i in 0..lower.len() {
+            let mut deleted = lower.clone();
+            deleted.remove(i);
+            consider(deleted);
+            if i + 1 < lower.len() {
+                let mut transposed = lower.clone();
+                transposed.swap(i, i + 1);
+                consider(transposed);
+            }
+            for &c in &self.alphabet {
+                if c != lower[i] {
+                    let mut replaced = lower.clone();
+                    replaced[i] = c;
+                    consider(replaced);
+                }
+            }
+        }
+        for i in 0..=lower.len() {
+            for &c in &self.alphabet {
+                let mut inserted = lower.clone();
+                inserted.insert(i, c);
+                consider(inserted);
+            }
+        }
+        if candidates.len() != 1 {
+            return None;
+        }
+        let candidate = candidates.into_iter().next()?;
+        Some(if word.chars().all(|c| !c.is_lowercase()) {
+            candidate.to_uppercase()
+        } else if word.starts_with(char::is_uppercase) {
+            capitalize(&candidate)
+        } else {
+            candidate
+        })
+    }
+}
+
+/// Check the spelling of each line, correcting unknown words if asked to and
+/// returning those left.
+pub fn check(
+    subtitles: &mut [Subtitle],
+    dictionary: &Dictionary,
+    correct: bool,
+) -> Vec<UnknownWord> {
+    let mut unknown = Vec::new();
+    for (i, subtitle) in subtitles.iter_mut().enumerate() {
+        let time = subtitle.time_span.start;
+        for line in &mut subtitle.lines {
+            let mut fixed = String::with_capacity(line.text.len());
+            let mut copied = 0;
+            for (start, end) in words(&line.text) {
+                let word = &line.text[start..end];
+                if word.chars().count() < 2 || dictionary.check(&word.replace('’', "'")) {
+                    continue;
+                }
+                match dictionary.correction(word).filter(|_| correct) {
+                    Some(correction) => {
+                        info!("Correcting `{}` to `{}` at {}", word, correction, time);
+                        fixed.push_str(&line.text[copied..start]);
+                        fixed.push_str(&correction);
+                        copied = end;
+                    }
+                    None => unknown.push(UnknownWord {
+                        number: i + 1,
+                        time,
+                        confidence: line.confidence,
+                        word: word.to_owned(),
+                        line: line.text.clone(),
+                    }),
+                }
+            }
+            if copied > 0 {
+                fixed.push_str(&line.text[copied..]);
+                line.text = fixed;
+            }
+        }
+    }
+    unknown
+}
+
+/// Write the unknown words, one per line after the number and start time of
+/// their subtitle and the confidence in their line, followed by the line,
+/// separated by tabs.
+pub fn write_report(filename: &Path, unknown: &[UnknownWord]) -> Result<()> {
+    let mut report = String::new();
+    for word in unknown {
+        report.push_str(&format!(
+            "{}\t{}\t{}\t{}\t{}\n",
+            word.number,
+            format_time(word.time),
+            word.confidence,
+            word.word,
+            word.line
+        ));
+    }
+    fs::write(filename, report).context(WriteReportSnafu { filename })
+}
+
+/// Find the byte ranges of the words of a line: runs of letters, with the
+/// apostrophes inside them, which aren't stuck to numbers like in `3rd`.
+pub fn words(line: &str) -> Vec<(usize, usize)> {
+    let chars: Vec<(usize, char)> = line.char_indices().collect();
+    let mut words = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        if !chars[i].1.is_alphabetic() {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        while i < chars.len()
+            && (chars[i].1.is_alphabetic()
+                || (matches!(chars[i].1, '\'' | '’')
+                    && matches!(chars.get(i + 1), Some((_, c)) if c.is_alphabetic())))
+        {
+            i += 1;
+        }
+        let before = start.checked_sub(1).map(|j| chars[j].1);
+        let after = chars.get(i).map(|&(_, c)| c);
+        if !matches!(before, Some(c) if c.is_numeric())
+            && !matches!(after, Some(c) if c.is_numeric())
+        {
+            words.push((
+                chars[start].0,
+                chars.get(i).map_or(line.len(), |&(offset, _)| offset),
+            ));
+        }
+    }
+    words
+}
+
+/// Find the dictionary for a Tesseract language in `DICPATH` or the usual
+/// places, preferring the one for the country named like the language, such
+/// as `de_DE`.
+fn find_dictionary(language: &str) -> Option<PathBuf> {
+    let code = language::iso_639_1(language)?;
+    let dirs = env::var_os("DICPATH")
+        .map(|paths| env::split_paths(&paths).collect::<Vec<_>>())
+        .unwrap_or_default()
+        .into_iter()
+        .chain(DICTIONARY_DIRS.iter().map(PathBuf::from));
+    let preferred = [format!("{}_{}", code, code.to_uppercase()), code.to_owned()];
+    for dir in dirs {
+        for name in &preferred {
+            let path = dir.join(format!("{}.dic", name));
+            if path.is_file() {
+                return Some(path);
+            }
+        }
+        let prefix = format!("{}_", code);
+        let mut others: Vec<PathBuf> = fs::read_dir(&dir)
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                let name = path
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .unwrap_or("");
+                name.starts_with(&prefix) && name.ends_with(".dic")
+            })
+            .collect();
+        others.sort();
+        if let Some(path) = others.into_iter().next() {
+            return Some(path);
+        }
+    }
+    None
+}
+
+/// Read a dictionary file in its encoding, which is UTF-8 unless the `.aff`
+/// file declares ISO 8859-1.
+fn read_text(filename: &Path, encoding: Option<&str>) -> Result<String> {
+    let bytes = fs::read(filename).context(ReadSnafu { filename })?;
+    Ok(match encoding {
+        Some("ISO8859-1") | Some("ISO-8859-1") => bytes.iter().map(|&b| b as char).collect(),
+        _ => String::from_utf8_lossy(&bytes).into_owned(),
+    })
+}
+
+/// How the flags of a dictionary are written.
+#[derive(Clone, Copy)]
+enum FlagType {
+    /// One character each.
+    Char,
+    /// Two characters each.
+    Long,
+    /// Numbers separated by commas.
+    Num,
+}
+
+/// A prefix or suffix rule from a `.aff` file.
+struct Affix {
+    prefix: bool,
+    /// Whether the rule combines with rules of the other kind.
+    cross: bool,
+    strip: String,
+    add: String,
+    condition: Vec<Condition>,
+}
+
+/// One character of an affix's condition.
+enum Condition {
+    Any,
+    Char(char),
+    Set(Vec<char>, bool),
+}
+
+/// What the `.aff` file defines.
+struct Affixes {
+    encoding: Option<String>,
+    flag_type: FlagType,
+    try_letters: Vec<char>,
+    rules: HashMap<String, Vec<Affix>>,
+}
+
+impl Affixes {
+    fn parse(aff: &str) -> Self {
+        let mut affixes = Self {
+            encoding: None,
+            flag_type: FlagType::Char,
+            try_letters: Vec::new(),
+            rules: HashMap::new(),
+        };
+        let mut cross = HashMap::new();
+        for line in aff.lines() {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields.as_slice() {
+                ["SET", encoding, ..] => affixes.encoding = Some(encoding.to_uppercase()),
+                ["FLAG", "long", ..] => affixes.flag_type = FlagType::Long,
+                ["FLAG", "num", ..] => affixes.flag_type = FlagType::Num,
+                ["TRY", letters, ..] => affixes.try_letters = letters.chars().collect(),
+                // The header of a rule's entries, like `SFX D Y 4`.
+                [kind @ ("PFX" | "SFX"), flag, combines, count]
+                    if count.parse::<usize>().is_ok() =>
+                {
+                    cross.insert((*kind, flag.to_string()), *combines == "Y");
+                }
+                [kind @ ("PFX" | "SFX"), flag, strip, add, rest @ ..] => {
+                    let add = add.split('/').next().unwrap_or("");
+                    affixes
+                        .rules
+                        .entry(flag.to_string())
+                        .or_default()
+                        .push(Affix {
+                            prefix: *kind == "PFX",
+                            cross: cross.get(&(*kind, flag.to_string())) == Some(&true),
+                            strip: if *strip == "0" { "" } else { strip }.to_owned(),
+                            add: if add == "0" { "" } else { add }.to_owned(),
+                            condition: parse_condition(rest.first().copied().unwrap_or(".")),
+                        });
+                }
+                _ => {}
+            }
+        }
+        affixes
+    }
+
+    fn parse_flags(&self, flags: &str) -> Vec<String> {
+        match self.flag_type {
+            FlagType::Char => flags.chars().map(String::from).collect(),
+            FlagType::Long => flags
+                .chars()
+                .collect::<Vec<_>>()
+                .chunks(2)
+                .map(|pair| pair.iter().collect())
+                .collect(),
+            FlagType::Num => flags.split(',').map(str::to_owned).collect(),
+        }
+    }
+
+    /// Add a stem and every word its affixes make of it.
+    fn expand(&self, stem: &str, flags: &[String], words: &mut HashSet<String>) {
+        let stem = stem.to_owned();
+        let rules: Vec<&Affix> = flags
+            .iter()
+            .filter_map(|flag| self.rules.get(flag))
+            .flatten()
+            .collect();
+        let mut suffixed = Vec::new();
+        for rule in rules.iter().filter(|rule| !rule.prefix) {
+            if let Some(word) = rule.apply(&stem) {
+                if rule.cross {
+                    suffixed.push(word.clone());
+                }
+                words.insert(word);
+            }
+        }
+        for rule in rules.iter().filter(|rule| rule.prefix) {
+            let crossed = suffixed.iter().filter(|_| rule.cross);
+            for base in iter::once(&stem).chain(crossed) {
+                if let Some(word) = rule.apply(base) {
+                    words.insert(word);
+                }
+            }
+        }
+        words.insert(stem);
+    }
+}
+
+impl Affix {
+    /// Apply the rule to a word, if the word meets its condition.
+    fn apply(&self, word: &str) -> Option<String> {
+        let chars: Vec<char> = word.chars().collect();
+        if chars.len() < self.condition.len() {
+            return None;
+        }
+        let checked = if self.prefix {
+            &chars[..self.condition.len()]
+        } else {
+            &chars[chars.len() - self.condition.len()..]
+        };
+        if !checked
+            .iter()
+            .zip(&self.condition)
+            .all(|(&c, condition)| condition.matches(c))
+        {
+            return None;
+        }
+        if self.prefix {
+            let rest = word.strip_prefix(self.strip.as_str())?;
+            Some(format!("{}{}", self.add, rest))
+        } else {
+            let rest = word.strip_suffix(self.strip.as_str())?;
+            Some(format!("{}{}", rest, self.add))
+        }
+    }
+}
+
+impl Condition {
+    fn matches(&self, c: char) -> bool {
+        match self {
+            Condition::Any => true,
+            Condition::Char(expected) => c == *expected,
+            Condition::Set(set, negated) => set.contains(&c) != *negated,
+        }
+    }
+}
+
+/// Parse a condition like `[^aeiou]y`, where `.` stands for any character.
+fn parse_condition(condition: &str) -> Vec<Condition> {
+    if condition == "." {
+        return Vec::new();
+    }
+    let mut parsed = Vec::new();
+    let mut chars = condition.chars();
+    while let Some(c) = chars.next() {
+        parsed.push(match c {
+            '.' => Condition::Any,
+            '[' => {
+                let set: String = chars.by_ref().take_while(|&c| c != ']').collect();
+                match set.strip_prefix('^') {
+                    Some(set) => Condition::Set(set.chars().collect(), true),
+                    None => Condition::Set(set.chars().collect(), false),
+                }
+            }
+            c => Condition::Char(c),
+        });
+    }
+    parsed
+}
+
+/// Uppercase the first letter of a word.
+pub fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
@@ -0,0 +1,83 @@
+//! Writing SubRip subtitles.
+
+use std::collections::HashMap;
+
+use crate::{
+    format::hex_color,
+    ocr::{Line, Subtitle},
+    writer::{self, Result, SrtSnafu, SubtitleWriter},
+};
+use subparse::{SrtFile, SubtitleFile};
+
+/// The tag most players understand as placing a subtitle at the top of the
+/// screen.
+const TOP_TAG: &str = "{\\an8}";
+
+/// Writes subtitles as an SRT file.
+///
+/// Lines in a different color from most of the text, such as a second
+/// speaker's, are wrapped in `<font color>` tags, and italic lines in `<i>`
+/// tags. Subtitles in the upper half of the screen start with an `{\an8}` tag.
+pub struct SrtWriter;
+
+impl SubtitleWriter for SrtWriter {
+    fn write(&self, subtitles: &[Subtitle]) -> Result<Vec<u8>> {
+        let main_color = main_color(subtitles);
+        let subtitles = subtitles
+            .iter()
+            .map(|subtitle| {
+                // A blank line would end the cue early.
+                let mut text = subtitle
+                    .lines
+                    .iter()
+                    .flat_map(|line| line.text.lines().map(move |text| (line, text.trim())))
+                    .filter(|(_, text)| !text.is_empty())
+                    .map(|(line, text)| format_line(line, text, main_color))
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                if subtitle.placement.is_top() {
+                    text.insert_str(0, TOP_TAG);
+                }
+                (subtitle.time_span, text)
+            })
+            .collect();
+        let subtitles = SubtitleFile::SubRipFile(SrtFile::create(subtitles).map_err(|e| {
+            SrtSnafu {
+                message: e.to_string(),
+            }
+            .build()
+        })?);
+        subtitles.to_data().map_err(|e| {
+            SrtSnafu {
+                message: e.to_string(),
+            }
+            .build()
+        })
+    }
+}
+
+/// Find the color most lines are in.
+fn main_color(subtitles: &[Subtitle]) -> [u8; 3] {
+    let mut counts = HashMap::new();
+    for line in subtitles.iter().flat_map(|subtitle| &subtitle.lines) {
+        *counts.entry(line.color).or_insert(0usize) += 1;
+    }
+    counts
+        .into_iter()
+        // Break ties by color, so that the same input is always tagged the same.
+        .max_by_key(|&(color, count)| (count, color))
+        .map_or([255, 255, 255], |(color, _)| color)
+}
+
+fn format_line(line: &Line, text: &str, main_color: [u8; 3]) -> String {
+    let text = if line.italic {
+        format!("<i>{}</i>", text)
+    } else {
+        text.to_owned()
+    };
+    if writer::similar_colors(line.color, main_color) {
+        text
+    } else {
+        format!("<font color=\"#{}\">{}</font>", hex_color(line.color), text)
+    }
+}
@@ -0,0 +1,70 @@
+//! Writing Spruce STL subtitles, the text format of DVD Studio Pro.
+//!
+//! Settings are given with `$Name = value` lines, and apply to every
+//! subtitle after them, so they are only repeated when a subtitle needs
+//! something different from the one before.
+
+use std::fmt::Write;
+
+use crate::{
+    bdn,
+    ocr::Subtitle,
+    writer::{self, Result, SubtitleWriter},
+};
+
+/// Settings at the top of every file. The contrasts are 0-15, with the
+/// background left transparent.
+const HEADER: &str = "\
+$FontName = Arial
+$FontSize = 42
+$HorzAlign = Center
+$XOffset = 0
+$YOffset = 0
+$TextContrast = 15
+$Outline1Contrast = 15
+$Outline2Contrast = 13
+$BackgroundContrast = 0
+$FadeIn = 0
+$FadeOut = 0
+$TapeOffset = FALSE
+";
+
+/// Writes subtitles as Spruce STL.
+pub struct StlWriter {
+    /// Frame rate of the timecodes.
+    pub fps: f64,
+}
+
+impl SubtitleWriter for StlWriter {
+    fn write(&self, subtitles: &[Subtitle]) -> Result<Vec<u8>> {
+        let mut output = String::from(HEADER);
+        let mut top = None;
+        let mut force = None;
+        for subtitle in subtitles {
+            let text = writer::join_lines(&subtitle.text(), "|");
+            if text.is_empty() {
+                continue;
+            }
+            let is_top = subtitle.placement.is_top();
+            if top != Some(is_top) {
+                let align = if is_top { "Top" } else { "Bottom" };
+                writeln!(output, "$VertAlign = {}", align).unwrap();
+                top = Some(is_top);
+            }
+            if force != Some(subtitle.force) {
+                let value = if subtitle.force { "TRUE" } else { "FALSE" };
+                writeln!(output, "$ForceDisplay = {}", value).unwrap();
+                force = Some(subtitle.force);
+            }
+            writeln!(
+                output,
+                "{} , {} , {}",
+                bdn::format_timecode(subtitle.time_span.start, self.fps),
+                bdn::format_timecode(subtitle.time_span.end, self.fps),
+                text
+            )
+            .unwrap();
+        }
+        Ok(output.into_bytes())
+    }
+}
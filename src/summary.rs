@@ -0,0 +1,134 @@
+//! A machine-readable summary of a run, for `--summary-json`, so that
+//! pipelines can decide whether the result needs a human to look at it.
+
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+use crate::ocr::Subtitle;
+use serde::Serialize;
+use snafu::{ResultExt, Snafu};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Could not write summary {}: {}", filename.display(), source))]
+    Write {
+        filename: PathBuf,
+        source: io::Error,
+    },
+
+    #[snafu(display("Could not write JSON: {}", source))]
+    Json { source: serde_json::Error },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[derive(Serialize)]
+pub struct Summary {
+    /// The command line, without the program name.
+    arguments: Vec<String>,
+    /// The Tesseract languages OCR used.
+    pub languages: Vec<String>,
+    /// Subtitles written.
+    subtitles: usize,
+    /// Subtitle images OCR failed on, which were left out.
+    pub failed: usize,
+    /// Mean confidence of the subtitles written, from 0 to 100.
+    mean_confidence: Option<f64>,
+    /// The threshold given with `--min-confidence`.
+    min_confidence: Option<i32>,
+    /// Subtitles written with a confidence below `min_confidence`.
+    low_confidence: usize,
+    /// Words not in the `--spellcheck` dictionary.
+    pub unknown_words: usize,
+    /// Seconds spent in each stage.
+    seconds: Stages,
+    #[serde(skip)]
+    confidence_total: i64,
+    #[serde(skip)]
+    stage_start: Instant,
+}
+
+#[derive(Serialize, Default)]
+struct Stages {
+    /// Reading and parsing the inputs.
+    read: f64,
+    /// Preparing images for OCR.
+    preprocess: f64,
+    ocr: f64,
+    /// Fixing, checking and retiming the text.
+    postprocess: f64,
+    write: f64,
+}
+
+/// A stage of a run which is timed.
+#[derive(Clone, Copy)]
+pub enum Stage {
+    Read,
+    Preprocess,
+    Ocr,
+    Postprocess,
+    Write,
+}
+
+impl Summary {
+    /// Start summarizing a run, and timing its first stage.
+    pub fn new(min_confidence: Option<i32>) -> Self {
+        Self {
+            arguments: env::args().skip(1).collect(),
+            languages: Vec::new(),
+            subtitles: 0,
+            failed: 0,
+            mean_confidence: None,
+            min_confidence,
+            low_confidence: 0,
+            unknown_words: 0,
+            seconds: Stages::default(),
+            confidence_total: 0,
+            stage_start: Instant::now(),
+        }
+    }
+
+    /// Record the time since the previous stage ended as spent in `stage`.
+    pub fn end_stage(&mut self, stage: Stage) {
+        let seconds = self.stage_start.elapsed().as_secs_f64();
+        let total = match stage {
+            Stage::Read => &mut self.seconds.read,
+            Stage::Preprocess => &mut self.seconds.preprocess,
+            Stage::Ocr => &mut self.seconds.ocr,
+            Stage::Postprocess => &mut self.seconds.postprocess,
+            Stage::Write => &mut self.seconds.write,
+        };
+        *total += seconds;
+        self.stage_start = Instant::now();
+    }
+
+    /// Don't count the time since the previous stage ended towards the next
+    /// one, such as time spent waiting for answers.
+    pub fn skip_time(&mut self) {
+        self.stage_start = Instant::now();
+    }
+
+    /// Count the subtitles about to be written.
+    pub fn add_subtitles(&mut self, subtitles: &[Subtitle]) {
+        for subtitle in subtitles {
+            let confidence = subtitle.confidence();
+            self.subtitles += 1;
+            self.confidence_total += confidence as i64;
+            if matches!(self.min_confidence, Some(min) if confidence < min) {
+                self.low_confidence += 1;
+            }
+        }
+        self.mean_confidence = Some(self.confidence_total as f64 / self.subtitles as f64)
+            .filter(|_| self.subtitles > 0);
+    }
+
+    /// Write the summary as JSON.
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let mut json = serde_json::to_vec_pretty(self).context(JsonSnafu {})?;
+        json.push(b'\n');
+        fs::write(path, json).context(WriteSnafu { filename: path })
+    }
+}
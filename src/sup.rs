@@ -0,0 +1,185 @@
+//! Writing Blu-ray PGS subtitles, as found in `.sup` files.
+//!
+//! Each subtitle becomes a display set showing it as a single object in a
+//! single window, followed by one clearing the screen when it ends.
+
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use crate::{ifo::rgb_to_ycrcb, preprocessor::SubtitleBitmap};
+use subparse::timetypes::TimePoint;
+
+const PALETTE_DEFINITION: u8 = 0x14;
+const OBJECT_DEFINITION: u8 = 0x15;
+const PRESENTATION_COMPOSITION: u8 = 0x16;
+const WINDOW_DEFINITION: u8 = 0x17;
+const END_OF_DISPLAY_SET: u8 = 0x80;
+
+/// Largest amount of data a segment can hold.
+const MAX_SEGMENT_SIZE: usize = 0xffff;
+
+/// Write subtitle bitmaps to a `.sup` file.
+pub fn write_sup(path: impl AsRef<Path>, bitmaps: &[SubtitleBitmap]) -> io::Result<()> {
+    let mut output = BufWriter::new(File::create(path)?);
+    let mut composition_number = 0u16;
+    for bitmap in bitmaps {
+        if bitmap.width == 0 || bitmap.height == 0 {
+            continue;
+        }
+        let start = to_pts(bitmap.time_span.start);
+        let end = to_pts(bitmap.time_span.end);
+
+        let mut composition = composition_segment(bitmap, composition_number, true);
+        write_segment(&mut output, start, PRESENTATION_COMPOSITION, &composition)?;
+        write_segment(
+            &mut output,
+            start,
+            WINDOW_DEFINITION,
+            &window_segment(bitmap),
+        )?;
+        write_segment(
+            &mut output,
+            start,
+            PALETTE_DEFINITION,
+            &palette_segment(bitmap),
+        )?;
+        for object in object_segments(bitmap) {
+            write_segment(&mut output, start, OBJECT_DEFINITION, &object)?;
+        }
+        write_segment(&mut output, start, END_OF_DISPLAY_SET, &[])?;
+        composition_number = composition_number.wrapping_add(1);
+
+        composition = composition_segment(bitmap, composition_number, false);
+        write_segment(&mut output, end, PRESENTATION_COMPOSITION, &composition)?;
+        write_segment(&mut output, end, WINDOW_DEFINITION, &window_segment(bitmap))?;
+        write_segment(&mut output, end, END_OF_DISPLAY_SET, &[])?;
+        composition_number = composition_number.wrapping_add(1);
+    }
+    output.flush()
+}
+
+/// Convert a time to a 90kHz timestamp.
+fn to_pts(time: TimePoint) -> u32 {
+    (time.msecs().max(0) * 90) as u32
+}
+
+fn write_segment(output: &mut impl Write, pts: u32, kind: u8, data: &[u8]) -> io::Result<()> {
+    output.write_all(b"PG")?;
+    output.write_all(&pts.to_be_bytes())?;
+    // The decoding timestamp is unused by players.
+    output.write_all(&0u32.to_be_bytes())?;
+    output.write_all(&[kind])?;
+    output.write_all(&(data.len() as u16).to_be_bytes())?;
+    output.write_all(data)
+}
+
+/// Build a presentation composition segment that either starts a new epoch
+/// showing the bitmap, or clears the screen.
+fn composition_segment(bitmap: &SubtitleBitmap, number: u16, show: bool) -> Vec<u8> {
+    let (width, height) = bitmap.screen_size;
+    let mut data = Vec::with_capacity(19);
+    data.extend((width as u16).to_be_bytes());
+    data.extend((height as u16).to_be_bytes());
+    // The frame rate field is always 0x10.
+    data.push(0x10);
+    data.extend(number.to_be_bytes());
+    // Composition state: epoch start or normal.
+    data.push(if show { 0x80 } else { 0x00 });
+    // No palette update, palette 0.
+    data.extend([0x00, 0x00]);
+    if show {
+        data.push(1);
+        // Object 0 in window 0, uncropped.
+        data.extend([0x00, 0x00, 0x00, 0x00]);
+        data.extend((bitmap.x as u16).to_be_bytes());
+        data.extend((bitmap.y as u16).to_be_bytes());
+    } else {
+        data.push(0);
+    }
+    data
+}
+
+/// Build a window definition segment with a window covering the bitmap.
+fn window_segment(bitmap: &SubtitleBitmap) -> Vec<u8> {
+    let mut data = vec![1, 0];
+    data.extend((bitmap.x as u16).to_be_bytes());
+    data.extend((bitmap.y as u16).to_be_bytes());
+    data.extend((bitmap.width as u16).to_be_bytes());
+    data.extend((bitmap.height as u16).to_be_bytes());
+    data
+}
+
+/// Build a palette definition segment from the bitmap's palette.
+fn palette_segment(bitmap: &SubtitleBitmap) -> Vec<u8> {
+    let mut data = vec![0, 0];
+    for (i, color) in bitmap.palette.iter().enumerate().take(256) {
+        let [r, g, b, a] = color.0;
+        let [y, cr, cb] = rgb_to_ycrcb([r, g, b]);
+        data.extend([i as u8, y, cr, cb, a]);
+    }
+    data
+}
+
+/// Build the object definition segments for the bitmap, splitting its image
+/// data across several segments if it doesn't fit in one.
+fn object_segments(bitmap: &SubtitleBitmap) -> Vec<Vec<u8>> {
+    let rle = encode_rle(bitmap);
+    // The first segment also holds the data length and image size.
+    let first_len = (MAX_SEGMENT_SIZE - 11).min(rle.len());
+    let mut chunks = vec![&rle[..first_len]];
+    chunks.extend(rle[first_len..].chunks(MAX_SEGMENT_SIZE - 4));
+
+    let last = chunks.len() - 1;
+    chunks
+        .into_iter()
+        .enumerate()
+        .map(|(i, chunk)| {
+            let mut data = vec![0, 0, 0];
+            let mut sequence = 0;
+            if i == 0 {
+                sequence |= 0x80;
+            }
+            if i == last {
+                sequence |= 0x40;
+            }
+            data.push(sequence);
+            if i == 0 {
+                data.extend(&((rle.len() + 4) as u32).to_be_bytes()[1..]);
+                data.extend((bitmap.width as u16).to_be_bytes());
+                data.extend((bitmap.height as u16).to_be_bytes());
+            }
+            data.extend(chunk);
+            data
+        })
+        .collect()
+}
+
+/// Run-length encode the bitmap's pixels the way PGS objects store them.
+fn encode_rle(bitmap: &SubtitleBitmap) -> Vec<u8> {
+    let mut rle = Vec::new();
+    for row in bitmap.pixels.chunks(bitmap.width) {
+        let mut x = 0;
+        while x < row.len() {
+            let color = row[x];
+            let run = row[x..]
+                .iter()
+                .take(0x3fff)
+                .take_while(|&&c| c == color)
+                .count();
+            match (color, run) {
+                (0, 1..=0x3f) => rle.extend([0x00, run as u8]),
+                (0, _) => rle.extend([0x00, 0x40 | (run >> 8) as u8, run as u8]),
+                (_, 1..=2) => rle.resize(rle.len() + run, color),
+                (_, 3..=0x3f) => rle.extend([0x00, 0x80 | run as u8, color]),
+                (_, _) => rle.extend([0x00, 0xc0 | (run >> 8) as u8, run as u8, color]),
+            }
+            x += run;
+        }
+        // End of line.
+        rle.extend([0x00, 0x00]);
+    }
+    rle
+}
@@ -0,0 +1,81 @@
+//! Adjusting the timings of recognized subtitles.
+
+use crate::{ocr::Subtitle, opt::Overlaps};
+use subparse::timetypes::{TimeDelta, TimePoint};
+
+/// Multiply every time by `scale` and then add `offset`, keeping times from
+/// going below zero.
+pub fn retime(subtitles: &mut [Subtitle], scale: f64, offset: TimeDelta) {
+    let retime = |time: TimePoint| {
+        let msecs = (time.msecs() as f64 * scale).round() as i64 + offset.msecs();
+        TimePoint::from_msecs(msecs.max(0))
+    };
+    for subtitle in subtitles {
+        subtitle.time_span.start = retime(subtitle.time_span.start);
+        subtitle.time_span.end = retime(subtitle.time_span.end);
+    }
+}
+
+/// Stop subtitles from running past the start of the next subtitle on the
+/// same half of the screen. Subtitles shown together on different halves,
+/// like a sign above dialogue, are meant to overlap and are left alone, as
+/// are those starting at the same time.
+pub fn fix_overlaps(subtitles: &mut [Subtitle], strategy: Overlaps) {
+    for i in 0..subtitles.len() {
+        let next = match next_on_same_half(subtitles, i) {
+            Some(next) => next,
+            None => continue,
+        };
+        let end = subtitles[i].time_span.end;
+        let start = subtitles[i].time_span.start;
+        let next_start = subtitles[next].time_span.start;
+        if end <= next_start || next_start <= start {
+            continue;
+        }
+        let boundary = match strategy {
+            Overlaps::Clamp => next_start,
+            Overlaps::Rebalance => TimePoint::from_msecs((next_start.msecs() + end.msecs()) / 2)
+                .min(subtitles[next].time_span.end),
+        };
+        subtitles[i].time_span.end = boundary;
+        subtitles[next].time_span.start = boundary;
+    }
+}
+
+/// Shorten subtitles to at most `max_duration`, lengthen them to at least
+/// `min_duration` where that doesn't run into the next subtitle on the same
+/// half of the screen, and then end them `min_gap` before it where that leaves
+/// them any time at all.
+pub fn enforce_durations(
+    subtitles: &mut [Subtitle],
+    min_duration: Option<TimeDelta>,
+    max_duration: Option<TimeDelta>,
+    min_gap: Option<TimeDelta>,
+) {
+    for i in 0..subtitles.len() {
+        let next_start =
+            next_on_same_half(subtitles, i).map(|next| subtitles[next].time_span.start);
+        let span = &mut subtitles[i].time_span;
+        if let Some(max_duration) = max_duration {
+            span.end = span.end.min(span.start + max_duration);
+        }
+        if let Some(min_duration) = min_duration {
+            let mut end = span.start + min_duration;
+            if let Some(next_start) = next_start {
+                end = end.min(next_start - min_gap.unwrap_or_else(|| TimeDelta::from_msecs(0)));
+            }
+            span.end = span.end.max(end);
+        }
+        if let (Some(next_start), Some(gap)) = (next_start, min_gap) {
+            if next_start - span.end < gap && next_start - gap > span.start {
+                span.end = next_start - gap;
+            }
+        }
+    }
+}
+
+/// Find the next subtitle on the same half of the screen as a subtitle.
+fn next_on_same_half(subtitles: &[Subtitle], i: usize) -> Option<usize> {
+    let top = subtitles[i].placement.is_top();
+    (i + 1..subtitles.len()).find(|&j| subtitles[j].placement.is_top() == top)
+}
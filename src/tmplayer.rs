@@ -0,0 +1,48 @@
+//! Writing TMPlayer subtitles, whose lines look like `hh:mm:ss:text`.
+//!
+//! The format has no end times. Each subtitle stays up until the next one, so
+//! an empty line is written where there is a gap between two subtitles.
+
+use std::fmt::Write;
+
+use crate::{
+    ocr::Subtitle,
+    writer::{self, Result, SubtitleWriter},
+};
+use subparse::timetypes::TimePoint;
+
+/// Writes subtitles as TMPlayer.
+pub struct TmPlayerWriter;
+
+impl SubtitleWriter for TmPlayerWriter {
+    fn write(&self, subtitles: &[Subtitle]) -> Result<Vec<u8>> {
+        let mut output = String::new();
+        let cues = subtitles
+            .iter()
+            .map(|subtitle| (subtitle, writer::join_lines(&subtitle.text(), "|")))
+            .filter(|(_, text)| !text.is_empty())
+            .collect::<Vec<_>>();
+        for (i, (subtitle, text)) in cues.iter().enumerate() {
+            let start = format_time(subtitle.time_span.start);
+            let end = format_time(subtitle.time_span.end);
+            writeln!(output, "{}:{}", start, text).unwrap();
+            // Only whole seconds can be written, so a clearing line that
+            // would land on the next subtitle's second is left out.
+            let overlaps_next = cues
+                .get(i + 1)
+                .map(|(next, _)| format_time(next.time_span.start))
+                .filter(|next_start| *next_start <= end)
+                .is_some();
+            if !overlaps_next {
+                writeln!(output, "{}:", end).unwrap();
+            }
+        }
+        Ok(output.into_bytes())
+    }
+}
+
+/// Format a time as `hh:mm:ss`.
+fn format_time(time: TimePoint) -> String {
+    let secs = time.msecs().max(0) / 1000;
+    format!("{:02}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
@@ -0,0 +1,91 @@
+//! Writing WebVTT subtitles.
+
+use std::fmt::Write;
+
+use crate::{
+    format::format_time,
+    ocr::Subtitle,
+    preprocessor::{Column, Placement},
+    writer::{Result, SubtitleWriter},
+};
+
+/// Writes subtitles as a WebVTT file.
+pub struct VttWriter;
+
+impl SubtitleWriter for VttWriter {
+    fn write(&self, subtitles: &[Subtitle]) -> Result<Vec<u8>> {
+        let mut vtt = String::from("WEBVTT\n");
+        // An empty line would end the cue early, and an empty cue is useless.
+        let cues = subtitles
+            .iter()
+            .map(|subtitle| (subtitle, format_text(subtitle)))
+            .filter(|(_, text)| !text.is_empty());
+        for (i, (subtitle, text)) in cues.enumerate() {
+            writeln!(
+                vtt,
+                "\n{}\n{} --> {}{}\n{}",
+                i + 1,
+                format_time(subtitle.time_span.start),
+                format_time(subtitle.time_span.end),
+                cue_settings(&subtitle.placement),
+                text,
+            )
+            .unwrap();
+        }
+        Ok(vtt.into_bytes())
+    }
+}
+
+/// Build the settings placing a cue where the subtitle was on the screen, or
+/// nothing for the usual bottom-centered subtitles.
+fn cue_settings(placement: &Placement) -> String {
+    let column = placement.column();
+    let top = placement.is_top();
+    if !top && column == Column::Center {
+        return String::new();
+    }
+    let percent = |value: u32, total: u32| value as f64 * 100.0 / total.max(1) as f64;
+    let line = if top {
+        format!("{:.1}%", percent(placement.y, placement.screen_height))
+    } else {
+        format!(
+            "{:.1}%,end",
+            percent(placement.y + placement.height, placement.screen_height)
+        )
+    };
+    let (x, position_alignment, alignment) = match column {
+        Column::Left => (placement.x, "line-left", "start"),
+        Column::Center => (placement.x + placement.width / 2, "center", "center"),
+        Column::Right => (placement.x + placement.width, "line-right", "end"),
+    };
+    format!(
+        " line:{} position:{:.1}%,{} align:{}",
+        line,
+        percent(x, placement.screen_width),
+        position_alignment,
+        alignment
+    )
+}
+
+/// Escape the characters WebVTT treats as markup, wrap italic lines in `<i>`
+/// tags and drop blank lines.
+fn format_text(subtitle: &Subtitle) -> String {
+    subtitle
+        .lines
+        .iter()
+        .flat_map(|line| line.text.lines().map(move |text| (line, text.trim())))
+        .filter(|(_, text)| !text.is_empty())
+        .map(|(line, text)| {
+            let text = text
+                .replace('&', "&amp;")
+                .replace('<', "&lt;")
+                .replace('>', "&gt;");
+            if line.italic {
+                format!("<i>{}</i>", text)
+            } else {
+                text
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
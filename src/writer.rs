@@ -0,0 +1,204 @@
+//! Serialization of recognized subtitles into the supported output formats.
+
+use std::path::Path;
+
+use crate::{
+    alto::AltoWriter,
+    ass::AssWriter,
+    hocr::HocrWriter,
+    json::JsonWriter,
+    microdvd::MicroDvdWriter,
+    mpl2::Mpl2Writer,
+    ocr::Subtitle,
+    opt::{OcrOpt, OutputFormat, TextEncoding},
+    srt::SrtWriter,
+    stl::StlWriter,
+    tmplayer::TmPlayerWriter,
+    vtt::VttWriter,
+};
+use log::warn;
+use snafu::Snafu;
+
+#[derive(Debug, Snafu)]
+#[snafu(visibility(pub(crate)))]
+pub enum Error {
+    #[snafu(display("Could not generate SRT file: {}", message))]
+    Srt { message: String },
+
+    #[snafu(display("Could not generate JSON: {}", source))]
+    Json { source: serde_json::Error },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Colors closer than this in every channel are treated as the same color.
+const COLOR_TOLERANCE: u8 = 0x40;
+
+/// A subtitle output format.
+pub trait SubtitleWriter {
+    /// Serialize the subtitles into the contents of a file.
+    fn write(&self, subtitles: &[Subtitle]) -> Result<Vec<u8>>;
+}
+
+/// Every output format, along with the file extensions it is chosen for.
+const FORMATS: &[(OutputFormat, &[&str])] = &[
+    (OutputFormat::Srt, &["srt"]),
+    (OutputFormat::Ass, &["ass", "ssa"]),
+    (OutputFormat::Vtt, &["vtt"]),
+    (OutputFormat::Microdvd, &["sub"]),
+    // Both usually use `.txt`, so they have to be asked for with `--format`.
+    (OutputFormat::Mpl2, &[]),
+    (OutputFormat::Tmplayer, &[]),
+    (OutputFormat::Stl, &["stl"]),
+    (OutputFormat::Json, &["json"]),
+    (OutputFormat::Hocr, &["hocr", "html"]),
+    (OutputFormat::Alto, &["alto", "xml"]),
+];
+
+/// The output format given with `--format`, or else the one matching the
+/// output file's extension, or else SRT.
+pub fn output_format(opt: &OcrOpt) -> OutputFormat {
+    opt.format
+        .or_else(|| opt.output.as_deref().and_then(format_for_path))
+        .unwrap_or(OutputFormat::Srt)
+}
+
+/// The format to write `--forced-output` in.
+pub fn forced_output_format(opt: &OcrOpt) -> OutputFormat {
+    opt.forced_output
+        .as_deref()
+        .and_then(format_for_path)
+        .unwrap_or_else(|| output_format(opt))
+}
+
+/// The extension to give a file written in an output format.
+pub fn extension(format: OutputFormat) -> &'static str {
+    FORMATS
+        .iter()
+        .find(|&&(f, _)| f == format)
+        .and_then(|(_, extensions)| extensions.first().copied())
+        .unwrap_or("txt")
+}
+
+/// Find the output format a file should be written in from its extension.
+fn format_for_path(path: &Path) -> Option<OutputFormat> {
+    let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+    FORMATS
+        .iter()
+        .find(|(_, extensions)| extensions.contains(&extension.as_str()))
+        .map(|&(format, _)| format)
+}
+
+/// Join the non-blank lines of a subtitle's text with a separator, for formats
+/// which need each subtitle on one line of the file.
+pub fn join_lines(text: &str, separator: &str) -> String {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join(separator)
+}
+
+/// Whether two text colors are close enough to be the same, as far as telling
+/// speakers apart goes.
+pub fn similar_colors(a: [u8; 3], b: [u8; 3]) -> bool {
+    a.iter()
+        .zip(b)
+        .all(|(&a, b)| a.abs_diff(b) < COLOR_TOLERANCE)
+}
+
+/// Create the writer for an output format, with the line endings chosen with
+/// `--crlf`, writing formats other than JSON, hOCR and ALTO in the
+/// `--encoding`.
+pub fn new(format: OutputFormat, opt: &OcrOpt) -> Box<dyn SubtitleWriter> {
+    let writer: Box<dyn SubtitleWriter> = match format {
+        OutputFormat::Srt => Box::new(SrtWriter),
+        OutputFormat::Ass => Box::new(AssWriter),
+        OutputFormat::Vtt => Box::new(VttWriter),
+        OutputFormat::Microdvd => Box::new(MicroDvdWriter { fps: opt.fps }),
+        OutputFormat::Mpl2 => Box::new(Mpl2Writer),
+        OutputFormat::Tmplayer => Box::new(TmPlayerWriter),
+        OutputFormat::Stl => Box::new(StlWriter { fps: opt.fps }),
+        OutputFormat::Json => Box::new(JsonWriter),
+        OutputFormat::Hocr => Box::new(HocrWriter),
+        OutputFormat::Alto => Box::new(AltoWriter),
+    };
+    let encoding = match format {
+        OutputFormat::Json | OutputFormat::Hocr | OutputFormat::Alto
+            if opt.encoding != TextEncoding::Utf8 =>
+        {
+            warn!("JSON, hOCR and ALTO are always written as UTF-8, ignoring --encoding");
+            TextEncoding::Utf8
+        }
+        _ => opt.encoding,
+    };
+    if encoding == TextEncoding::Utf8 && !opt.crlf {
+        return writer;
+    }
+    Box::new(TextWriter {
+        writer,
+        encoding,
+        crlf: opt.crlf,
+    })
+}
+
+/// Writes the output of another writer with other line endings or in an
+/// encoding other than UTF-8.
+struct TextWriter {
+    writer: Box<dyn SubtitleWriter>,
+    encoding: TextEncoding,
+    crlf: bool,
+}
+
+impl SubtitleWriter for TextWriter {
+    fn write(&self, subtitles: &[Subtitle]) -> Result<Vec<u8>> {
+        let output = self.writer.write(subtitles)?;
+        let mut text = String::from_utf8_lossy(&output).into_owned();
+        if self.crlf {
+            text = text.replace("\r\n", "\n").replace('\n', "\r\n");
+        }
+        Ok(encode(&text, self.encoding))
+    }
+}
+
+/// Encode text, writing the characters the encoding lacks as `?`.
+fn encode(text: &str, encoding: TextEncoding) -> Vec<u8> {
+    match encoding {
+        TextEncoding::Utf8 => text.as_bytes().to_vec(),
+        TextEncoding::Utf8Bom => format!("\u{feff}{}", text).into_bytes(),
+        TextEncoding::Utf16Le => format!("\u{feff}{}", text)
+            .encode_utf16()
+            .flat_map(u16::to_le_bytes)
+            .collect(),
+        TextEncoding::Utf16Be => format!("\u{feff}{}", text)
+            .encode_utf16()
+            .flat_map(u16::to_be_bytes)
+            .collect(),
+        TextEncoding::Legacy(encoding) => {
+            let (bytes, _, lossy) = encoding.encode(text);
+            if !lossy {
+                return bytes.into_owned();
+            }
+            // The encoder writes HTML character references for characters it
+            // lacks, which players would show as they are.
+            let mut missing = 0;
+            let text: String = text
+                .chars()
+                .map(|c| {
+                    if encoding.encode(c.encode_utf8(&mut [0; 4])).2 {
+                        missing += 1;
+                        '?'
+                    } else {
+                        c
+                    }
+                })
+                .collect();
+            warn!(
+                "Wrote {} characters which {} lacks as `?`",
+                missing,
+                encoding.name()
+            );
+            encoding.encode(&text).0.into_owned()
+        }
+    }
+}